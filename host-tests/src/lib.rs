@@ -0,0 +1,43 @@
+// The firmware's protocol code, built for the host: see `tests/`.
+// The modules are the firmware's own; none of them touches the hardware.
+
+extern crate alloc;
+
+#[path = "../../pokakus/src/config.rs"]
+pub mod config;
+#[path = "../../pokakus/src/retry.rs"]
+pub mod retry;
+#[path = "../../pokakus/src/ack.rs"]
+pub mod ack;
+#[path = "../../pokakus/src/bot_api.rs"]
+pub mod bot_api;
+#[path = "../../pokakus/src/recipient.rs"]
+pub mod recipient;
+#[path = "../../pokakus/src/text.rs"]
+pub mod text;
+#[path = "../../pokakus/src/gesture.rs"]
+pub mod gesture;
+#[path = "../../pokakus/src/clock.rs"]
+pub mod clock;
+#[path = "../../pokakus/src/event.rs"]
+pub mod event;
+#[path = "../../pokakus/src/template.rs"]
+pub mod template;
+#[path = "../../pokakus/src/mqtt_packet.rs"]
+pub mod mqtt_packet;
+#[path = "../../pokakus/src/dns_packet.rs"]
+pub mod dns_packet;
+
+
+// Logs: nowhere. The tests check results, not logs.
+#[defmt::global_logger]
+struct NoLogger;
+
+unsafe impl defmt::Logger for NoLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("");
@@ -0,0 +1,35 @@
+// Acknowledgements: which message a button press is about. See `ack`.
+// The state is global: one test, in order.
+
+use host_tests::ack::{self, AckKind};
+use host_tests::text::ParseMode;
+
+
+const CHANNEL: i64 = -1001234567890;  // sent to "@pokakus_channel": the sendMessage result has its numeric id
+const GROUP: i64 = -4012345678;
+
+
+#[test]
+fn acknowledge() {
+    ack::record_sent(1, CHANNEL, 7, "*Pokakus\\!*".into(), Some(ParseMode::MarkdownV2));
+    ack::record_sent(1, GROUP, 7, "Pokakus!".into(), None);  // the same message id, another chat
+
+    // The channel's message: with its markup, names escaped
+    let edited = ack::acknowledge(CHANNEL, 7, AckKind::OnIt, "Anna_B", Some("Pokakus!")).unwrap();
+    assert_eq!(edited.text, "*Pokakus\\!*\n\n👀 On it: Anna\\_B");
+    assert_eq!(edited.parse_mode, Some(ParseMode::MarkdownV2));
+    assert!(edited.keyboard.is_some());  // "Done" is left
+    assert!(ack::is_acknowledged(1));
+
+    // The group's: its own acknowledgements, not the channel's
+    let edited = ack::acknowledge(GROUP, 7, AckKind::Done, "Bo", Some("Pokakus!")).unwrap();
+    assert_eq!(edited.text, "Pokakus!\n\n✅ Done: Bo");
+    assert!(edited.keyboard.is_none());
+
+    // A chat we haven't sent that message to: the text from the callback, plain
+    let edited = ack::acknowledge(-1009999999999, 7, AckKind::Done, "Cy", Some("Pokakus!")).unwrap();
+    assert_eq!(edited.text, "Pokakus!\n\n✅ Done: Cy");
+    assert_eq!(edited.parse_mode, None);
+    assert!(ack::acknowledge(-1009999999999, 7, AckKind::Done, "Cy", None).is_none());
+    assert!(!ack::is_acknowledged(2));
+}
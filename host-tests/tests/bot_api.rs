@@ -0,0 +1,105 @@
+// Bot API responses, as Telegram sends them: see `bot_api::parse_api_response()`.
+
+use serde::Deserialize;
+
+use host_tests::bot_api::{parse_api_response, SentMessage, TelegramSendMessageError};
+use host_tests::retry::Retryable;
+
+
+// $ http POST 'https://api.telegram.org/bot${TELEGRAM_BOT_TOKEN}/sendMessage' chat_id:=${TELEGRAM_SEND_TO} text="hey"
+const SENT: &str = r#"{"ok":true,"result":{"message_id":39,"from":{"id":6415095545,"is_bot":true,"first_name":"Pokakus","username":"pokakus_bot"},"chat":{"id":691814383,"first_name":"Anna","username":"anna","type":"private"},"date":1767364636,"text":"hi there"}}"#;
+
+
+#[test]
+fn ok() {
+    let sent: SentMessage = parse(200, SENT).unwrap();
+    assert_eq!(sent.message_id, 39);
+    assert_eq!(sent.chat.map(|c| c.id), Some(691814383));
+}
+
+#[test]
+fn ok_without_result() {
+    assert!(matches!(parse::<SentMessage>(200, r#"{"ok":true}"#), Err(TelegramSendMessageError::ResponseError)));
+}
+
+#[test]
+fn bad_request() {
+    let e = fail(400, r#"{"ok":false,"error_code":400,"description":"Bad Request: chat not found"}"#);
+    assert!(matches!(e, TelegramSendMessageError::ApiError { error_code: 400, retry_after: None, migrate_to_chat_id: None }));
+    assert!(e.is_permanent());
+}
+
+#[test]
+fn unauthorized() {
+    let e = fail(401, r#"{"ok":false,"error_code":401,"description":"Unauthorized"}"#);
+    assert_eq!(e.error_code(), Some(401));
+    assert!(e.is_permanent());
+}
+
+#[test]
+fn blocked() {
+    let e = fail(403, r#"{"ok":false,"error_code":403,"description":"Forbidden: bot was blocked by the user"}"#);
+    assert_eq!(e.error_code(), Some(403));
+    assert!(e.is_permanent());
+}
+
+#[test]
+fn too_many_requests() {
+    let e = fail(429, r#"{"ok":false,"error_code":429,"description":"Too Many Requests: retry after 35","parameters":{"retry_after":35}}"#);
+    assert!(matches!(e, TelegramSendMessageError::ApiError { error_code: 429, retry_after: Some(35), .. }));
+    assert!(!e.is_permanent());
+    assert_eq!(e.retry_after(), Some(embassy_time::Duration::from_secs(35)));
+}
+
+#[test]
+fn migrated() {
+    let e = fail(400, r#"{"ok":false,"error_code":400,"description":"Bad Request: group chat was upgraded to a supergroup chat","parameters":{"migrate_to_chat_id":-1001234567890}}"#);
+    assert_eq!(e.error_code(), Some(400));
+    assert_eq!(e.migrate_to_chat_id(), Some(-1001234567890));
+}
+
+#[test]
+fn not_modified() {
+    let e = fail(400, r#"{"ok":false,"error_code":400,"description":"Bad Request: message is not modified: specified new message content and reply markup are exactly the same as a current content and reply markup of the message"}"#);
+    assert!(matches!(e, TelegramSendMessageError::MessageNotModified));
+}
+
+#[test]
+fn not_json() {
+    // A proxy's error page: the HTTP status still counts
+    let e = fail(502, "<html>Bad Gateway</html>");
+    assert!(matches!(e, TelegramSendMessageError::ApiError { error_code: 502, .. }));
+    assert!(!e.is_permanent());
+    assert!(matches!(parse::<SentMessage>(200, "<html>"), Err(TelegramSendMessageError::ResponseError)));
+}
+
+#[test]
+fn escaped() {
+    // Telegram escapes everything non-ASCII: "пока" is "пока"
+    #[derive(Deserialize)]
+    struct User {
+        first_name: heapless::String<32>,
+    }
+    let user: User = parse(200, r#"{"ok":true,"result":{"first_name":"пока \"❤\""}}"#).unwrap();
+    assert_eq!(user.first_name, "пока \"❤\"");
+}
+
+#[test]
+fn escaped_error() {
+    // An escaped description mustn't cost us the error code and retry_after
+    let e = fail(429, r#"{"ok":false,"error_code":429,"description":"Too Many Requests: повторите \"later\"","parameters":{"retry_after":7}}"#);
+    assert!(matches!(e, TelegramSendMessageError::ApiError { error_code: 429, retry_after: Some(7), .. }));
+}
+
+
+fn fail(status: u16, body: &str) -> TelegramSendMessageError {
+    match parse::<SentMessage>(status, body) {
+        Ok(_) => panic!("expected an error"),
+        Err(e) => e,
+    }
+}
+
+fn parse<T: for<'a> Deserialize<'a>>(status: u16, body: &str) -> Result<T, TelegramSendMessageError> {
+    let mut unescape_buf = [0u8; 256];
+    parse_api_response(status, body.as_bytes(), &mut unescape_buf)
+}
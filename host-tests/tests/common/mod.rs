@@ -0,0 +1,61 @@
+// The Bot API over plain HTTP, on the host: to the mock. See `mock-bot-api/`.
+// The device's is HTTPS: `telegram::TelegramApi`. The calls on top are the same: `bot_api`.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mock_bot_api::{MockBotApi, Scenario};
+
+use host_tests::bot_api::{MessageOptions, TelegramSendMessageError, Transport};
+use host_tests::recipient::{ChatId, Recipient};
+
+
+pub const TOKEN: &str = "1234567890:test";
+
+pub const OPTIONS: MessageOptions = MessageOptions {
+    parse_mode: None,
+    disable_notification: false,
+    protect_content: false,
+    disable_link_preview: false,
+    ack_buttons: true,
+};
+
+/// The mock, with a scenario: see `mock-bot-api/scenarios/`. And a way to it.
+pub fn start(scenario: &str) -> (MockBotApi, MockTransport) {
+    let mock = MockBotApi::start("127.0.0.1:0", Scenario::parse(scenario).unwrap(), Some(TOKEN.into()), None).unwrap();
+    let api = MockTransport { addr: mock.addr() };
+    (mock, api)
+}
+
+pub fn chat(id: i64) -> Recipient {
+    Recipient { chat_id: ChatId::Id(id), message_thread_id: None }
+}
+
+pub struct MockTransport {
+    pub addr: SocketAddr,
+}
+
+impl Transport for MockTransport {
+    async fn post<'b>(&self, method: &str, body: &[u8], rx_buf: &'b mut [u8]) -> Result<(u16, &'b [u8]), TelegramSendMessageError> {
+        let mut tcp = TcpStream::connect(self.addr).map_err(|_| TelegramSendMessageError::ConnectError)?;
+        tcp.set_read_timeout(Some(Duration::from_secs(2))).map_err(|_| TelegramSendMessageError::ConnectError)?;
+        let head = format!(
+            "POST /bot{}/{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            TOKEN, method, self.addr, body.len(),
+        );
+        tcp.write_all(head.as_bytes()).and_then(|_| tcp.write_all(body)).map_err(|_| TelegramSendMessageError::RequestError)?;
+
+        // The response: up to the end. Nothing, or no answer in time: it may have got through.
+        let mut response = Vec::new();
+        tcp.read_to_end(&mut response).map_err(|_| TelegramSendMessageError::RequestError)?;
+        let text = String::from_utf8(response).map_err(|_| TelegramSendMessageError::RequestError)?;
+        let (head, body) = text.split_once("\r\n\r\n").ok_or(TelegramSendMessageError::RequestError)?;
+        let status = head.split(' ').nth(1).and_then(|s| s.parse().ok()).ok_or(TelegramSendMessageError::RequestError)?;
+
+        // Into the caller's buffer: the way the device reads it
+        let rx = rx_buf.get_mut(..body.len()).ok_or(TelegramSendMessageError::RequestError)?;
+        rx.copy_from_slice(body.as_bytes());
+        Ok((status, rx))
+    }
+}
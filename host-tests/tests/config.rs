@@ -0,0 +1,116 @@
+// Compile-time configuration: see `config`. A bad value must fail the build.
+
+use host_tests::config::{list_contains, parse_bool, parse_i32, parse_i64, parse_u32, trim};
+use host_tests::recipient::{ChatId, Recipient, Recipients};
+
+
+#[test]
+fn numbers() {
+    assert_eq!(parse_u32(None, 7), 7);
+    assert_eq!(parse_u32(Some(""), 7), 7);
+    assert_eq!(parse_u32(Some("4294967295"), 7), u32::MAX);
+    assert_eq!(parse_i32(None, -7), -7);
+    assert_eq!(parse_i32(Some(""), -7), -7);
+    assert_eq!(parse_i32(Some("180"), 0), 180);
+    assert_eq!(parse_i32(Some("-180"), 0), -180);
+    assert_eq!(parse_i32(Some("2147483647"), 0), i32::MAX);
+    assert_eq!(parse_i32(Some("-2147483648"), 0), i32::MIN);
+}
+
+#[test]
+#[should_panic(expected = "Invalid number in config")]
+fn minus_alone() {
+    parse_i32(Some("-"), 0);
+}
+
+#[test]
+#[should_panic(expected = "Number too large in config")]
+fn i32_too_large() {
+    parse_i32(Some("2147483648"), 0);
+}
+
+#[test]
+#[should_panic(expected = "Number too large in config")]
+fn i32_too_small() {
+    parse_i32(Some("-2147483649"), 0);
+}
+
+#[test]
+#[should_panic(expected = "Number too large in config")]
+fn u32_too_large() {
+    parse_u32(Some("4294967296"), 0);
+}
+
+#[test]
+#[should_panic(expected = "Invalid number in config")]
+fn not_a_number() {
+    parse_i32(Some("+5"), 0);
+}
+
+#[test]
+fn ids() {
+    assert_eq!(parse_i64(b"691814383"), 691814383);
+    assert_eq!(parse_i64(b"-1001234567890"), -1001234567890);
+    assert_eq!(parse_i64(b"9223372036854775807"), i64::MAX);
+    assert_eq!(parse_i64(b"-9223372036854775808"), i64::MIN);
+}
+
+#[test]
+#[should_panic(expected = "Invalid number in config")]
+fn id_minus_alone() {
+    parse_i64(b"-");
+}
+
+#[test]
+#[should_panic(expected = "Number too large in config")]
+fn id_too_large() {
+    parse_i64(b"9223372036854775808");
+}
+
+#[test]
+#[should_panic(expected = "Invalid number in config")]
+fn id_not_a_number() {
+    parse_i64(b"12 34");
+}
+
+#[test]
+fn trimmed() {
+    assert_eq!(trim(b" \t 12\t "), b"12");
+    assert_eq!(trim(b"\t\t"), b"");
+    assert_eq!(trim(b"a b"), b"a b");
+}
+
+#[test]
+fn recipients() {
+    // Tabs too: a list split over lines in mise.toml
+    const RECIPIENTS: Recipients = Recipients::parse("691814383,\t-1001234567890/42 , @pokakus_channel");
+    let parsed: Vec<&Recipient> = RECIPIENTS.iter().collect();
+    assert_eq!(parsed.len(), 3);
+    assert!(matches!(parsed[0], Recipient { chat_id: ChatId::Id(691814383), message_thread_id: None }));
+    assert!(matches!(parsed[1], Recipient { chat_id: ChatId::Id(-1001234567890), message_thread_id: Some(42) }));
+    assert!(matches!(parsed[2], Recipient { chat_id: ChatId::Username("@pokakus_channel"), message_thread_id: None }));
+}
+
+#[test]
+fn no_recipients() {
+    // Fine: telegram may not be sending. See `telegram`.
+    const RECIPIENTS: Recipients = Recipients::parse(" , ");
+    assert!(RECIPIENTS.is_empty());
+    assert_eq!(RECIPIENTS.len(), 0);
+}
+
+#[test]
+fn flags() {
+    assert!(parse_bool(None, true));
+    assert!(parse_bool(Some("yes"), false));
+    assert!(!parse_bool(Some("0"), true));
+}
+
+#[test]
+fn lists() {
+    assert!(list_contains("telegram, ntfy", "ntfy"));
+    assert!(list_contains(" telegram ,mqtt", "telegram"));
+    assert!(!list_contains("telegram", "tele"));
+    assert!(!list_contains("", "telegram"));
+    assert!(list_contains("mqtt,\tntfy", "ntfy"));
+}
@@ -0,0 +1,148 @@
+// DNS packets: the query, and what we make of the answers. See `dns_packet`.
+
+use std::net::IpAddr;
+
+use host_tests::dns_packet::{encode_query, parse_response, DnsError, RecordType};
+
+
+const ID: u16 = 0x1234;
+
+// A response: header, the question for "api.example.com", then the answers
+fn response(flags: u16, answers: &[&[u8]]) -> Vec<u8> {
+    let mut r = ID.to_be_bytes().to_vec();
+    r.extend_from_slice(&flags.to_be_bytes());
+    r.extend_from_slice(&[0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+    r.extend_from_slice(b"\x03api\x07example\x03com\x00\x00\x01\x00\x01");
+    for a in answers {
+        r.extend_from_slice(a);
+    }
+    r
+}
+
+// A record: its name, type, TTL and data. Class IN.
+fn record(name: &[u8], rtype: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+    let mut r = name.to_vec();
+    r.extend_from_slice(&rtype.to_be_bytes());
+    r.extend_from_slice(&[0, 1]);
+    r.extend_from_slice(&ttl.to_be_bytes());
+    r.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    r.extend_from_slice(data);
+    r
+}
+
+const QUESTION: &[u8] = b"\xc0\x0c";  // a pointer to the name in the question
+const A: u16 = 1;
+const CNAME: u16 = 5;
+const AAAA: u16 = 28;
+const OK: u16 = 0x8180;  // a response, recursion desired and available
+
+fn v4(a: [u8; 4]) -> IpAddr {
+    IpAddr::from(a)
+}
+
+
+#[test]
+fn query() {
+    let mut buf = [0u8; 300];
+    let len = encode_query(&mut buf, ID, "api.example.com.", RecordType::Aaaa).unwrap();
+    assert_eq!(&buf[..len], b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x03api\x07example\x03com\x00\x00\x1c\x00\x01");
+
+    // Names we can't ask for
+    assert_eq!(encode_query(&mut buf, ID, "", RecordType::A), Err(DnsError::Unsupported));
+    assert_eq!(encode_query(&mut buf, ID, "a..b", RecordType::A), Err(DnsError::Unsupported));
+    assert_eq!(encode_query(&mut buf, ID, &"a".repeat(64), RecordType::A), Err(DnsError::Unsupported));
+    let long = ["a".repeat(63).as_str(); 5].join(".");
+    assert_eq!(encode_query(&mut buf, ID, &long, RecordType::A), Err(DnsError::Unsupported));  // doesn't fit
+}
+
+#[test]
+fn answer() {
+    let r = response(OK, &[&record(QUESTION, A, 300, &[192, 0, 2, 1])]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([192, 0, 2, 1]), 300)));
+
+    // The name in full, no pointer
+    let r = response(OK, &[&record(b"\x03api\x07example\x03com\x00", A, 60, &[192, 0, 2, 2])]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([192, 0, 2, 2]), 60)));
+}
+
+#[test]
+fn compression() {
+    // A label, then a pointer to the rest: "edge.example.com"
+    let r = response(OK, &[
+        &record(QUESTION, CNAME, 300, b"\x04edge\xc0\x10"),
+        &record(b"\x04edge\xc0\x10", A, 20, &[192, 0, 2, 3]),
+    ]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([192, 0, 2, 3]), 20)));
+}
+
+#[test]
+fn cname_chain() {
+    // api.example.com → a.cdn.net → b.cdn.net → the address. The TTL: the address record's.
+    let r = response(OK, &[
+        &record(QUESTION, CNAME, 3600, b"\x01a\x03cdn\x03net\x00"),
+        &record(b"\x01a\x03cdn\x03net\x00", CNAME, 600, b"\x01b\xc0\x2f"),
+        &record(b"\x01b\x03cdn\x03net\x00", A, 30, &[198, 51, 100, 7]),
+    ]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([198, 51, 100, 7]), 30)));
+
+    // The chain, but the address isn't there
+    let r = response(OK, &[&record(QUESTION, CNAME, 3600, b"\x01a\x03cdn\x03net\x00")]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Err(DnsError::NotFound));
+}
+
+#[test]
+fn families() {
+    let aaaa = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    let r = response(OK, &[
+        &record(QUESTION, AAAA, 100, &aaaa),
+        &record(QUESTION, A, 200, &[192, 0, 2, 4]),
+        &record(QUESTION, A, 200, &[192, 0, 2, 5]),
+    ]);
+    assert_eq!(parse_response(&r, ID, RecordType::Aaaa), Ok((IpAddr::from(aaaa), 100)));
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([192, 0, 2, 4]), 200)));  // the first
+
+    // Only the other family
+    let r = response(OK, &[&record(QUESTION, A, 200, &[192, 0, 2, 4])]);
+    assert_eq!(parse_response(&r, ID, RecordType::Aaaa), Err(DnsError::NotFound));
+
+    // The right type, the wrong size
+    let r = response(OK, &[&record(QUESTION, AAAA, 100, &[192, 0, 2, 4])]);
+    assert_eq!(parse_response(&r, ID, RecordType::Aaaa), Err(DnsError::Failed));
+}
+
+#[test]
+fn truncated() {
+    let r = response(OK, &[
+        &record(QUESTION, CNAME, 300, b"\x04edge\xc0\x10"),
+        &record(b"\x04edge\xc0\x10", A, 20, &[192, 0, 2, 3]),
+    ]);
+    assert!(parse_response(&r, ID, RecordType::A).is_ok());
+
+    // Cut anywhere: malformed, never an answer, never a panic
+    for len in 0..r.len() {
+        assert_eq!(parse_response(&r[..len], ID, RecordType::A), Err(DnsError::Failed), "cut at {len}");
+    }
+
+    // The server says it's truncated (TC), and what we've asked for didn't fit: ask another
+    let r = response(OK | 0x0200, &[]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Err(DnsError::Failed));
+    let r = response(OK | 0x0200, &[&record(QUESTION, A, 20, &[192, 0, 2, 3])]);
+    assert_eq!(parse_response(&r, ID, RecordType::A), Ok((v4([192, 0, 2, 3]), 20)));
+}
+
+#[test]
+fn not_ours() {
+    let r = response(OK, &[&record(QUESTION, A, 300, &[192, 0, 2, 1])]);
+    assert_eq!(parse_response(&r, ID + 1, RecordType::A), Err(DnsError::Failed));  // another query's
+
+    let mut query = r.clone();
+    query[2] &= 0x7f;  // not a response
+    assert_eq!(parse_response(&query, ID, RecordType::A), Err(DnsError::Failed));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(parse_response(&response(0x8183, &[]), ID, RecordType::A), Err(DnsError::NotFound));  // NXDOMAIN
+    assert_eq!(parse_response(&response(0x8182, &[]), ID, RecordType::A), Err(DnsError::Failed));    // SERVFAIL
+    assert_eq!(parse_response(&response(OK, &[]), ID, RecordType::A), Err(DnsError::NotFound));      // no records
+}
@@ -0,0 +1,168 @@
+// MQTT against the mock broker: the firmware's packets, read by a broker; the broker's, read by the firmware.
+// MQTT 3.1.1 and 5. See `mqtt_packet`, and `mock-mqtt-broker/`.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mock_mqtt_broker::{MockBroker, Scenario};
+
+use host_tests::mqtt_packet::{self as packet, Connect, Publish, Version, Will, CONNACK, PINGRESP, PUBACK, PUBLISH, SUBACK};
+
+
+const VERSIONS: [Version; 2] = [Version::V311, Version::V5];
+
+const WILL: Will<'static> = Will { topic: "pk/availability", payload: b"offline", retain: true };
+
+
+#[test]
+fn last_will() {
+    for v in VERSIONS {
+        let broker = start("");
+        let mut conn = connect(&broker, v, "p");
+        assert_eq!(conn.connack(), 0);
+
+        // Gone without a DISCONNECT: the will's published. Retained.
+        drop(conn);
+        broker.wait_for_publishes(1);
+        assert_eq!(broker.will("pk/availability").as_deref(), Some(&b"offline"[..]));
+        assert_eq!(broker.retained("pk/availability").as_deref(), Some(&b"offline"[..]));
+        assert_eq!(broker.connects(), 1);
+    }
+}
+
+#[test]
+fn bad_password() {
+    for (v, code) in [(Version::V311, 4), (Version::V5, 0x86)] {
+        let broker = start("");
+        let mut conn = connect(&broker, v, "wrong");
+        assert_eq!(conn.connack(), code);
+    }
+}
+
+#[test]
+fn refused() {
+    for (v, code) in [(Version::V311, 5), (Version::V5, 0x87)] {
+        let broker = start("CONNECT refuse");
+        let mut conn = connect(&broker, v, "p");
+        assert_eq!(conn.connack(), code);
+    }
+}
+
+#[test]
+fn publish() {
+    for v in VERSIONS {
+        let broker = start("");
+        let mut conn = connect(&broker, v, "p");
+        conn.connack();
+
+        let p = Publish { topic: "pk/status", payload: br#"{"uptime":42}"#, qos: 1, retain: true, packet_id: 7 };
+        conn.send(&packet::publish(v, &p));
+        let (header, body) = conn.read();
+        assert_eq!(header >> 4, PUBACK);
+        assert_eq!(packet::packet_id(&body).unwrap(), 7);
+        assert!(packet::puback_reason(&body) < 0x80);
+        assert_eq!(broker.retained("pk/status").as_deref(), Some(&br#"{"uptime":42}"#[..]));
+
+        // QoS 0: no PUBACK. The PINGRESP comes next.
+        let p = Publish { topic: "pk/action", payload: b"click", qos: 0, retain: false, packet_id: 0 };
+        conn.send(&packet::publish(v, &p));
+        conn.send(&packet::pingreq());
+        assert_eq!(conn.read().0 >> 4, PINGRESP);
+        assert!(broker.retained("pk/action").is_none());
+    }
+}
+
+#[test]
+fn subscribe() {
+    for v in VERSIONS {
+        let broker = start("");
+        let mut conn = connect(&broker, v, "p");
+        conn.connack();
+
+        conn.send(&packet::subscribe(v, 1, "pk/command"));
+        let (header, body) = conn.read();
+        assert_eq!(header >> 4, SUBACK);
+        assert_eq!(packet::packet_id(&body).unwrap(), 1);
+        assert_eq!(packet::suback_code(v, &body).unwrap(), 1);
+        assert!(broker.subscribed("pk/command"));
+
+        // A command, as if from Home Assistant
+        broker.publish("pk/command", b"status");
+        let (header, body) = conn.read();
+        assert_eq!(header >> 4, PUBLISH);
+        let p = packet::parse_publish(v, header, &body).unwrap();
+        assert_eq!((p.topic, p.payload, p.qos), ("pk/command", &b"status"[..], 1));
+        conn.send(&packet::puback(p.packet_id));
+    }
+}
+
+#[test]
+fn retained_on_subscribe() {
+    for v in VERSIONS {
+        let broker = start("");
+        let mut other = connect(&broker, v, "p");
+        other.connack();
+        let p = Publish { topic: "pk/command", payload: b"undo", qos: 1, retain: true, packet_id: 1 };
+        other.send(&packet::publish(v, &p));
+        other.read();
+
+        let mut conn = connect(&broker, v, "p");
+        conn.connack();
+        conn.send(&packet::subscribe(v, 2, "pk/+"));
+        assert_eq!(conn.read().0 >> 4, SUBACK);
+        let (header, body) = conn.read();
+        let p = packet::parse_publish(v, header, &body).unwrap();
+        assert_eq!((p.topic, p.payload, p.retain), ("pk/command", &b"undo"[..], true));
+    }
+}
+
+
+fn start(scenario: &str) -> MockBroker {
+    MockBroker::start("127.0.0.1:0", Scenario::parse(scenario).unwrap(), Some("u".into()), Some("p".into()), None).unwrap()
+}
+
+// Connect: CONNECT sent, CONNACK not read yet
+fn connect(broker: &MockBroker, v: Version, password: &str) -> Conn {
+    let tcp = TcpStream::connect(broker.addr()).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut conn = Conn { tcp };
+    let c = Connect { client_id: "pokakus", keep_alive_secs: 60, username: Some("u"), password: Some(password), will: Some(WILL) };
+    conn.send(&packet::connect(v, &c));
+    conn
+}
+
+// A connection to the broker: whole packets
+struct Conn {
+    tcp: TcpStream,
+}
+
+impl Conn {
+    fn send(&mut self, packet: &[u8]) {
+        self.tcp.write_all(packet).unwrap();
+    }
+
+    // A packet: the first byte, and the body. The remaining length: the way `mqtt` reads it.
+    fn read(&mut self) -> (u8, Vec<u8>) {
+        let mut byte = [0u8];
+        self.tcp.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut len, mut i) = (0, 0);
+        let len = loop {
+            self.tcp.read_exact(&mut byte).unwrap();
+            if let Some(len) = packet::varint_step(&mut len, i, byte[0]).unwrap() {
+                break len;
+            }
+            i += 1;
+        };
+        let mut body = vec![0; len];
+        self.tcp.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    fn connack(&mut self) -> u8 {
+        let (header, body) = self.read();
+        assert_eq!(header >> 4, CONNACK);
+        packet::connack(&body).unwrap()
+    }
+}
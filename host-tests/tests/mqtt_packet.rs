@@ -0,0 +1,160 @@
+// MQTT packets: the bytes, MQTT 3.1.1 and 5. See `mqtt_packet`.
+// The same packets against a broker: see `mqtt.rs`.
+
+use host_tests::mqtt_packet::{self as packet, Connect, Publish, Version, Will};
+
+
+#[test]
+fn connect() {
+    let c = Connect { client_id: "pk", keep_alive_secs: 60, username: None, password: None, will: None };
+    assert_eq!(packet::connect(Version::V311, &c), b"\x10\x0e\x00\x04MQTT\x04\x02\x00\x3c\x00\x02pk");
+    assert_eq!(packet::connect(Version::V5, &c), b"\x10\x0f\x00\x04MQTT\x05\x02\x00\x3c\x00\x00\x02pk");  // no properties
+}
+
+#[test]
+fn connect_will_credentials() {
+    let will = Will { topic: "pk/availability", payload: b"offline", retain: true };
+    let c = Connect { client_id: "pk", keep_alive_secs: 60, username: Some("u"), password: Some("p"), will: Some(will) };
+    // Flags: username, password, will retain, will QoS 1, will, clean session
+    let mut expected = b"\x10\x2e\x00\x04MQTT\x04\xee\x00\x3c\x00\x02pk".to_vec();
+    expected.extend_from_slice(b"\x00\x0fpk/availability\x00\x07offline\x00\x01u\x00\x01p");
+    assert_eq!(packet::connect(Version::V311, &c), expected);
+
+    // MQTT 5: the will has properties too. None.
+    let mut expected = b"\x10\x30\x00\x04MQTT\x05\xee\x00\x3c\x00\x00\x02pk".to_vec();
+    expected.extend_from_slice(b"\x00\x00\x0fpk/availability\x00\x07offline\x00\x01u\x00\x01p");
+    assert_eq!(packet::connect(Version::V5, &c), expected);
+}
+
+#[test]
+fn publish() {
+    let p = Publish { topic: "t", payload: b"hi", qos: 1, retain: true, packet_id: 7 };
+    assert_eq!(packet::publish(Version::V311, &p), b"\x33\x07\x00\x01t\x00\x07hi");
+    assert_eq!(packet::publish(Version::V5, &p), b"\x33\x08\x00\x01t\x00\x07\x00hi");
+
+    // QoS 0: no packet id
+    let p = Publish { qos: 0, retain: false, ..p };
+    assert_eq!(packet::publish(Version::V311, &p), b"\x30\x05\x00\x01thi");
+}
+
+#[test]
+fn publish_long() {
+    // The remaining length: 7 bits a byte
+    for len in [0, 124, 125, 16380, 16381, 20000] {
+        let payload = vec![b'x'; len];
+        let p = Publish { topic: "t", payload: &payload, qos: 0, retain: false, packet_id: 0 };
+        let bytes = packet::publish(Version::V311, &p);
+        let (remaining, header_len) = remaining_length(&bytes[1..]).unwrap();
+        assert_eq!(remaining, len + 3);
+        assert_eq!(bytes.len(), 1 + header_len + remaining);
+    }
+}
+
+#[test]
+fn subscribe() {
+    assert_eq!(packet::subscribe(Version::V311, 1, "c"), b"\x82\x06\x00\x01\x00\x01c\x01");
+    assert_eq!(packet::subscribe(Version::V5, 1, "c"), b"\x82\x07\x00\x01\x00\x00\x01c\x01");
+}
+
+#[test]
+fn small_ones() {
+    assert_eq!(packet::puback(7), b"\x40\x02\x00\x07");
+    assert_eq!(packet::pingreq(), b"\xc0\x00");
+}
+
+#[test]
+fn connack() {
+    assert_eq!(packet::connack(b"\x00\x00").unwrap(), 0);
+    assert_eq!(packet::connack(b"\x00\x05").unwrap(), 5);  // 3.1.1: not authorized
+    assert_eq!(packet::connack(b"\x00\x87\x00").unwrap(), 0x87);  // 5: not authorized, no properties
+    assert!(packet::connack(b"\x00").is_err());
+}
+
+#[test]
+fn puback() {
+    assert_eq!(packet::packet_id(b"\x00\x07").unwrap(), 7);
+    assert_eq!(packet::puback_reason(b"\x00\x07"), 0);  // 3.1.1
+    assert_eq!(packet::puback_reason(b"\x00\x07\x10"), 0x10);  // 5: no matching subscribers, a success
+    assert_eq!(packet::puback_reason(b"\x00\x07\x87\x00"), 0x87);  // 5: not authorized
+    assert!(packet::packet_id(b"\x00").is_err());
+}
+
+#[test]
+fn suback() {
+    assert_eq!(packet::suback_code(Version::V311, b"\x00\x01\x01").unwrap(), 1);
+    assert_eq!(packet::suback_code(Version::V311, b"\x00\x01\x80").unwrap(), 0x80);
+    assert_eq!(packet::suback_code(Version::V5, b"\x00\x01\x00\x01").unwrap(), 1);
+    // A reason string: skipped
+    assert_eq!(packet::suback_code(Version::V5, b"\x00\x01\x06\x1f\x00\x03abc\x87").unwrap(), 0x87);
+}
+
+#[test]
+fn suback_malformed() {
+    assert!(packet::suback_code(Version::V311, b"\x00\x01").is_err());  // no code
+    assert!(packet::suback_code(Version::V5, b"\x00\x01\x00").is_err());
+    assert!(packet::suback_code(Version::V5, b"\x00\x01").is_err());  // no properties
+    assert!(packet::suback_code(Version::V5, b"\x00\x01\x05\x1f\x00").is_err());  // properties cut short
+    assert!(packet::suback_code(Version::V311, b"\x00").is_err());
+}
+
+#[test]
+fn parse_publish() {
+    let p = packet::parse_publish(Version::V311, 0x32, b"\x00\x01t\x00\x09on").unwrap();
+    assert_eq!((p.topic, p.payload, p.qos, p.retain, p.packet_id), ("t", &b"on"[..], 1, false, 9));
+
+    let p = packet::parse_publish(Version::V311, 0x31, b"\x00\x03a/bon").unwrap();
+    assert_eq!((p.topic, p.payload, p.qos, p.retain), ("a/b", &b"on"[..], 0, true));
+
+    let p = packet::parse_publish(Version::V5, 0x30, b"\x00\x01t\x00on").unwrap();
+    assert_eq!((p.topic, p.payload), ("t", &b"on"[..]));
+}
+
+#[test]
+fn parse_publish_properties() {
+    // Payload format: UTF-8; topic alias 2. Skipped.
+    let p = packet::parse_publish(Version::V5, 0x32, b"\x00\x01t\x00\x09\x05\x01\x01\x23\x00\x02on").unwrap();
+    assert_eq!((p.topic, p.payload, p.packet_id), ("t", &b"on"[..], 9));
+
+    // Long properties: a two-byte length
+    let mut body = b"\x00\x01t\x80\x01".to_vec();
+    body.extend_from_slice(&[0x1f, 0x00, 0x7d]);
+    body.extend_from_slice(&[b'x'; 0x7d]);
+    body.extend_from_slice(b"on");
+    let p = packet::parse_publish(Version::V5, 0x30, &body).unwrap();
+    assert_eq!(p.payload, b"on");
+}
+
+#[test]
+fn parse_publish_malformed() {
+    assert!(packet::parse_publish(Version::V311, 0x30, b"\x00\x05t").is_err());  // topic cut short
+    assert!(packet::parse_publish(Version::V311, 0x30, b"\x00").is_err());
+    assert!(packet::parse_publish(Version::V311, 0x32, b"\x00\x01t\x00").is_err());  // QoS 1, no packet id
+    assert!(packet::parse_publish(Version::V311, 0x30, b"\x00\x01\xff").is_err());  // not UTF-8
+    assert!(packet::parse_publish(Version::V5, 0x30, b"\x00\x01t").is_err());  // no properties
+    assert!(packet::parse_publish(Version::V5, 0x30, b"\x00\x01t\x09\x01").is_err());  // properties cut short
+    assert!(packet::parse_publish(Version::V5, 0x30, b"\x00\x01t\x80\x80\x80\x80\x01").is_err());  // 5-byte length
+    assert!(packet::parse_publish(Version::V5, 0x30, b"\x00\x01t\x80").is_err());  // length cut short
+}
+
+#[test]
+fn remaining_lengths() {
+    assert_eq!(remaining_length(b"\x00").unwrap(), (0, 1));
+    assert_eq!(remaining_length(b"\x7f").unwrap(), (127, 1));
+    assert_eq!(remaining_length(b"\x80\x01").unwrap(), (128, 2));
+    assert_eq!(remaining_length(b"\xff\x7f").unwrap(), (16_383, 2));
+    assert_eq!(remaining_length(b"\x80\x80\x01").unwrap(), (16_384, 3));
+    assert_eq!(remaining_length(b"\xff\xff\xff\x7f").unwrap(), (268_435_455, 4));  // the most there is
+    assert!(remaining_length(b"\x80\x80\x80\x80\x01").is_none());
+}
+
+
+// The remaining length, the way `mqtt` reads it: a byte at a time. And how many bytes it took.
+fn remaining_length(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        if let Some(len) = packet::varint_step(&mut value, i, byte).ok()? {
+            return Some((len, i + 1));
+        }
+    }
+    None
+}
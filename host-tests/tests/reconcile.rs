@@ -0,0 +1,73 @@
+// Reconciliation against the mock Bot API: a send has failed, has the message got through?
+// See `bot_api::reconcile()`. Every test has a chat of its own: the last message ids are global.
+
+mod common;
+
+use embassy_futures::block_on;
+
+use host_tests::bot_api;
+use host_tests::recipient::ChatId;
+use host_tests::retry::Retryable;
+
+use common::{chat, start, OPTIONS};
+
+
+#[test]
+fn dropped_response() {
+    // The message arrives, the response is lost: it's found, not sent again
+    let (mock, api) = start("sendMessage ok\nsendMessage drop");
+    let to = chat(1001);
+    block_on(bot_api::send_message(&api, to, "first", &OPTIONS)).unwrap();
+    let since = bot_api::last_message_id(ChatId::Id(1001)).unwrap();
+    let e = block_on(bot_api::send_message(&api, to, "second", &OPTIONS)).err().unwrap();
+    assert!(e.maybe_delivered());
+    assert_eq!(bot_api::last_message_id(ChatId::Id(1001)), None);  // something may be after it now
+
+    assert_eq!(block_on(bot_api::reconcile(&api, to, "second", &OPTIONS, since)), Some(2));
+    assert_eq!(mock.calls("editMessageText"), 1);
+    assert_eq!(mock.messages("1001"), ["first", "second"]);
+    assert_eq!(bot_api::last_message_id(ChatId::Id(1001)), Some(2));
+}
+
+#[test]
+fn lost() {
+    // It hasn't arrived: nothing to find
+    let (mock, api) = start("sendMessage ok");
+    let to = chat(1002);
+    block_on(bot_api::send_message(&api, to, "first", &OPTIONS)).unwrap();
+
+    assert_eq!(block_on(bot_api::reconcile(&api, to, "second", &OPTIONS, 1)), None);
+    assert_eq!(mock.calls("editMessageText"), 3);  // a few ids after the last one
+    assert_eq!(mock.messages("1002"), ["first"]);
+}
+
+#[test]
+fn other_message() {
+    // Before ours, another message of ours went missing: it may be there, after the last one we know of.
+    // Probing would edit it into ours. So there's nothing to go by: ours is sent again, nothing's edited.
+    let (mock, api) = start("sendMessage ok\nsendMessage drop\nsendMessage drop");
+    let to = chat(1003);
+    block_on(bot_api::send_message(&api, to, "first", &OPTIONS)).unwrap();
+    assert!(block_on(bot_api::send_message(&api, to, "other", &OPTIONS)).is_err());
+
+    let since = bot_api::last_message_id(ChatId::Id(1003));
+    assert_eq!(since, None);
+    assert!(block_on(bot_api::send_or_reconcile(&api, to, "second", &OPTIONS, since)).is_err());
+    assert_eq!(mock.calls("editMessageText"), 0);
+    assert_eq!(mock.messages("1003"), ["first", "other", "second"]);
+}
+
+#[test]
+fn known_message() {
+    // Before ours, another message of ours that we know of: we look after it, it's left alone
+    let (mock, api) = start("sendMessage ok\nsendMessage ok\nsendMessage drop");
+    let to = chat(1004);
+    block_on(bot_api::send_message(&api, to, "first", &OPTIONS)).unwrap();
+    block_on(bot_api::send_message(&api, to, "other", &OPTIONS)).unwrap();
+
+    let since = bot_api::last_message_id(ChatId::Id(1004));
+    assert_eq!(since, Some(2));
+    assert_eq!(block_on(bot_api::send_or_reconcile(&api, to, "second", &OPTIONS, since)).unwrap().message_id, 3);
+    assert_eq!(mock.calls("editMessageText"), 1);
+    assert_eq!(mock.messages("1004"), ["first", "other", "second"]);
+}
@@ -0,0 +1,124 @@
+// Sending against the mock Bot API: requests built, responses parsed, retries, the way the device does.
+// The scenarios are those in `mock-bot-api/scenarios/`. Every test has a chat of its own: the last message ids are global.
+
+mod common;
+
+use std::time::Instant;
+
+use embassy_futures::block_on;
+use embassy_time::Duration;
+
+use host_tests::bot_api::{self, SentMessage, TelegramSendMessageError};
+use host_tests::recipient::ChatId;
+use host_tests::retry::{DeliveryMode, Retryable, RetryPolicy};
+
+use common::{chat, start, MockTransport, OPTIONS};
+
+
+// The device's, but quick
+const AT_LEAST_ONCE: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    initial_delay: Duration::from_millis(10),
+    max_delay: Duration::from_millis(100),
+    mode: DeliveryMode::AtLeastOnce,
+};
+const AT_MOST_ONCE: RetryPolicy = RetryPolicy { mode: DeliveryMode::AtMostOnce, ..AT_LEAST_ONCE };
+
+
+#[test]
+fn ok() {
+    let (mock, api) = start("");
+    let sent = send(&api, AT_LEAST_ONCE, 2001, "Pokakus! #ev00002a").unwrap();
+    assert_eq!(sent.message_id, 1);
+    assert_eq!(sent.chat.map(|c| c.id), Some(2001));
+    assert_eq!(mock.calls("sendMessage"), 1);
+    assert_eq!(mock.messages("2001"), ["Pokakus! #ev00002a"]);
+    assert!(mock.check().iter().all(|c| c.is_ok()));
+}
+
+#[test]
+fn flood_control() {
+    // Telegram says when: that's how long we wait
+    let (mock, api) = start("sendMessage 429 retry_after=1");
+    let start = Instant::now();
+    send(&api, AT_LEAST_ONCE, 2002, "Pokakus!").unwrap();
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    assert_eq!(mock.calls("sendMessage"), 2);
+    assert_eq!(mock.messages("2002"), ["Pokakus!"]);
+}
+
+#[test]
+fn unauthorized() {
+    // Permanent: not retried
+    let (mock, api) = start("sendMessage 401");
+    let e = send(&api, AT_LEAST_ONCE, 2003, "Pokakus!").err().unwrap();
+    assert!(e.is_permanent());
+    assert_eq!(e.error_code(), Some(401));
+    assert_eq!(mock.calls("sendMessage"), 1);
+    assert!(mock.messages("2003").is_empty());
+}
+
+#[test]
+fn server_error() {
+    // Retried, with backoff
+    let (mock, api) = start("sendMessage 500\nsendMessage 502 Bad Gateway");
+    send(&api, AT_LEAST_ONCE, 2004, "Pokakus!").unwrap();
+    assert_eq!(mock.calls("sendMessage"), 3);
+    assert_eq!(mock.messages("2004"), ["Pokakus!"]);
+}
+
+#[test]
+fn dropped_response() {
+    // At least once: the message is found, not sent again
+    let (mock, api) = start("sendMessage ok\nsendMessage drop");
+    send(&api, AT_LEAST_ONCE, 2005, "first").unwrap();
+    let sent = send(&api, AT_LEAST_ONCE, 2005, "second").unwrap();
+    assert_eq!(sent.message_id, 2);
+    assert_eq!(mock.calls("sendMessage"), 2);
+    assert_eq!(mock.calls("editMessageText"), 1);
+    assert_eq!(mock.messages("2005"), ["first", "second"]);
+}
+
+#[test]
+fn dropped_response_at_most_once() {
+    // Nothing to go by, and it may have got through: given up on
+    let (mock, api) = start("sendMessage drop");
+    let e = send(&api, AT_MOST_ONCE, 2006, "Pokakus!").err().unwrap();
+    assert!(e.maybe_delivered());
+    assert_eq!(mock.calls("sendMessage"), 1);
+    assert_eq!(mock.messages("2006"), ["Pokakus!"]);
+}
+
+#[test]
+fn timeout() {
+    // No answer: it hasn't arrived, so it's sent again. Once.
+    let (mock, api) = start("sendMessage timeout");
+    send(&api, AT_LEAST_ONCE, 2007, "Pokakus!").unwrap();
+    assert_eq!(mock.calls("sendMessage"), 2);
+    assert_eq!(mock.messages("2007"), ["Pokakus!"]);
+}
+
+#[test]
+fn edit_and_delete() {
+    // Undo: see `undo`
+    let (mock, api) = start("");
+    let sent = send(&api, AT_LEAST_ONCE, 2008, "Pokakus!").unwrap();
+    let chat_id = ChatId::Id(2008);
+
+    block_on(bot_api::edit_message_text(&api, chat_id, sent.message_id, "~Pokakus!~ (undone)", None, None)).unwrap();
+    assert_eq!(mock.messages("2008"), ["~Pokakus!~ (undone)"]);
+    let e = block_on(bot_api::edit_message_text(&api, chat_id, sent.message_id, "~Pokakus!~ (undone)", None, None)).err().unwrap();
+    assert!(matches!(e, TelegramSendMessageError::MessageNotModified));
+
+    block_on(bot_api::delete_message(&api, chat_id, sent.message_id)).unwrap();
+    assert!(mock.messages("2008").is_empty());
+    let e = block_on(bot_api::delete_message(&api, chat_id, sent.message_id)).err().unwrap();
+    assert_eq!(e.error_code(), Some(400));
+}
+
+
+// One recipient, the way `telegram::fan_out()` sends to each: lost messages are looked for after the last one we knew of
+fn send(api: &MockTransport, policy: RetryPolicy, chat_id: i64, text: &str) -> Result<SentMessage, TelegramSendMessageError> {
+    let since = bot_api::last_message_id(ChatId::Id(chat_id));
+    block_on(policy.run(|| bot_api::send_or_reconcile(api, chat(chat_id), text, &OPTIONS, since), |_, _| ()))
+}
@@ -0,0 +1,165 @@
+// Message templates: checking, rendering, escaping. See `template`.
+
+use embassy_time::{Duration, Instant};
+
+use host_tests::gesture::Gesture;
+use host_tests::event::Event;
+use host_tests::template::{self, render, validate, validate_message, Context, TemplateError};
+use host_tests::text::ParseMode;
+
+
+// Every placeholder: a template that must build
+const EVERYTHING: &str = "#{count_today} at {time}, {since_last} since; up {uptime}, {rssi} dBm, {hostname} {device_id}: {gesture}. {message} {event_id} {{ok}}";
+const _: () = template::check(EVERYTHING);
+
+
+#[test]
+fn valid() {
+    assert_eq!(validate(EVERYTHING), Ok(()));
+    assert_eq!(validate(""), Ok(()));
+    assert_eq!(validate("no placeholders"), Ok(()));
+    assert_eq!(validate("{{}}"), Ok(()));
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(validate("at {tme}"), Err(TemplateError::UnknownPlaceholder(3)));
+    assert_eq!(validate("at {}"), Err(TemplateError::UnknownPlaceholder(3)));
+    assert_eq!(validate("at {time"), Err(TemplateError::Unclosed(3)));
+    assert_eq!(validate("{{time}"), Err(TemplateError::UnmatchedClose(6)));
+    assert_eq!(validate("}"), Err(TemplateError::UnmatchedClose(0)));
+}
+
+#[test]
+fn message() {
+    // The message itself: it can't contain itself, and the event id is appended anyway
+    assert_eq!(validate_message("{count_today} at {time}"), Ok(()));
+    assert_eq!(validate_message("at {time}: {message}"), Err(TemplateError::BodyOnly(11)));
+    assert_eq!(validate_message("{event_id}"), Err(TemplateError::BodyOnly(0)));
+    assert_eq!(validate_message("{{message}}"), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "Template: unknown placeholder")]
+fn check_unknown() {
+    template::check("{count}");
+}
+
+#[test]
+#[should_panic(expected = "Template: unclosed brace")]
+fn check_unclosed() {
+    template::check("{time");
+}
+
+#[test]
+#[should_panic(expected = "Template: unmatched closing brace")]
+fn check_unmatched() {
+    template::check("time}");
+}
+
+#[test]
+#[should_panic(expected = "Template: {message} and {event_id} are for webhook bodies")]
+fn check_message_body_only() {
+    template::check_message("{message}");
+}
+
+
+#[test]
+fn placeholders() {
+    let event = event();
+    let ctx = context(Some(&event));
+    assert_eq!(rendered("{count_today}", &ctx), "3");
+    assert_eq!(rendered("{time}", &ctx), "13:05");  // UTC: see `clock`
+    assert_eq!(rendered("{since_last}", &ctx), "1 h 30 min");
+    assert_eq!(rendered("{rssi}", &ctx), "-61");
+    assert_eq!(rendered("{hostname}", &ctx), "pokakus");
+    assert_eq!(rendered("{gesture}", &ctx), "double click");
+    assert_eq!(rendered("{device_id}", &ctx), "a0b1c2d3e4f5");
+    assert_eq!(rendered("{message}", &ctx), "Pokakus!");
+    assert_eq!(rendered("{event_id}", &ctx), "1a2b3c");
+    assert!(rendered("{uptime}", &ctx).ends_with(" s"));
+}
+
+#[test]
+fn placeholders_unknown() {
+    // No event, no signal: placeholders for what we don't know
+    let ctx = Context { rssi: None, message: None, event_id: None, ..context(None) };
+    assert_eq!(rendered("{count_today} {time} {since_last} {rssi} {gesture}", &ctx), "? --:-- — ? ?");
+    assert_eq!(rendered("[{message}{event_id}]", &ctx), "[]");
+}
+
+#[test]
+fn durations() {
+    let mut out = String::new();
+    for secs in [0, 59, 60, 3599, 3600, 86399, 86400, 2 * 86400 + 4 * 3600 + 59] {
+        template::write_duration(&mut out, Duration::from_secs(secs)).unwrap();
+        out.push('|');
+    }
+    assert_eq!(out, "0 s|59 s|1 min|59 min|1 h 0 min|23 h 59 min|1 d 0 h|2 d 4 h|");
+}
+
+#[test]
+fn braces() {
+    let ctx = context(None);
+    assert_eq!(rendered("{{hostname}} is {hostname}", &ctx), "{hostname} is pokakus");
+    assert_eq!(rendered("}}{{", &ctx), "}{");
+    // Invalid templates fail the build: rendering them is best effort
+    assert_eq!(rendered("{nope} {", &ctx), "{nope} {");
+}
+
+#[test]
+fn markdown_v2() {
+    // Values are escaped; the template is markup, as is
+    let ctx = Context { parse_mode: Some(ParseMode::MarkdownV2), message: Some("a_b*c [d](e) 1.5!"), ..context(None) };
+    assert_eq!(rendered("*{message}*", &ctx), r"*a\_b\*c \[d\]\(e\) 1\.5\!*");
+    assert_eq!(rendered("{rssi}", &ctx), r"\-61");
+}
+
+#[test]
+fn html() {
+    let ctx = Context { parse_mode: Some(ParseMode::Html), message: Some(r#"<b>"Tom & Jerry"</b>"#), ..context(None) };
+    assert_eq!(rendered("<i>{message}</i>", &ctx), "<i>&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;</i>");
+}
+
+#[test]
+fn plain() {
+    let ctx = Context { message: Some(r#"<b>*"x"*</b>"#), ..context(None) };
+    assert_eq!(rendered("{message}", &ctx), r#"<b>*"x"*</b>"#);
+}
+
+#[test]
+fn json() {
+    let ctx = Context { json: true, message: Some("say \"hi\"\\\n\tbye\u{1}"), ..context(None) };
+    assert_eq!(rendered(r#"{"text":"{message}"}"#, &ctx), r#"{"text":"say \"hi\"\\\n\tbye\u0001"}"#);
+}
+
+
+fn rendered(template: &str, ctx: &Context) -> String {
+    let mut out = String::new();
+    render(&mut out, template, ctx).unwrap();
+    out
+}
+
+// 13:05 UTC; the previous one: an hour and a half ago
+fn event() -> Event {
+    Event {
+        gesture: Gesture::DoubleClick,
+        at: Instant::now(),
+        unix_time: Some(1_767_272_700),
+        count_today: 3,
+        since_last: Some(Duration::from_secs(5400)),
+    }
+}
+
+fn context(event: Option<&Event>) -> Context<'_> {
+    Context {
+        parse_mode: None,
+        json: false,
+        event,
+        hostname: "pokakus",
+        device_id: [0xa0, 0xb1, 0xc2, 0xd3, 0xe4, 0xf5],
+        rssi: Some(-61),
+        message: Some("Pokakus!"),
+        event_id: Some("1a2b3c"),
+    }
+}
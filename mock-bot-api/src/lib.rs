@@ -0,0 +1,385 @@
+// Mock Bot API: a stand-in for api.telegram.org, for testing the device's send path.
+//
+// Runs on the host: `src/main.rs`. Point the device at it, and press the button:
+//   $ TELEGRAM_API_URL=http://192.168.1.10:8081 cargo run              # in pokakus/
+//   $ cargo run -- scenarios/dropped-response.txt --requests 4         # here
+//
+// It keeps chats in memory, the way Telegram would: messages get ids, can be edited and deleted.
+// Every request is recorded: printed as a JSON line, and appended to `--log`.
+// A scenario scripts the responses: see `scenarios/`.
+//   sendMessage 429 retry_after=3    # flood control
+//   sendMessage drop                 # the message is sent, the response is lost
+//   sendMessage timeout              # no response at all
+//   expect sendMessage 3             # checked at the end
+//   expect messages 691814383 1      # messages in the chat: no duplicates
+// Once a method's responses have run out: it works normally.
+//
+// With `--requests N`, stops after N requests (getUpdates not counted) and checks the expectations.
+// Exit code: 0 if they're met.
+//
+// Tests start it in-process: `MockBotApi::start()`. See `host-tests/`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+
+/// A mock Bot API, serving: a thread per connection
+pub struct MockBotApi {
+    addr: SocketAddr,
+    state: Arc<(Mutex<State>, Condvar)>,  // the condition: a request's been served
+}
+
+/// An expectation, checked
+pub struct Checked {
+    pub what: String,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl MockBotApi {
+    /// Listen on `listen`: e.g. "0.0.0.0:8081"; port 0 for any.
+    /// `token`: only this one's authorized; any, if `None`. `log`: every request is appended to it.
+    pub fn start(listen: &str, scenario: Scenario, token: Option<String>, log: Option<File>) -> std::io::Result<MockBotApi> {
+        let listener = TcpListener::bind(listen)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new((Mutex::new(State { scenario, chats: HashMap::new(), calls: HashMap::new(), counted: 0, log }), Condvar::new()));
+
+        let server = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (state, token) = (server.clone(), token.clone());
+                std::thread::spawn(move || {
+                    if let Err(e) = serve(stream, &state.0, token.as_deref()) {
+                        eprintln!("Connection: {}", e);
+                    }
+                    state.1.notify_all();
+                });
+            }
+        });
+        Ok(MockBotApi { addr, state })
+    }
+
+    /// Where it's listening
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Wait until `n` requests have been served: getUpdates not counted
+    pub fn wait_for_requests(&self, n: usize) {
+        let mut state = self.state.0.lock().unwrap();
+        while state.counted < n {
+            state = self.state.1.wait(state).unwrap();
+        }
+    }
+
+    /// How many times a method's been called
+    pub fn calls(&self, method: &str) -> usize {
+        self.state.0.lock().unwrap().calls.get(method).copied().unwrap_or(0)
+    }
+
+    /// The messages in a chat, as they are now: their text
+    pub fn messages(&self, chat_id: &str) -> Vec<String> {
+        let state = self.state.0.lock().unwrap();
+        state.chats.get(chat_id).map_or(Vec::new(), |c| c.messages.iter().map(|m| m.text.clone()).collect())
+    }
+
+    /// Check the scenario's expectations
+    pub fn check(&self) -> Vec<Checked> {
+        let state = self.state.0.lock().unwrap();
+        state.scenario.expectations.iter().map(|expect| match expect {
+            Expect::Calls { method, count } => Checked {
+                what: format!("{} calls", method),
+                expected: *count,
+                actual: state.calls.get(method).copied().unwrap_or(0),
+            },
+            Expect::Messages { chat_id, count } => Checked {
+                what: format!("messages in {}", chat_id),
+                expected: *count,
+                actual: state.chats.get(chat_id).map_or(0, |c| c.messages.len()),
+            },
+        }).collect()
+    }
+}
+
+impl Checked {
+    pub fn is_ok(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+
+// Scenario: scripted responses, expectations
+#[derive(Default)]
+pub struct Scenario {
+    responses: HashMap<String, VecDeque<Scripted>>,  // per method, in order
+    expectations: Vec<Expect>,
+}
+
+#[derive(Clone, Debug)]
+enum Scripted {
+    Ok,                                                     // work normally
+    Error { code: u16, retry_after: Option<u32>, description: String },
+    Drop,                                                   // do it, but close the connection without responding
+    Timeout,                                                // don't do it, don't respond: hold the connection
+}
+
+#[derive(Clone)]
+pub enum Expect {
+    Calls { method: String, count: usize },
+    Messages { chat_id: String, count: usize },
+}
+
+impl Scenario {
+    /// Load a scenario file: see `scenarios/`
+    pub fn load(path: &str) -> Result<Scenario, String> {
+        Scenario::parse(&std::fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    /// Parse a scenario: one response or expectation per line
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut scenario = Scenario::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match words.as_slice() {
+                ["expect", "messages", chat_id, count] => count.parse().ok().map(|count| {
+                    scenario.expectations.push(Expect::Messages { chat_id: chat_id.to_string(), count });
+                }),
+                ["expect", method, count] => count.parse().ok().map(|count| {
+                    scenario.expectations.push(Expect::Calls { method: method.to_string(), count });
+                }),
+                [method, response, rest @ ..] => Scripted::parse(response, rest).map(|scripted| {
+                    scenario.responses.entry(method.to_string()).or_default().push_back(scripted);
+                }),
+                _ => None,
+            };
+            parsed.ok_or(format!("line {}: can't make sense of it: {}", n + 1, line))?;
+        }
+        Ok(scenario)
+    }
+}
+
+impl Scripted {
+    // "ok", "drop", "timeout", or an error code: "429 retry_after=3", "400 Bad Request: chat not found"
+    fn parse(response: &str, rest: &[&str]) -> Option<Scripted> {
+        match response {
+            "ok" => Some(Scripted::Ok),
+            "drop" => Some(Scripted::Drop),
+            "timeout" => Some(Scripted::Timeout),
+            code => {
+                let code: u16 = code.parse().ok()?;
+                let retry_after = rest.iter().find_map(|w| w.strip_prefix("retry_after=")).and_then(|v| v.parse().ok());
+                let description: Vec<&str> = rest.iter().copied().filter(|w| !w.starts_with("retry_after=")).collect();
+                let description = match (description.is_empty(), code) {
+                    (false, _) => description.join(" "),
+                    (true, 401) => String::from("Unauthorized"),
+                    (true, 403) => String::from("Forbidden: bot was blocked by the user"),
+                    (true, 429) => format!("Too Many Requests: retry after {}", retry_after.unwrap_or(1)),
+                    (true, _) => String::from("Internal Server Error"),
+                };
+                Some(Scripted::Error { code, retry_after, description })
+            }
+        }
+    }
+}
+
+
+// Bot state: what Telegram would remember
+struct State {
+    scenario: Scenario,
+    chats: HashMap<String, Chat>,
+    calls: HashMap<String, usize>,
+    counted: usize,  // requests, getUpdates aside
+    log: Option<File>,
+}
+
+#[derive(Default)]
+struct Chat {
+    next_id: i64,
+    messages: Vec<Message>,
+}
+
+struct Message {
+    message_id: i64,
+    text: String,
+    reply_markup: Value,
+}
+
+// Serve a connection: one request
+fn serve(mut stream: TcpStream, state: &Mutex<State>, token: Option<&str>) -> std::io::Result<()> {
+    let (path, body) = read_request(&mut stream)?;
+
+    // "/bot<token>/<method>"
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let (bot, method) = (parts.next().unwrap_or(""), parts.next().unwrap_or("").to_string());
+    let authorized = token.is_none_or(|t| bot.strip_prefix("bot") == Some(t));
+    let params: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (scripted, long_poll) = {
+        let mut state = state.lock().unwrap();
+        *state.calls.entry(method.clone()).or_default() += 1;
+        let scripted = state.scenario.responses.get_mut(&method).and_then(|r| r.pop_front()).unwrap_or(Scripted::Ok);
+        (scripted, method == "getUpdates")
+    };
+
+    // Long polling: nothing's going to happen. Don't keep the device waiting too long.
+    if long_poll && matches!(scripted, Scripted::Ok) {
+        let timeout = params["timeout"].as_u64().unwrap_or(0).min(5);
+        std::thread::sleep(Duration::from_secs(timeout));
+    }
+
+    let mut state = state.lock().unwrap();
+    let response = match (&scripted, authorized) {
+        (_, false) => error(401, None, "Unauthorized"),
+        (Scripted::Error { code, retry_after, description }, true) => error(*code, *retry_after, description),
+        (Scripted::Timeout, true) => Value::Null,
+        (Scripted::Ok | Scripted::Drop, true) => state.call(&method, &params),
+    };
+    if !long_poll {
+        state.counted += 1;
+    }
+    state.record(&method, &params, &scripted, &response);
+    drop(state);
+
+    match scripted {
+        Scripted::Drop => Ok(()),  // the connection's closed: the response is lost
+        Scripted::Timeout => {
+            std::thread::sleep(Duration::from_secs(120));
+            Ok(())
+        }
+        _ => write_response(&mut stream, &response),
+    }
+}
+
+impl State {
+    // Do what Telegram would do
+    fn call(&mut self, method: &str, params: &Value) -> Value {
+        let chat_id = match &params["chat_id"] {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match method {
+            "getMe" => ok(json!({ "id": 1234567890, "is_bot": true, "first_name": "Mock", "username": "mock_bot" })),
+            "getUpdates" => ok(json!([])),
+            "answerCallbackQuery" => ok(json!(true)),
+            "sendMessage" => {
+                let chat = self.chats.entry(chat_id.clone()).or_default();
+                chat.next_id += 1;
+                let message = Message {
+                    message_id: chat.next_id,
+                    text: params["text"].as_str().unwrap_or("").to_string(),
+                    reply_markup: params["reply_markup"].clone(),
+                };
+                let result = message_json(&chat_id, &message);
+                chat.messages.push(message);
+                ok(result)
+            }
+            "editMessageText" => {
+                let message_id = params["message_id"].as_i64().unwrap_or(0);
+                let text = params["text"].as_str().unwrap_or("");
+                let Some(message) = self.chats.get_mut(&chat_id).and_then(|c| c.messages.iter_mut().find(|m| m.message_id == message_id)) else {
+                    return error(400, None, "Bad Request: message to edit not found");
+                };
+                if message.text == text && message.reply_markup == params["reply_markup"] {
+                    return error(400, None, "Bad Request: message is not modified: specified new message content and reply markup are exactly the same as a current content and reply markup of the message");
+                }
+                message.text = text.to_string();
+                message.reply_markup = params["reply_markup"].clone();
+                ok(message_json(&chat_id, message))
+            }
+            "deleteMessage" => {
+                let message_id = params["message_id"].as_i64().unwrap_or(0);
+                let chat = self.chats.entry(chat_id).or_default();
+                match chat.messages.iter().position(|m| m.message_id == message_id) {
+                    Some(index) => {
+                        chat.messages.remove(index);
+                        ok(json!(true))
+                    }
+                    None => error(400, None, "Bad Request: message to delete not found"),
+                }
+            }
+            _ => error(404, None, "Not Found"),
+        }
+    }
+
+    // Record a request: stdout, and the log
+    fn record(&mut self, method: &str, params: &Value, scripted: &Scripted, response: &Value) {
+        let line = json!({
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            "method": method,
+            "params": params,
+            "scripted": format!("{:?}", scripted),
+            "response": response,
+        }).to_string();
+        println!("{}", line);
+        if let Some(log) = self.log.as_mut() {
+            let _ = writeln!(log, "{}", line);
+        }
+    }
+}
+
+fn message_json(chat_id: &str, message: &Message) -> Value {
+    let date = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    json!({
+        "message_id": message.message_id,
+        "chat": { "id": chat_id.parse::<i64>().map(Value::from).unwrap_or(Value::from(chat_id)) },
+        "date": date,
+        "text": message.text,
+    })
+}
+
+fn ok(result: Value) -> Value {
+    json!({ "ok": true, "result": result })
+}
+
+fn error(code: u16, retry_after: Option<u32>, description: &str) -> Value {
+    let mut response = json!({ "ok": false, "error_code": code, "description": description });
+    if let Some(secs) = retry_after {
+        response["parameters"] = json!({ "retry_after": secs });
+    }
+    response
+}
+
+
+// HTTP: just enough. One request per connection.
+fn read_request(stream: &mut TcpStream) -> std::io::Result<(String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().unwrap_or(0);
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((path, body))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Value) -> std::io::Result<()> {
+    let status = match response["error_code"].as_u64() {
+        None => String::from("200 OK"),
+        Some(code) => format!("{} Error", code),
+    };
+    let body = response.to_string();
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)?;
+    stream.flush()
+}
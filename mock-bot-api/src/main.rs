@@ -0,0 +1,80 @@
+// Mock Bot API: the command line. See `lib.rs`.
+//   $ cargo run -- scenarios/dropped-response.txt --requests 4
+
+use std::fs::OpenOptions;
+
+use mock_bot_api::{MockBotApi, Scenario};
+
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: mock-bot-api [scenario.txt] [--listen 0.0.0.0:8081] [--token TOKEN] [--requests N] [--log requests.jsonl]");
+            std::process::exit(2);
+        }
+    };
+    let scenario = match &args.scenario {
+        Some(path) => Scenario::load(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        }),
+        None => Scenario::default(),
+    };
+    let log = args.log.as_ref().map(|path| {
+        OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        })
+    });
+
+    let mock = MockBotApi::start(&args.listen, scenario, args.token, log).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args.listen, e);
+        std::process::exit(2);
+    });
+    eprintln!("Mock Bot API: listening on http://{}", mock.addr());
+
+    // Wait for `--requests`. Without it: run until killed.
+    match args.requests {
+        Some(n) => mock.wait_for_requests(n),
+        None => loop {
+            std::thread::park();
+        },
+    }
+
+    let mut failed = false;
+    for checked in mock.check() {
+        failed |= !checked.is_ok();
+        eprintln!("{} {}: expected {}, got {}", if checked.is_ok() { "ok  " } else { "FAIL" }, checked.what, checked.expected, checked.actual);
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
+
+// Command line
+struct Args {
+    scenario: Option<String>,
+    listen: String,
+    token: Option<String>,  // only this token is authorized; any, if not given
+    requests: Option<usize>,
+    log: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args { scenario: None, listen: String::from("0.0.0.0:8081"), token: None, requests: None, log: None };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{}: value expected", name));
+            match arg.as_str() {
+                "--listen" => out.listen = value("--listen")?,
+                "--token" => out.token = Some(value("--token")?),
+                "--log" => out.log = Some(value("--log")?),
+                "--requests" => out.requests = Some(value("--requests")?.parse().map_err(|_| "--requests: a number expected")?),
+                _ if arg.starts_with("--") => return Err(format!("{}: unknown option", arg)),
+                _ => out.scenario = Some(arg),
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,697 @@
+// Mock MQTT broker: a stand-in for Mosquitto, for testing the device's MQTT backend.
+//
+// Runs on the host. Point the device at it:
+//   $ MQTT_URL=mqtt://192.168.1.10:1883 cargo run --features mqtt    # in pokakus/
+//   $ cargo run -- scenarios/ok.txt --publishes 12                   # here
+//
+// MQTT 3.1.1 and 5, QoS 0 and 1: what the device speaks. Retained messages, last wills, topic filters.
+// Every packet is recorded: printed as a JSON line, and appended to `--log`.
+// A scenario scripts faults, and the broker's own publishes: see `scenarios/`.
+//   CONNECT refuse                           # CONNACK: not authorized
+//   PUBLISH pokakus/event noack              # take it, don't acknowledge it
+//   PUBLISH pokakus/event drop               # take it, close the connection: the will is published
+//   PINGREQ drop                             # close the connection
+//   after 5 publish pokakus/command status   # seconds after the first connection
+//   expect publish pokakus/# 12              # checked at the end. A topic filter.
+//   expect retained pokakus/availability online
+//   expect will pokakus/availability offline
+//   expect subscribed pokakus/command
+//   expect connects 1
+// Once the faults have run out: it works normally.
+//
+// With `--publishes N`, stops after N publishes from clients (wills included) and checks the expectations.
+// Exit code: 0 if they're met.
+//
+// Tests start it in-process: `MockBroker::start()`. See `host-tests/`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+
+/// A mock broker, serving: a thread per connection
+pub struct MockBroker {
+    addr: SocketAddr,
+    state: Arc<(Mutex<State>, Condvar)>,  // the condition: a client's published, or a will has been
+}
+
+/// An expectation, checked
+pub struct Checked {
+    pub what: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl MockBroker {
+    /// Listen on `listen`: e.g. "0.0.0.0:1883"; port 0 for any.
+    /// `username`, `password`: only these are accepted; any, if `None`. `log`: every packet is appended to it.
+    pub fn start(listen: &str, mut scenario: Scenario, username: Option<String>, password: Option<String>, log: Option<File>) -> std::io::Result<MockBroker> {
+        let listener = TcpListener::bind(listen)?;
+        let addr = listener.local_addr()?;
+        let after = std::mem::take(&mut scenario.after);
+        let state = Arc::new((Mutex::new(State {
+            scenario,
+            clients: HashMap::new(),
+            next_key: 0,
+            retained: HashMap::new(),
+            published: Vec::new(),
+            wills: Vec::new(),
+            subscribed: Vec::new(),
+            connects: 0,
+            first_connect: None,
+            log,
+        }), Condvar::new()));
+
+        // The broker's own publishes: commands, as if from Home Assistant
+        let broker = state.clone();
+        std::thread::spawn(move || {
+            let start = loop {
+                if let Some(start) = broker.0.lock().unwrap().first_connect {
+                    break start;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            };
+            for (secs, topic, payload) in after {
+                std::thread::sleep((start + Duration::from_secs(secs)).saturating_duration_since(Instant::now()));
+                broker.0.lock().unwrap().broker_publish(&topic, payload.as_bytes());
+            }
+        });
+
+        let server = state.clone();
+        let auth = Auth { username, password };
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (state, auth) = (server.clone(), auth.clone());
+                std::thread::spawn(move || {
+                    if let Err(e) = serve(stream, &state.0, &state.1, &auth) {
+                        eprintln!("Connection: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(MockBroker { addr, state })
+    }
+
+    /// Where it's listening
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Wait until clients have published `n` messages: wills included
+    pub fn wait_for_publishes(&self, n: usize) {
+        let mut state = self.state.0.lock().unwrap();
+        while state.published.len() < n {
+            state = self.state.1.wait(state).unwrap();
+        }
+    }
+
+    /// Publish to the clients subscribed: QoS 1, as if from Home Assistant
+    pub fn publish(&self, topic: &str, payload: &[u8]) {
+        self.state.0.lock().unwrap().broker_publish(topic, payload);
+    }
+
+    /// The retained message on a topic
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.0.lock().unwrap().retained.get(topic).cloned()
+    }
+
+    /// The last will published on a topic
+    pub fn will(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.0.lock().unwrap().wills.iter().rev().find(|(t, _)| t == topic).map(|(_, p)| p.clone())
+    }
+
+    /// Has anyone ever subscribed to this filter?
+    pub fn subscribed(&self, filter: &str) -> bool {
+        self.state.0.lock().unwrap().subscribed.iter().any(|f| f == filter)
+    }
+
+    /// Connections, accepted or not
+    pub fn connects(&self) -> usize {
+        self.state.0.lock().unwrap().connects
+    }
+
+    /// Check the scenario's expectations
+    pub fn check(&self) -> Vec<Checked> {
+        let state = self.state.0.lock().unwrap();
+        state.scenario.expectations.iter().map(|expect| match expect {
+            Expect::Publishes { filter, count } => Checked {
+                what: format!("publishes to {}", filter),
+                expected: count.to_string(),
+                actual: state.published.iter().filter(|t| matches(filter, t)).count().to_string(),
+            },
+            Expect::Retained { topic, payload } => Checked {
+                what: format!("retained on {}", topic),
+                expected: payload.clone(),
+                actual: state.retained.get(topic).map_or(String::from("(nothing)"), |p| String::from_utf8_lossy(p).into_owned()),
+            },
+            Expect::Will { topic, payload } => Checked {
+                what: format!("will on {}", topic),
+                expected: payload.clone(),
+                actual: state.wills.iter().rev().find(|(t, _)| t == topic).map_or(String::from("(not published)"), |(_, p)| String::from_utf8_lossy(p).into_owned()),
+            },
+            Expect::Subscribed { filter } => Checked {
+                what: format!("subscribed to {}", filter),
+                expected: String::from("yes"),
+                actual: String::from(if state.subscribed.contains(filter) { "yes" } else { "no" }),
+            },
+            Expect::Connects { count } => Checked {
+                what: String::from("connects"),
+                expected: count.to_string(),
+                actual: state.connects.to_string(),
+            },
+        }).collect()
+    }
+}
+
+impl Checked {
+    pub fn is_ok(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+#[derive(Clone)]
+struct Auth {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Auth {
+    fn allows(&self, connect: &Connect) -> bool {
+        (self.username.is_none() || self.username == connect.username)
+            && (self.password.is_none() || self.password == connect.password)
+    }
+}
+
+
+// Scenario: scripted faults, the broker's publishes, expectations
+#[derive(Default)]
+pub struct Scenario {
+    faults: Vec<Scripted>,  // in order: the first that fits is used up
+    after: Vec<(u64, String, String)>,  // seconds, topic, payload
+    expectations: Vec<Expect>,
+}
+
+struct Scripted {
+    packet: &'static str,
+    filter: Option<String>,  // PUBLISH: which topics
+    fault: Fault,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Fault {
+    Refuse(Option<u8>),  // CONNACK with an error: "not authorized", or this code
+    NoAck,               // PUBLISH: no PUBACK
+    Drop,                // close the connection, no response
+}
+
+#[derive(Clone)]
+pub enum Expect {
+    Publishes { filter: String, count: usize },
+    Retained { topic: String, payload: String },
+    Will { topic: String, payload: String },
+    Subscribed { filter: String },
+    Connects { count: usize },
+}
+
+impl Scenario {
+    /// Load a scenario file: see `scenarios/`
+    pub fn load(path: &str) -> Result<Scenario, String> {
+        Scenario::parse(&std::fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    /// Parse a scenario: one fault, publish or expectation per line
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut scenario = Scenario::default();
+        for (n, line) in text.lines().enumerate() {
+            // Comments: "#" on its own. Not the wildcard in "pokakus/#".
+            let comment = line.char_indices().find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)));
+            let line = line[..comment.map_or(line.len(), |(i, _)| i)].trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match words.as_slice() {
+                ["expect", "publish", filter, count] => count.parse().ok().map(|count| {
+                    scenario.expectations.push(Expect::Publishes { filter: filter.to_string(), count });
+                }),
+                ["expect", "retained", topic, payload @ ..] => {
+                    scenario.expectations.push(Expect::Retained { topic: topic.to_string(), payload: payload.join(" ") });
+                    Some(())
+                }
+                ["expect", "will", topic, payload @ ..] => {
+                    scenario.expectations.push(Expect::Will { topic: topic.to_string(), payload: payload.join(" ") });
+                    Some(())
+                }
+                ["expect", "subscribed", filter] => {
+                    scenario.expectations.push(Expect::Subscribed { filter: filter.to_string() });
+                    Some(())
+                }
+                ["expect", "connects", count] => count.parse().ok().map(|count| {
+                    scenario.expectations.push(Expect::Connects { count });
+                }),
+                ["after", secs, "publish", topic, payload @ ..] => secs.parse().ok().map(|secs| {
+                    scenario.after.push((secs, topic.to_string(), payload.join(" ")));
+                }),
+                ["CONNECT", "refuse", code @ ..] => match code {
+                    [] => Some(None),
+                    [code] => code.parse().ok().map(Some),
+                    _ => None,
+                }.map(|code| scenario.faults.push(Scripted { packet: "CONNECT", filter: None, fault: Fault::Refuse(code) })),
+                ["PUBLISH", filter, fault] => match *fault {
+                    "noack" => Some(Fault::NoAck),
+                    "drop" => Some(Fault::Drop),
+                    _ => None,
+                }.map(|fault| scenario.faults.push(Scripted { packet: "PUBLISH", filter: Some(filter.to_string()), fault })),
+                ["PINGREQ", "drop"] => {
+                    scenario.faults.push(Scripted { packet: "PINGREQ", filter: None, fault: Fault::Drop });
+                    Some(())
+                }
+                _ => None,
+            };
+            parsed.ok_or(format!("line {}: can't make sense of it: {}", n + 1, line))?;
+        }
+        scenario.after.sort_by_key(|(secs, ..)| *secs);
+        Ok(scenario)
+    }
+
+    // The next fault for this packet, if any: used up
+    fn fault(&mut self, packet: &str, topic: Option<&str>) -> Option<Fault> {
+        let index = self.faults.iter().position(|s| {
+            s.packet == packet && s.filter.as_deref().is_none_or(|f| topic.is_some_and(|t| matches(f, t)))
+        })?;
+        Some(self.faults.remove(index).fault)
+    }
+}
+
+
+// Broker state
+struct State {
+    scenario: Scenario,
+    clients: HashMap<u64, Client>,  // connected
+    next_key: u64,
+    retained: HashMap<String, Vec<u8>>,
+    published: Vec<String>,  // topics: publishes from clients, wills included
+    wills: Vec<(String, Vec<u8>)>,  // published
+    subscribed: Vec<String>,  // every filter, ever
+    connects: usize,
+    first_connect: Option<Instant>,
+    log: Option<File>,
+}
+
+struct Client {
+    id: String,
+    version: u8,
+    writer: TcpStream,
+    filters: Vec<(String, u8)>,  // and the granted QoS
+    next_packet_id: u16,
+}
+
+impl State {
+    // A message: to every client subscribed to it
+    fn route(&mut self, topic: &str, payload: &[u8], qos: u8) {
+        for client in self.clients.values_mut() {
+            let Some(granted) = client.filters.iter().filter(|(f, _)| matches(f, topic)).map(|(_, q)| *q).max() else {
+                continue;
+            };
+            client.next_packet_id = client.next_packet_id.wrapping_add(1).max(1);
+            let packet = publish(client.version, topic, payload, qos.min(granted), false, client.next_packet_id);
+            if let Err(e) = client.writer.write_all(&packet) {
+                eprintln!("{}: {}", client.id, e);
+            }
+        }
+    }
+
+    // From the broker itself: recorded, routed
+    fn broker_publish(&mut self, topic: &str, payload: &[u8]) {
+        self.record("broker", "PUBLISH", json!({ "topic": topic, "payload": String::from_utf8_lossy(payload) }), None);
+        self.route(topic, payload, 1);
+    }
+
+    // From a client: counted, retained, routed
+    fn publish(&mut self, topic: &str, payload: &[u8], qos: u8, retain: bool) {
+        self.published.push(topic.to_string());
+        if retain {
+            if payload.is_empty() {
+                self.retained.remove(topic);
+            } else {
+                self.retained.insert(topic.to_string(), payload.to_vec());
+            }
+        }
+        self.route(topic, payload, qos);
+    }
+
+    // Record a packet: stdout, and the log
+    fn record(&mut self, client: &str, packet: &str, details: Value, fault: Option<Fault>) {
+        let mut line = json!({
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            "client": client,
+            "packet": packet,
+            "scripted": fault.map(|f| format!("{:?}", f)),
+        });
+        if let (Value::Object(line), Value::Object(details)) = (&mut line, details) {
+            line.extend(details);
+        }
+        let line = line.to_string();
+        println!("{}", line);
+        if let Some(log) = self.log.as_mut() {
+            let _ = writeln!(log, "{}", line);
+        }
+    }
+}
+
+
+// Packet types: the high nibble of the first byte
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+const DISCONNECT: u8 = 14;
+
+// Serve a connection: one client, until it's gone
+fn serve(stream: TcpStream, state: &Mutex<State>, published: &Condvar, auth: &Auth) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let (header, body) = read_packet(&mut reader)?;
+    if header >> 4 != CONNECT {
+        return Err(invalid("expected CONNECT"));
+    }
+    let connect = Connect::parse(&body)?;
+    let v = connect.version;
+
+    let code = {
+        let mut state = state.lock().unwrap();
+        let fault = state.scenario.fault("CONNECT", None);
+        let code = match fault {
+            Some(Fault::Refuse(code)) => code.unwrap_or(if v == 5 { 0x87 } else { 5 }),  // not authorized
+            _ if !auth.allows(&connect) => if v == 5 { 0x86 } else { 4 },  // bad user name or password
+            _ => 0,
+        };
+        state.connects += 1;
+        let details = json!({
+            "version": if v == 5 { "5" } else { "3.1.1" },
+            "client_id": connect.client_id,
+            "keep_alive": connect.keep_alive,
+            "username": connect.username,
+            "will": connect.will.as_ref().map(|w| json!({ "topic": w.topic, "payload": String::from_utf8_lossy(&w.payload), "retain": w.retain })),
+            "connack": code,
+        });
+        state.record(&connect.client_id, "CONNECT", details, fault);
+        code
+    };
+    writer.write_all(&connack(v, code))?;
+    if code != 0 {
+        return Ok(());
+    }
+
+    let key = {
+        let mut state = state.lock().unwrap();
+        let key = state.next_key;
+        state.next_key += 1;
+        state.first_connect.get_or_insert_with(Instant::now);
+        state.clients.insert(key, Client { id: connect.client_id.clone(), version: v, writer: writer.try_clone()?, filters: Vec::new(), next_packet_id: 0 });
+        key
+    };
+    let mut will = connect.will;
+    let result = session(&mut reader, &mut writer, &connect.client_id, v, key, state, published, &mut will);
+
+    // Gone. Without a DISCONNECT: the will is published.
+    let mut state = state.lock().unwrap();
+    state.clients.remove(&key);
+    if let Some(will) = will {
+        let details = json!({ "topic": will.topic, "payload": String::from_utf8_lossy(&will.payload), "retain": will.retain });
+        state.record(&connect.client_id, "WILL", details, None);
+        state.wills.push((will.topic.clone(), will.payload.clone()));
+        state.publish(&will.topic, &will.payload, will.qos, will.retain);
+        published.notify_all();
+    }
+    match result {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),  // closed
+        result => result,
+    }
+}
+
+// After CONNACK: packets, until DISCONNECT or the connection's gone
+#[allow(clippy::too_many_arguments)]
+fn session(reader: &mut impl Read, writer: &mut TcpStream, client: &str, v: u8, key: u64, state: &Mutex<State>, published: &Condvar, will: &mut Option<Will>) -> std::io::Result<()> {
+    loop {
+        let (header, body) = read_packet(reader)?;
+        match header >> 4 {
+            PUBLISH => {
+                let mut b = Bytes(&body);
+                let topic = b.string()?;
+                let (qos, retain) = ((header >> 1) & 0x03, header & 0x01 != 0);
+                let packet_id = if qos > 0 { b.u16()? } else { 0 };
+                if v == 5 {
+                    b.skip_properties()?;
+                }
+                let payload = b.0.to_vec();
+
+                let mut s = state.lock().unwrap();
+                let fault = s.scenario.fault("PUBLISH", Some(&topic));
+                let details = json!({ "topic": topic, "payload": String::from_utf8_lossy(&payload), "qos": qos, "retain": retain, "packet_id": packet_id });
+                s.record(client, "PUBLISH", details, fault);
+                s.publish(&topic, &payload, qos, retain);
+                published.notify_all();
+                drop(s);
+
+                match fault {
+                    Some(Fault::Drop) => return Ok(()),
+                    Some(_) => (),
+                    None if qos > 0 => writer.write_all(&packet(PUBACK << 4, &packet_id.to_be_bytes()))?,
+                    None => (),
+                }
+            }
+            PUBACK => {
+                let packet_id = Bytes(&body).u16()?;
+                state.lock().unwrap().record(client, "PUBACK", json!({ "packet_id": packet_id }), None);
+            }
+            SUBSCRIBE => {
+                let mut b = Bytes(&body);
+                let packet_id = b.u16()?;
+                if v == 5 {
+                    b.skip_properties()?;
+                }
+                let mut filters = Vec::new();
+                while !b.0.is_empty() {
+                    let filter = b.string()?;
+                    let qos = b.u8()? & 0x03;
+                    filters.push((filter, qos.min(1)));
+                }
+
+                let mut s = state.lock().unwrap();
+                let details = json!({ "packet_id": packet_id, "filters": filters.iter().map(|(f, _)| f).collect::<Vec<_>>() });
+                s.record(client, "SUBSCRIBE", details, None);
+                s.subscribed.extend(filters.iter().map(|(f, _)| f.clone()));
+                let mut ack = packet_id.to_be_bytes().to_vec();
+                if v == 5 {
+                    ack.push(0);  // no properties
+                }
+                ack.extend(filters.iter().map(|(_, q)| *q));
+                writer.write_all(&packet(0x90, &ack))?;
+
+                // Retained messages: sent on subscribing
+                let retained: Vec<(String, Vec<u8>)> = s.retained.iter()
+                    .filter(|(t, _)| filters.iter().any(|(f, _)| matches(f, t)))
+                    .map(|(t, p)| (t.clone(), p.clone()))
+                    .collect();
+                let Some(c) = s.clients.get_mut(&key) else { return Ok(()) };
+                c.filters.extend(filters);
+                for (topic, payload) in retained {
+                    c.next_packet_id = c.next_packet_id.wrapping_add(1).max(1);
+                    writer.write_all(&publish(v, &topic, &payload, 1, true, c.next_packet_id))?;
+                }
+            }
+            PINGREQ => {
+                let mut s = state.lock().unwrap();
+                let fault = s.scenario.fault("PINGREQ", None);
+                s.record(client, "PINGREQ", json!({}), fault);
+                drop(s);
+                match fault {
+                    Some(_) => return Ok(()),
+                    None => writer.write_all(&packet(0xD0, &[]))?,  // PINGRESP
+                }
+            }
+            DISCONNECT => {
+                state.lock().unwrap().record(client, "DISCONNECT", json!({}), None);
+                *will = None;
+                return Ok(());
+            }
+            other => return Err(invalid(&format!("unexpected packet type {}", other))),
+        }
+    }
+}
+
+
+// CONNECT: what matters here
+struct Connect {
+    version: u8,  // 4: 3.1.1; 5
+    client_id: String,
+    keep_alive: u16,
+    username: Option<String>,
+    password: Option<String>,
+    will: Option<Will>,
+}
+
+struct Will {
+    topic: String,
+    payload: Vec<u8>,
+    qos: u8,
+    retain: bool,
+}
+
+impl Connect {
+    fn parse(body: &[u8]) -> std::io::Result<Connect> {
+        let mut b = Bytes(body);
+        if b.string()? != "MQTT" {
+            return Err(invalid("not MQTT"));
+        }
+        let version = b.u8()?;
+        if version != 4 && version != 5 {
+            return Err(invalid(&format!("protocol level {}: expected 4 or 5", version)));
+        }
+        let flags = b.u8()?;
+        let keep_alive = b.u16()?;
+        if version == 5 {
+            b.skip_properties()?;
+        }
+        let client_id = b.string()?;
+        let will = if flags & 0x04 != 0 {
+            if version == 5 {
+                b.skip_properties()?;
+            }
+            let topic = b.string()?;
+            let payload = b.binary()?.to_vec();
+            Some(Will { topic, payload, qos: (flags >> 3) & 0x03, retain: flags & 0x20 != 0 })
+        } else {
+            None
+        };
+        let username = if flags & 0x80 != 0 { Some(b.string()?) } else { None };
+        let password = if flags & 0x40 != 0 { Some(String::from_utf8_lossy(b.binary()?).into_owned()) } else { None };
+        Ok(Connect { version, client_id, keep_alive, username, password, will })
+    }
+}
+
+// Reading a packet body
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("packet too short"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> std::io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn binary(&mut self) -> std::io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        String::from_utf8(self.binary()?.to_vec()).map_err(|_| invalid("not UTF-8"))
+    }
+
+    // MQTT 5: whatever they are
+    fn skip_properties(&mut self) -> std::io::Result<()> {
+        let len = varint(|| self.u8())?;
+        self.take(len).map(|_| ())
+    }
+}
+
+// Remaining length, property length: 7 bits a byte, up to 4 bytes
+fn varint(mut next: impl FnMut() -> std::io::Result<u8>) -> std::io::Result<usize> {
+    let mut value = 0;
+    for i in 0..4 {
+        let byte = next()?;
+        value |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("malformed length"))
+}
+
+fn read_packet(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    let header = byte[0];
+    let len = varint(|| {
+        reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    })?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok((header, body))
+}
+
+fn packet(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = vec![first];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        p.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    p.extend_from_slice(body);
+    p
+}
+
+fn connack(v: u8, code: u8) -> Vec<u8> {
+    let mut body = vec![0, code];  // no session present
+    if v == 5 {
+        body.push(0);  // no properties
+    }
+    packet(0x20, &body)
+}
+
+fn publish(v: u8, topic: &str, payload: &[u8], qos: u8, retain: bool, packet_id: u16) -> Vec<u8> {
+    let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+    body.extend_from_slice(topic.as_bytes());
+    if qos > 0 {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    if v == 5 {
+        body.push(0);  // no properties
+    }
+    body.extend_from_slice(payload);
+    packet((PUBLISH << 4) | (qos << 1) | retain as u8, &body)
+}
+
+// Topic filters: "+" one level, "#" the rest
+fn matches(filter: &str, topic: &str) -> bool {
+    let (mut f, mut t) = (filter.split('/'), topic.split('/'));
+    loop {
+        match (f.next(), t.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => (),
+            (Some(a), Some(b)) if a == b => (),
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, what.to_string())
+}
@@ -0,0 +1,82 @@
+// Mock MQTT broker: the command line. See `lib.rs`.
+//   $ cargo run -- scenarios/ok.txt --publishes 12
+
+use std::fs::OpenOptions;
+
+use mock_mqtt_broker::{MockBroker, Scenario};
+
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: mock-mqtt-broker [scenario.txt] [--listen 0.0.0.0:1883] [--username NAME] [--password PASS] [--publishes N] [--log packets.jsonl]");
+            std::process::exit(2);
+        }
+    };
+    let scenario = match &args.scenario {
+        Some(path) => Scenario::load(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        }),
+        None => Scenario::default(),
+    };
+    let log = args.log.as_ref().map(|path| {
+        OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        })
+    });
+
+    let broker = MockBroker::start(&args.listen, scenario, args.username, args.password, log).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args.listen, e);
+        std::process::exit(2);
+    });
+    eprintln!("Mock MQTT broker: listening on mqtt://{}", broker.addr());
+
+    // Wait for `--publishes`. Without it: run until killed.
+    match args.publishes {
+        Some(n) => broker.wait_for_publishes(n),
+        None => loop {
+            std::thread::park();
+        },
+    }
+
+    let mut failed = false;
+    for checked in broker.check() {
+        failed |= !checked.is_ok();
+        eprintln!("{} {}: expected {}, got {}", if checked.is_ok() { "ok  " } else { "FAIL" }, checked.what, checked.expected, checked.actual);
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
+
+// Command line
+struct Args {
+    scenario: Option<String>,
+    listen: String,
+    username: Option<String>,  // only these credentials are accepted; any, if not given
+    password: Option<String>,
+    publishes: Option<usize>,
+    log: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args { scenario: None, listen: String::from("0.0.0.0:1883"), username: None, password: None, publishes: None, log: None };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{}: value expected", name));
+            match arg.as_str() {
+                "--listen" => out.listen = value("--listen")?,
+                "--username" => out.username = Some(value("--username")?),
+                "--password" => out.password = Some(value("--password")?),
+                "--log" => out.log = Some(value("--log")?),
+                "--publishes" => out.publishes = Some(value("--publishes")?.parse().map_err(|_| "--publishes: a number expected")?),
+                _ if arg.starts_with("--") => return Err(format!("{}: unknown option", arg)),
+                _ => out.scenario = Some(arg),
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,209 @@
+// Acknowledgements: "On it", "Done".
+//
+// Event messages come with inline keyboard buttons. When someone presses one,
+// Telegram sends us a `callback_query`: we edit the message to show who and when,
+// and the LED tells the person at the device that help is coming.
+//
+// To edit a message, we need its original text with markup:
+// the callback only has plain text. So sent messages are remembered: the last few.
+
+use defmt;
+use core::cell::RefCell;
+use core::fmt::Write;
+use alloc::string::String;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use serde::Serialize;
+
+use crate::text::{Escape, ParseMode};
+
+
+// Remember this many sent messages
+const MAX_TRACKED: usize = 8;
+
+// Acknowledgements per message
+const MAX_ACKS: usize = 4;
+
+
+/// Acknowledgement: which button has been pressed
+#[derive(defmt::Format, Clone, Copy, PartialEq, Debug)]
+pub enum AckKind {
+    OnIt,
+    Done,
+}
+
+impl AckKind {
+    /// Button label
+    pub const fn label(self) -> &'static str {
+        match self {
+            AckKind::OnIt => "👀 On it",
+            AckKind::Done => "✅ Done",
+        }
+    }
+
+    /// Callback data: what Telegram sends back when the button's pressed
+    pub const fn callback_data(self) -> &'static str {
+        match self {
+            AckKind::OnIt => "ack:on_it",
+            AckKind::Done => "ack:done",
+        }
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<AckKind> {
+        match data {
+            "ack:on_it" => Some(AckKind::OnIt),
+            "ack:done" => Some(AckKind::Done),
+            _ => None,
+        }
+    }
+}
+
+
+/// Inline keyboard: buttons under the message
+#[derive(Serialize, defmt::Format, Clone, Copy)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: &'static [&'static [InlineKeyboardButton]],
+}
+
+#[derive(Serialize, defmt::Format, Clone, Copy)]
+pub struct InlineKeyboardButton {
+    pub text: &'static str,
+    pub callback_data: &'static str,
+}
+
+const ON_IT_BUTTON: InlineKeyboardButton = InlineKeyboardButton { text: AckKind::OnIt.label(), callback_data: AckKind::OnIt.callback_data() };
+const DONE_BUTTON: InlineKeyboardButton = InlineKeyboardButton { text: AckKind::Done.label(), callback_data: AckKind::Done.callback_data() };
+
+/// Keyboard: for a new message
+pub const ACK_KEYBOARD: InlineKeyboardMarkup = InlineKeyboardMarkup { inline_keyboard: &[&[ON_IT_BUTTON, DONE_BUTTON]] };
+
+// Keyboard: someone's on it; it's not done yet
+const DONE_KEYBOARD: InlineKeyboardMarkup = InlineKeyboardMarkup { inline_keyboard: &[&[DONE_BUTTON]] };
+
+
+/// Remember a sent message: to edit it when acknowledged.
+/// `group`: the messages about the same event; acknowledging one acknowledges them all.
+/// `chat_id`: numeric, as in the sendMessage result. Callbacks only have that one: a @channel's too.
+pub fn record_sent(group: u32, chat_id: i64, message_id: i64, text: String, parse_mode: Option<ParseMode>) {
+    TRACKED.lock(|t| {
+        let mut tracked = t.borrow_mut();
+        if tracked.is_full() {
+            tracked.pop_front();  // the oldest
+        }
+        let _ = tracked.push_back(Tracked { group, chat_id, message_id, text, parse_mode, acks: heapless::Vec::new() });
+    });
+}
+
+/// An acknowledgement: the message, updated
+pub struct Acknowledged {
+    pub text: String,
+    pub parse_mode: Option<ParseMode>,
+    pub keyboard: Option<InlineKeyboardMarkup>,  // the buttons left: none when done
+}
+
+/// Record an acknowledgement, and render the updated message.
+/// `original`: the message text from the callback, in case we don't remember the message (e.g. after a reboot).
+/// `None`: nothing to edit.
+pub fn acknowledge(chat_id: i64, message_id: i64, kind: AckKind, by: &str, original: Option<&str>) -> Option<Acknowledged> {
+    let ack = Ack {
+        kind,
+        by: truncated(by),
+        unix_time: crate::clock::now_unix(),
+    };
+
+    TRACKED.lock(|t| {
+        let mut tracked = t.borrow_mut();
+        let found = tracked.iter_mut().find(|m| m.chat_id == chat_id && m.message_id == message_id);
+        match found {
+            Some(m) => {
+                if m.acks.is_full() {
+                    m.acks.remove(0);
+                }
+                let _ = m.acks.push(ack);
+                mark_acknowledged(m.group);
+                Some(render(&m.text, m.parse_mode, &m.acks))
+            }
+            // Don't remember it: plain text it is
+            None => original.map(|text| render(text, None, &[ack])),
+        }
+    })
+}
+
+
+/// Has anyone acknowledged any message in the group?
+pub fn is_acknowledged(group: u32) -> bool {
+    ACKNOWLEDGED.lock(|a| a.borrow().iter().any(|g| *g == group))
+}
+
+// Acknowledged groups: the last few
+static ACKNOWLEDGED: Mutex<CriticalSectionRawMutex, RefCell<heapless::Deque<u32, MAX_TRACKED>>> = Mutex::new(RefCell::new(heapless::Deque::new()));
+
+fn mark_acknowledged(group: u32) {
+    ACKNOWLEDGED.lock(|a| {
+        let mut acked = a.borrow_mut();
+        if acked.iter().any(|g| *g == group) {
+            return;
+        }
+        if acked.is_full() {
+            acked.pop_front();
+        }
+        let _ = acked.push_back(group);
+    });
+}
+
+
+// A sent message
+struct Tracked {
+    group: u32,
+    chat_id: i64,
+    message_id: i64,
+    text: String,  // as sent: with markup
+    parse_mode: Option<ParseMode>,
+    acks: heapless::Vec<Ack, MAX_ACKS>,
+}
+
+#[derive(Clone)]
+struct Ack {
+    kind: AckKind,
+    by: heapless::String<64>,  // user's first name
+    unix_time: Option<u64>,
+}
+
+static TRACKED: Mutex<CriticalSectionRawMutex, RefCell<heapless::Deque<Tracked, MAX_TRACKED>>> = Mutex::new(RefCell::new(heapless::Deque::new()));
+
+// Render: the original text, then one line per acknowledgement:
+//   👀 On it: Alice, 10:05
+fn render(text: &str, parse_mode: Option<ParseMode>, acks: &[Ack]) -> Acknowledged {
+    let mut out = String::from(text);
+    out.push('\n');
+    for ack in acks {
+        out.push('\n');
+        // Names are user input: escape
+        let mut line = Escape { inner: &mut out, mode: parse_mode };
+        let _ = write!(line, "{}: {}", ack.kind.label(), ack.by);  // String never fails
+        if let Some(unix) = ack.unix_time {
+            let _ = line.write_str(", ");
+            let _ = crate::template::write_time(&mut line, unix);
+        }
+    }
+
+    let done = acks.iter().any(|a| a.kind == AckKind::Done);
+    Acknowledged {
+        text: out,
+        parse_mode,
+        keyboard: if done { None } else { Some(DONE_KEYBOARD) },
+    }
+}
+
+// Cut a name to fit
+fn truncated(name: &str) -> heapless::String<64> {
+    let mut out = heapless::String::new();
+    for c in name.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
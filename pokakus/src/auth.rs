@@ -0,0 +1,197 @@
+// Who may control the device: an allowlist.
+//
+// Anyone can find the bot and send it commands. Only these are listened to:
+// - users:  TELEGRAM_ALLOWED_USERS="691814383:config, 12345"
+//           a user id, optionally with a permission level (default: read)
+// - chats:  TELEGRAM_ALLOWED_CHATS="-1001234567890"
+//           anyone in these chats: read only
+// Empty lists: nobody.
+//
+// Applies to commands and to button presses.
+// Rejected attempts are logged, answered at most once a minute,
+// and reported to TELEGRAM_ADMIN_CHAT (if set) at most once a minute.
+
+use defmt;
+use core::cell::Cell;
+use core::fmt::Write;
+use alloc::string::String;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant};
+
+use crate::config;
+use crate::recipient::Recipient;
+
+
+/// How many users/chats the allowlist holds
+pub const MAX_ALLOWED: usize = 16;
+
+// Allowlists
+const ALLOWED_USERS: Allowlist = Allowlist::parse(option_env!("TELEGRAM_ALLOWED_USERS"), Permission::Read);
+const ALLOWED_CHATS: Allowlist = Allowlist::parse(option_env!("TELEGRAM_ALLOWED_CHATS"), Permission::Read);
+
+// Report rejected attempts here
+const ADMIN_CHAT: Option<Recipient> = match option_env!("TELEGRAM_ADMIN_CHAT") {
+    Some(v) if !v.is_empty() => Some(Recipient::parse(v)),
+    _ => None,
+};
+
+// Rejections: reply/report at most this often
+const REJECT_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// Permission level. Ordered: a higher level includes the lower ones.
+#[derive(defmt::Format, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Permission {
+    Read,    // status, counters
+    Config,  // change settings
+}
+
+impl Permission {
+    const fn parse(v: &[u8]) -> Permission {
+        if config::eq(v, b"read") {
+            Permission::Read
+        } else if config::eq(v, b"config") {
+            Permission::Config
+        } else {
+            panic!("Allowlist: permission level: expected 'read' or 'config'")
+        }
+    }
+}
+
+
+/// What may this user do in this chat? `None`: nothing.
+/// The user's own level wins; an allowed chat gives read-only.
+pub fn authorize(user_id: Option<i64>, chat_id: i64) -> Option<Permission> {
+    let user = user_id.and_then(|id| ALLOWED_USERS.get(id));
+    let chat = ALLOWED_CHATS.get(chat_id).map(|_| Permission::Read);
+    user.max(chat)
+}
+
+/// Log a rejected attempt; tell the user and the admin, but not too often.
+/// `what`: e.g. "/status".
+/// `reply`: say "Not allowed" in the chat. Button presses are answered otherwise.
+pub fn reject(user_id: Option<i64>, chat: Recipient, what: &str, reply: bool) {
+    defmt::warn!("Auth: rejected {} from user {:?} in chat {}", what, user_id, chat.chat_id);
+
+    let now = Instant::now();
+    let (reply, report, suppressed) = REJECTS.lock(|r| {
+        let mut state = r.get();
+        state.total += 1;
+        let due = |last: Option<Instant>| last.is_none_or(|last| now - last >= REJECT_INTERVAL);
+
+        let reply = reply && due(state.last_reply);
+        if reply {
+            state.last_reply = Some(now);
+        }
+        let report = ADMIN_CHAT.is_some() && due(state.last_report);
+        let suppressed = state.suppressed;
+        if report {
+            state.last_report = Some(now);
+            state.suppressed = 0;
+        } else {
+            state.suppressed += 1;
+        }
+        r.set(state);
+        (reply, report, suppressed)
+    });
+
+    if reply {
+        crate::notifier::send_reply(chat, String::from("Not allowed"));
+    }
+    if let (true, Some(admin)) = (report, ADMIN_CHAT) {
+        let mut text = String::new();
+        let _ = write!(text, "Rejected {} from user ", what);  // String never fails
+        let _ = match user_id {
+            Some(id) => write!(text, "{}", id),
+            None => write!(text, "?"),
+        };
+        let _ = write!(text, " in chat {}", chat.chat_id);
+        if suppressed > 0 {
+            let _ = write!(text, " (+{} more since the last report)", suppressed);
+        }
+        crate::notifier::send_reply(admin, text);
+    }
+}
+
+/// Rejections since boot: for the status
+pub fn rejected_count() -> u32 {
+    REJECTS.lock(|r| r.get().total)
+}
+
+
+
+// Rejections: rate limiting
+#[derive(Clone, Copy)]
+struct RejectState {
+    last_reply: Option<Instant>,
+    last_report: Option<Instant>,
+    suppressed: u32,  // not reported yet
+    total: u32,
+}
+
+static REJECTS: Mutex<CriticalSectionRawMutex, Cell<RejectState>> = Mutex::new(Cell::new(RejectState {
+    last_reply: None,
+    last_report: None,
+    suppressed: 0,
+    total: 0,
+}));
+
+
+// Allowlist: ids with their permission levels
+struct Allowlist {
+    list: [Option<(i64, Permission)>; MAX_ALLOWED],
+}
+
+impl Allowlist {
+    // Parse a comma-separated list: "id" or "id:level"
+    const fn parse(value: Option<&'static str>, default: Permission) -> Allowlist {
+        let mut list = [None; MAX_ALLOWED];
+        let mut n = 0;
+        let mut rest = match value {
+            Some(v) => v.as_bytes(),
+            None => b"",
+        };
+
+        while !rest.is_empty() {
+            // Next item
+            let mut end = 0;
+            while end < rest.len() && rest[end] != b',' {
+                end += 1;
+            }
+            let (item, tail) = rest.split_at(end);
+            rest = match tail.split_first() {
+                Some((_, tail)) => tail,
+                None => tail,
+            };
+
+            let item = config::trim(item);
+            if item.is_empty() {
+                continue;
+            }
+            if n == MAX_ALLOWED {
+                panic!("Allowlist: too many entries");
+            }
+
+            // "id:level"
+            let mut colon = 0;
+            while colon < item.len() && item[colon] != b':' {
+                colon += 1;
+            }
+            let (id, level) = item.split_at(colon);
+            let level = match level.split_first() {
+                Some((_, level)) => Permission::parse(config::trim(level)),
+                None => default,
+            };
+            list[n] = Some((config::parse_i64(config::trim(id)), level));
+            n += 1;
+        }
+        Allowlist { list }
+    }
+
+    fn get(&self, id: i64) -> Option<Permission> {
+        self.list.iter().flatten().find(|(i, _)| *i == id).map(|(_, p)| *p)
+    }
+}
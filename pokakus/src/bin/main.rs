@@ -6,7 +6,6 @@ extern crate alloc;
 use {esp_backtrace as _, esp_println as _};
 esp_bootloader_esp_idf::esp_app_desc!();
 
-use defmt;
 use esp_hal::{
     clock::CpuClock,
     timer::timg::TimerGroup,
@@ -20,16 +19,26 @@ use embassy_time::{
 };
 
 
-// The message to send
+// The message to send: a template, see `pokakus::template`
 const MESSAGE_CONTENT: &str = env!("TELEGRAM_MESSAGE");
+const _: () = pokakus::template::check_message(MESSAGE_CONTENT);
 
 
 #[allow(clippy::large_stack_frames)]
 #[esp_rtos::main]
 async fn main(spawner: Spawner) -> ! {
-    // Init allocator: 64K in reclaimed memory + 66K in default RAM
+    // Init allocator: 64K in reclaimed memory + 144K in default RAM.
+    // The worst case, everything at once:
+    //   WiFi: esp-radio's buffers                                   ~64K
+    //   TLS: 2 sessions at most, see `http`                          65K
+    //   getUpdates: the response                                     32K
+    //   sendMessage: the body and the response, a 256-byte message   13K
+    //   MQTT: TLS with 4K records, and the receive buffer, for good  10K
+    //   the rest: getMe, callback answers, outbox payloads           ~8K
+    //                                                               192K
+    // Out of heap is a reset: keep a margin.
     esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 64 * 1024);
-    esp_alloc::heap_allocator!(size: 72 * 1024);
+    esp_alloc::heap_allocator!(size: 144 * 1024);
 
     // CPU Clock: WiFi in ESP32 requires a fast CPU
     let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
@@ -45,6 +54,11 @@ async fn main(spawner: Spawner) -> ! {
     // Init GPIO: LED
     let led = gpio::Output::new(peripherals.GPIO8, gpio::Level::High, gpio::OutputConfig::default());
 
+    // Init storage: flash. Without it, nothing's persisted, but everything works.
+    if let Err(e) = pokakus::storage::init(peripherals.FLASH).await {
+        defmt::error!("Init storage: {:?}", e);
+    }
+
     // Init WiFi & network stack
     let stack = defmt::expect!(
         pokakus::wifi::start_wifi(&spawner, peripherals.WIFI).await,
@@ -54,7 +68,16 @@ async fn main(spawner: Spawner) -> ! {
     // Spawn some tasks
     spawner.must_spawn(pokakus::button::task_button_clicks(button));
     spawner.must_spawn(pokakus::led::led_task(led));
-    spawner.must_spawn(pokakus::telegram::task_telegram_sender(stack));
+    spawner.must_spawn(pokakus::outbox::task_outbox(stack));
+    spawner.must_spawn(pokakus::notifier::task_sender(stack));
+    #[cfg(feature = "telegram")]
+    spawner.must_spawn(pokakus::telegram_bot::task_telegram_updates(stack));
+    #[cfg(feature = "telegram")]
+    spawner.must_spawn(pokakus::bot_identity::task_validate_token(stack));
+    #[cfg(feature = "mqtt")]
+    spawner.must_spawn(pokakus::mqtt::task_mqtt(stack));
+    spawner.must_spawn(pokakus::diagnostics::task_diagnostics(stack));
+    spawner.must_spawn(pokakus::sntp::task_sntp(stack));
     spawner.must_spawn(task_main());
 
     loop {
@@ -64,11 +87,22 @@ async fn main(spawner: Spawner) -> ! {
 
 // Task: main logic
 // - Read button clicks
-// - Send them as Telegram messages
+// - Send them as messages: see `notifier`
+// - Double click: undo the last message
+// - Long press: send network diagnostics
+// - Every gesture: MQTT, for Home Assistant's device triggers
 #[embassy_executor::task()]
 pub async fn task_main() {
+    use pokakus::button::Gesture;
+    use pokakus::event::Event;
     loop {
-        pokakus::button::wait_for_button_click().await;
-        pokakus::telegram::send_telegram_message(MESSAGE_CONTENT);
+        let gesture = pokakus::button::wait_for_gesture().await;
+        #[cfg(feature = "mqtt")]
+        pokakus::mqtt::trigger(gesture);
+        match gesture {
+            Gesture::Click => pokakus::notifier::send_event(MESSAGE_CONTENT, Event::record(Gesture::Click)),
+            Gesture::DoubleClick => pokakus::notifier::undo_last(),
+            Gesture::LongPress => pokakus::diagnostics::request_diagnostics(pokakus::diagnostics::ReportTo::Message),
+        }
     }
 }
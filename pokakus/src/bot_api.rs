@@ -0,0 +1,402 @@
+// Telegram Bot API: the protocol. Requests, responses, errors.
+// See https://core.telegram.org/bots/api
+//
+// Calls go through a `Transport`: HTTPS to Telegram on the device, see `telegram`.
+// In tests, plain HTTP to the mock Bot API: see `host-tests/`.
+
+use defmt;
+use core::cell::RefCell;
+use alloc::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::Duration;
+
+use crate::ack::{self, InlineKeyboardMarkup};
+use crate::recipient::{ChatId, Recipient, MAX_RECIPIENTS};
+use crate::retry::Retryable;
+use crate::text::ParseMode;
+
+
+// Reconciliation: look for the message this many ids after the last one we know of
+const RECONCILE_PROBES: i64 = 3;
+
+// Reconciliation: remember the last message in this many chats. Recipients, the escalation chat, and a few replies.
+const MAX_TRACKED_CHATS: usize = MAX_RECIPIENTS + 4;
+
+
+/// How calls get to the Bot API
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    /// POST a JSON body to a method. Returns the HTTP status, and the response body: read into `rx_buf`.
+    async fn post<'b>(&self, method: &str, body: &[u8], rx_buf: &'b mut [u8]) -> Result<(u16, &'b [u8]), TelegramSendMessageError>;
+}
+
+
+/// Send a message
+pub async fn send_message<A: Transport>(api: &A, send_to: Recipient, message: &str, options: &MessageOptions) -> Result<SentMessage, TelegramSendMessageError> {
+    let msg = TelegramMessageInput {
+        chat_id: send_to.chat_id,
+        message_thread_id: send_to.message_thread_id,
+        text: message,
+        parse_mode: options.parse_mode,
+        disable_notification: options.disable_notification,
+        protect_content: options.protect_content,
+        link_preview_options: options.disable_link_preview.then_some(LinkPreviewOptions { is_disabled: true }),
+        reply_markup: options.ack_buttons.then_some(ack::ACK_KEYBOARD),
+    };
+
+    // Body: JSON-escaped text + the rest of the fields.
+    // The response echoes the message back: with non-ASCII as "\uXXXX", 6 bytes per UTF-16 unit.
+    // Twice: see `api_call()`.
+    let body_size = crate::text::json_escaped_len(message) + 256;
+    let mut buf = alloc::vec![0u8; 2 * (4096 + 6 * crate::text::utf16_len(message))];
+    let sent: SentMessage = match api_call(api, "sendMessage", &msg, body_size, &mut buf).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            // It may be there: we don't know the last message anymore
+            if e.maybe_delivered() {
+                forget_message_id(send_to.chat_id);
+            }
+            return Err(e);
+        }
+    };
+    defmt::debug!("Telegram: message_id={}", sent.message_id);
+    record_message_id(send_to.chat_id, sent.message_id);
+
+    // Ok
+    Ok(sent)
+}
+
+/// Edit a message's text. Without a keyboard, the buttons are removed.
+pub async fn edit_message_text<A: Transport>(api: &A, chat_id: ChatId, message_id: i64, text: &str, parse_mode: Option<ParseMode>, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), TelegramSendMessageError> {
+    let params = EditMessageTextInput { chat_id, message_id, text, parse_mode, reply_markup };
+    let body_size = crate::text::json_escaped_len(text) + 512;
+    let mut buf = alloc::vec![0u8; 2 * (4096 + 6 * crate::text::utf16_len(text))];  // twice: see `api_call()`
+    let _: serde::de::IgnoredAny = api_call(api, "editMessageText", &params, body_size, &mut buf).await?;
+    Ok(())
+}
+
+/// Delete a message. Bots can only delete messages up to 48 hours old.
+pub async fn delete_message<A: Transport>(api: &A, chat_id: ChatId, message_id: i64) -> Result<(), TelegramSendMessageError> {
+    let params = DeleteMessageInput { chat_id, message_id };
+    let mut buf = alloc::vec![0u8; 2 * 1024];  // twice: see `api_call()`
+    let _: bool = api_call(api, "deleteMessage", &params, 256, &mut buf).await?;
+    Ok(())
+}
+
+/// Answer a button press: the user's client stops the spinner, shows the text
+pub async fn answer_callback_query<A: Transport>(api: &A, callback_query_id: &str, text: Option<&str>) -> Result<(), TelegramSendMessageError> {
+    let params = AnswerCallbackQueryInput { callback_query_id, text };
+    let mut buf = alloc::vec![0u8; 2 * 1024];  // twice: see `api_call()`
+    let _: bool = api_call(api, "answerCallbackQuery", &params, 512, &mut buf).await?;
+    Ok(())
+}
+
+
+/// Call a Bot API method: POST `params` as JSON, parse the result.
+/// `body_size`: how much room the JSON body needs.
+/// `buf`: for the response; the result may borrow strings from it.
+pub async fn api_call<'b, A, P, T>(
+    api: &A,
+    method: &str,
+    params: &P,
+    body_size: usize,
+    buf: &'b mut [u8],
+) -> Result<T, TelegramSendMessageError>
+where
+    A: Transport,
+    P: Serialize,
+    T: Deserialize<'b>,
+{
+    // Data
+    let mut body_buf = alloc::vec![0u8; body_size];
+    let body_len = serde_json_core::to_slice(params, &mut body_buf)?;
+
+    // Request.
+    // Half of the buffer for the response, half to unescape strings into.
+    let (rx_buf, unescape_buf) = buf.split_at_mut(buf.len() / 2);
+    let (status, body) = api.post(method, &body_buf[..body_len], rx_buf).await?;
+    parse_api_response(status, body, unescape_buf)
+}
+
+
+/// Send a message. Failed, but may have got through: look for it after `since`, see `reconcile()`.
+/// One attempt: the caller retries.
+pub async fn send_or_reconcile<A: Transport>(api: &A, recipient: Recipient, message: &str, options: &MessageOptions, since: Option<i64>) -> Result<SentMessage, TelegramSendMessageError> {
+    match (send_message(api, recipient, message, options).await, since) {
+        (Err(e), Some(since)) if e.maybe_delivered() => match reconcile(api, recipient, message, options, since).await {
+            Some(message_id) => {
+                let chat = match recipient.chat_id {
+                    ChatId::Id(id) => Some(Chat { id }),
+                    ChatId::Username(_) => None,  // a @channel: its numeric id's unknown
+                };
+                Ok(SentMessage { message_id, chat })
+            }
+            None => Err(e),
+        },
+        (result, _) => result,
+    }
+}
+
+/// Reconcile: the send has failed, but the message may have got through all the same. Find it.
+/// Returns its id.
+///
+/// The Bot API can't read chat history. It can edit, and an edit into exactly the text a message has
+/// is refused: "message is not modified". That changes nothing, and it means the message is ours.
+/// So we only look where any message of ours is this one:
+/// `since` is the last message we knew of when we first tried to send it, and nothing else has been sent since.
+/// (See `last_message_id()`: a message that may have got through makes it unknown.)
+/// Other people's messages can't be edited. An edit that goes through all the same: we were wrong, stop.
+pub async fn reconcile<A: Transport>(api: &A, recipient: Recipient, message: &str, options: &MessageOptions, since: i64) -> Option<i64> {
+    let keyboard = options.ack_buttons.then_some(ack::ACK_KEYBOARD);
+    for message_id in since + 1..=since + RECONCILE_PROBES {
+        match edit_message_text(api, recipient.chat_id, message_id, message, options.parse_mode, keyboard).await {
+            Err(TelegramSendMessageError::MessageNotModified) => {
+                defmt::info!("Telegram: message {} in {} has got through after all", message_id, recipient.chat_id);
+                record_message_id(recipient.chat_id, message_id);
+                return Some(message_id);
+            }
+            Ok(()) => {
+                defmt::error!("Telegram: message {} in {} was another of ours, and has been edited", message_id, recipient.chat_id);
+                return None;
+            }
+            Err(_) => continue,  // not there, not ours, or no answer
+        }
+    }
+    None
+}
+
+// The last message we've sent to each chat: see `reconcile()`
+static LAST_MESSAGE_IDS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<(ChatId, i64), MAX_TRACKED_CHATS>>> = Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// The last message we've sent to a chat. `None`: we don't know,
+/// or one may have got through after it, see `send_message()`.
+pub fn last_message_id(chat_id: ChatId) -> Option<i64> {
+    LAST_MESSAGE_IDS.lock(|l| l.borrow().iter().find(|(chat, _)| *chat == chat_id).map(|&(_, id)| id))
+}
+
+fn forget_message_id(chat_id: ChatId) {
+    LAST_MESSAGE_IDS.lock(|l| l.borrow_mut().retain(|(chat, _)| *chat != chat_id));
+}
+
+fn record_message_id(chat_id: ChatId, message_id: i64) {
+    LAST_MESSAGE_IDS.lock(|l| {
+        let mut last = l.borrow_mut();
+        match last.iter_mut().find(|(chat, _)| *chat == chat_id) {
+            Some(entry) => entry.1 = entry.1.max(message_id),
+            None => {
+                if last.is_full() {
+                    last.remove(0);  // the oldest
+                }
+                let _ = last.push((chat_id, message_id));
+            }
+        }
+    });
+}
+
+/// Parse a Bot API response: the envelope, and the `result` on success.
+/// Strings with escapes (e.g. non-ASCII: "\u043f") are unescaped into `unescape_buf`.
+/// Errors: `ApiError` with the parsed error code.
+pub fn parse_api_response<'a, T: Deserialize<'a>>(http_status: u16, body: &'a [u8], unescape_buf: &'a mut [u8]) -> Result<T, TelegramSendMessageError> {
+    let text = core::str::from_utf8(body)
+        .map_err(|_| TelegramSendMessageError::ResponseError)?;
+    let resp: TelegramResponse<'a, T> = match serde_json_core::from_slice_escaped(body, unescape_buf) {
+        Ok((resp, _)) => resp,
+        Err(e) => {
+            defmt::error!("Telegram: HTTP {}: unparseable response: {:?}: {}", http_status, e, text);
+            // Not JSON? Keep the HTTP status: the error is still classified.
+            return Err(match http_status {
+                200..=299 => TelegramSendMessageError::ResponseError,
+                status => TelegramSendMessageError::ApiError { error_code: status, retry_after: None, migrate_to_chat_id: None },
+            });
+        }
+    };
+
+    match resp {
+        TelegramResponse { ok: true, result: Some(result), .. } => Ok(result),
+        TelegramResponse { ok: true, result: None, .. } => {
+            defmt::error!("Telegram: no result: {}", text);
+            Err(TelegramSendMessageError::ResponseError)
+        }
+        TelegramResponse { ok: false, error_code, description, parameters, .. } => {
+            defmt::error!("Telegram failed: {} {}", error_code, description);
+            if description.is_some_and(|d| d.contains("message is not modified")) {
+                return Err(TelegramSendMessageError::MessageNotModified);
+            }
+            let parameters = parameters.unwrap_or_default();
+            Err(TelegramSendMessageError::ApiError {
+                error_code: error_code.unwrap_or(http_status),
+                retry_after: parameters.retry_after,
+                migrate_to_chat_id: parameters.migrate_to_chat_id,
+            })
+        }
+    }
+}
+
+
+// Error handling: only return as much info as the caller needs to have.
+// Everything else: log, don't return.
+// "Log generously, return sparingly."
+#[derive(Debug, defmt::Format)]
+pub enum TelegramSendMessageError {
+    InvalidArguments,
+    ConnectError,  // DNS, TCP, TLS: the request hasn't been sent
+    RequestError,  // sending, or waiting for the response: it may have got through
+    CertificateRejected,  // not Telegram's certificate: someone's intercepting!
+    ApiError {  // Telegram said no
+        error_code: u16,  // HTTP-like: 400, 401, 403, 429, ...
+        retry_after: Option<u32>,  // flood control: seconds
+        migrate_to_chat_id: Option<i64>,  // the group has become a supergroup: use this id
+    },
+    MessageNotModified,  // edit: the message already has this text and buttons
+    ResponseError,  // see logs
+}
+
+impl TelegramSendMessageError {
+    /// Telegram's error code, if Telegram has responded
+    pub fn error_code(&self) -> Option<u16> {
+        match self {
+            TelegramSendMessageError::ApiError { error_code, .. } => Some(*error_code),
+            TelegramSendMessageError::MessageNotModified => Some(400),
+            _ => None,
+        }
+    }
+
+    /// The chat has moved: send to this chat id instead
+    pub fn migrate_to_chat_id(&self) -> Option<i64> {
+        match self {
+            TelegramSendMessageError::ApiError { migrate_to_chat_id, .. } => *migrate_to_chat_id,
+            _ => None,
+        }
+    }
+}
+
+// Permanent: bad request, bad token, bot blocked. Retrying won't help.
+// Transient: network, 5xx, flood control.
+impl Retryable for TelegramSendMessageError {
+    fn is_permanent(&self) -> bool {
+        match self {
+            TelegramSendMessageError::InvalidArguments => true,
+            TelegramSendMessageError::CertificateRejected => true,
+            TelegramSendMessageError::ApiError { error_code, .. } => matches!(error_code, 400 | 401 | 403 | 404),
+            TelegramSendMessageError::MessageNotModified => true,
+            TelegramSendMessageError::ConnectError => false,
+            TelegramSendMessageError::RequestError => false,
+            TelegramSendMessageError::ResponseError => false,
+        }
+    }
+
+    // Telegram may have got it: the response was lost, or it made no sense
+    fn maybe_delivered(&self) -> bool {
+        matches!(self, TelegramSendMessageError::RequestError | TelegramSendMessageError::ResponseError)
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TelegramSendMessageError::ApiError { retry_after: Some(secs), .. } => Some(Duration::from_secs(*secs as u64)),
+            _ => None,
+        }
+    }
+}
+
+// Auto-convert with From impls
+impl From<serde_json_core::ser::Error> for TelegramSendMessageError {
+    fn from(_: serde_json_core::ser::Error) -> Self {
+        TelegramSendMessageError::InvalidArguments
+    }
+}
+
+
+/// Bot API response envelope.
+/// Every method returns one; `T` is the method's result.
+#[derive(Deserialize, defmt::Format)]
+pub struct TelegramResponse<'a, T> {
+    pub ok: bool,
+    pub error_code: Option<u16>,
+    #[serde(borrow)]
+    pub description: Option<Cow<'a, str>>,  // owned if it has escapes: quotes, non-ASCII
+    pub parameters: Option<TelegramResponseParameters>,
+    pub result: Option<T>,
+}
+
+/// Why the request has failed, and what to do about it
+#[derive(Deserialize, defmt::Format, Default)]
+pub struct TelegramResponseParameters {
+    pub retry_after: Option<u32>,
+    pub migrate_to_chat_id: Option<i64>,
+}
+
+/// sendMessage result: the message.
+/// We only need its ids: to edit it later.
+#[derive(Deserialize, defmt::Format)]
+pub struct SentMessage {
+    pub message_id: i64,
+    pub chat: Option<Chat>,  // always there; `None` when the message's been found by reconciling, see `telegram`
+}
+
+/// A chat: by its numeric id. A @channel's too.
+#[derive(Deserialize, defmt::Format, Clone, Copy)]
+pub struct Chat {
+    pub id: i64,
+}
+
+
+#[derive(Serialize, defmt::Format)]
+struct TelegramMessageInput<'a> {
+    chat_id: ChatId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_thread_id: Option<i64>,  // forum topic
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    disable_notification: bool,
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    protect_content: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link_preview_options: Option<LinkPreviewOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,  // buttons
+}
+
+#[derive(Serialize, defmt::Format)]
+struct EditMessageTextInput<'a> {
+    chat_id: ChatId,
+    message_id: i64,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize, defmt::Format)]
+struct DeleteMessageInput {
+    chat_id: ChatId,
+    message_id: i64,
+}
+
+#[derive(Serialize, defmt::Format)]
+struct AnswerCallbackQueryInput<'a> {
+    callback_query_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,  // a notification at the top of the chat screen
+}
+
+#[derive(Serialize, defmt::Format)]
+struct LinkPreviewOptions {
+    is_disabled: bool,
+}
+
+/// Message options
+#[derive(defmt::Format, Clone, Copy)]
+pub struct MessageOptions {
+    pub parse_mode: Option<ParseMode>,  // formatted text: values must be escaped, see `text::Escape`
+    pub disable_notification: bool,    // silent: no sound
+    pub protect_content: bool,         // no forwarding, no saving
+    pub disable_link_preview: bool,
+    pub ack_buttons: bool,             // "On it" / "Done" buttons: see `ack`
+}
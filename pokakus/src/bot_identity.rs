@@ -0,0 +1,125 @@
+// The bot's identity: is the token any good? Who are we?
+//
+// A wrong or revoked token would only show up on the first press, as a generic failure.
+// So: `getMe` as soon as the network is up. 401: the LED shows a configuration error, and stays so.
+// The config is baked into the firmware: a change means a reflash, and a reboot.
+// Still, we check again whenever the network comes back: e.g. after moving to another WiFi.
+// Any 401 along the way, and it's a configuration error too: see `unauthorized()`.
+
+use defmt;
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::led::{set_led_state, LedState};
+use crate::retry::{DeliveryMode, RetryPolicy};
+use crate::bot_api::{self, TelegramSendMessageError};
+use crate::telegram::TelegramApi;
+
+
+// Retries: the network may not be quite there yet
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    initial_delay: Duration::from_secs(5),
+    max_delay: Duration::from_secs(60),
+    mode: DeliveryMode::AtLeastOnce,  // getMe changes nothing
+};
+
+
+/// The bot: as Telegram knows it
+#[derive(defmt::Format, Clone)]
+pub struct BotInfo {
+    pub id: i64,
+    pub username: heapless::String<32>,  // without the "@"
+}
+
+/// What we know about the token
+#[derive(defmt::Format, Clone)]
+pub enum Identity {
+    Unknown,         // not checked yet, or couldn't tell
+    Valid(BotInfo),
+    Unauthorized,    // 401: the token is wrong, or revoked
+}
+
+/// What we know about the token: now
+pub fn identity() -> Identity {
+    IDENTITY.lock(|i| i.borrow().clone())
+}
+
+/// The bot's username, if we know it
+pub fn username() -> Option<heapless::String<32>> {
+    match identity() {
+        Identity::Valid(bot) => Some(bot.username),
+        _ => None,
+    }
+}
+
+/// Is the token known to be bad?
+pub fn is_unauthorized() -> bool {
+    matches!(identity(), Identity::Unauthorized)
+}
+
+/// Telegram has said 401: the token's no good
+pub fn unauthorized() {
+    defmt::error!("Telegram: 401 Unauthorized. Check TELEGRAM_BOT_TOKEN!");
+    IDENTITY.lock(|i| *i.borrow_mut() = Identity::Unauthorized);
+    set_led_state(LedState::ConfigError);
+}
+
+static IDENTITY: Mutex<CriticalSectionRawMutex, RefCell<Identity>> = Mutex::new(RefCell::new(Identity::Unknown));
+
+
+// Task: validate the token whenever the network comes up
+#[embassy_executor::task]
+pub async fn task_validate_token(stack: embassy_net::Stack<'static>) {
+    loop {
+        stack.wait_config_up().await;
+        validate(stack).await;
+        stack.wait_config_down().await;
+    }
+}
+
+// getMe: remember who we are
+async fn validate(stack: embassy_net::Stack<'_>) {
+    let result = RETRY_POLICY.run(|| get_me(stack), |_, _| ()).await;
+    match result {
+        Ok(bot) => {
+            defmt::info!("Telegram: bot @{} ({}): token ok", bot.username, bot.id);
+            let was_unauthorized = is_unauthorized();
+            IDENTITY.lock(|i| *i.borrow_mut() = Identity::Valid(bot));
+            if was_unauthorized {
+                set_led_state(LedState::PresenceBlink);
+            }
+        }
+        Err(e) if e.error_code() == Some(401) => (),  // see `unauthorized()`
+        Err(e) => defmt::warn!("Telegram: can't validate the token: {:?}", defmt::Debug2Format(&e)),
+    }
+}
+
+async fn get_me(stack: embassy_net::Stack<'_>) -> Result<BotInfo, TelegramSendMessageError> {
+    let mut buf = alloc::vec![0u8; 2 * 1024];  // twice: see `api_call()`
+    let me: GetMeResult = bot_api::api_call(&TelegramApi::new(stack), "getMe", &GetMeInput {}, 16, &mut buf).await?;
+    let mut username = heapless::String::new();
+    username.push_str(me.username.unwrap_or("")).map_err(|_| TelegramSendMessageError::ResponseError)?;
+    Ok(BotInfo { id: me.id, username })
+}
+
+#[derive(Serialize)]
+struct GetMeInput {}
+
+#[derive(Deserialize)]
+struct GetMeResult<'a> {
+    id: i64,
+    #[serde(borrow)]
+    username: Option<&'a str>,  // bots always have one
+}
+
+
+/* Telegram API:
+ * $ http POST 'https://api.telegram.org/bot${TELEGRAM_BOT_TOKEN}/getMe'
+ * {"ok":true,"result":{"id":6415095545,"is_bot":true,"first_name":"...","username":"...bot","can_join_groups":true,...}}
+ */
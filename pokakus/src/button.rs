@@ -7,21 +7,37 @@ use embassy_sync::{
     channel::Channel,
     blocking_mutex::raw::CriticalSectionRawMutex,
 };
+use embassy_time::{Duration, with_timeout};
 
+pub use crate::gesture::Gesture;
+
+
+// Hold the button this long for a long press
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+// Click again within this time for a double click.
+// This is how long a click waits before it's sent.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
 
 
 /// Wait until the button's clicked.
+/// Other gestures are ignored.
 //
 // NOTE: exposed as a function to hide implementation detail
 pub async fn wait_for_button_click() {
-    BUTTON_CLICKS.receive().await;
+    while wait_for_gesture().await != Gesture::Click {}
 }
 
-/// Channel: button clicks.
-/// An empty message is sent along every time the button's clicked.
+/// Wait for any gesture
+pub async fn wait_for_gesture() -> Gesture {
+    BUTTON_GESTURES.receive().await
+}
+
+/// Channel: button gestures.
+/// A message is sent along every time the button's clicked or held.
 //
 // A channel will send separate events.
-static BUTTON_CLICKS: Channel<CriticalSectionRawMutex, (), 1> = Channel::new();
+static BUTTON_GESTURES: Channel<CriticalSectionRawMutex, Gesture, 1> = Channel::new();
 
 /// Task: listen to button clicks
 #[embassy_executor::task]
@@ -34,9 +50,22 @@ pub async fn task_button_clicks(mut button: gpio::Input<'static>) {
         // Verify button is still pressed (not a bounce)
         embassy_time::Timer::after_millis(20).await;
         if button.is_low() {
+            // Long press? Wait for release, but not longer than LONG_PRESS
+            let gesture = match with_timeout(LONG_PRESS, button.wait_for_high()).await {
+                // Released. Pressed again soon? A double click.
+                Ok(()) => {
+                    embassy_time::Timer::after_millis(20).await;  // debounce the release
+                    match with_timeout(DOUBLE_CLICK, button.wait_for_falling_edge()).await {
+                        Ok(()) => Gesture::DoubleClick,
+                        Err(_) => Gesture::Click,
+                    }
+                }
+                Err(_) => Gesture::LongPress,
+            };
+
             // Send ONE event
-            defmt::debug!("Button clicked");
-            let _ = BUTTON_CLICKS.try_send(()); // Non-blocking
+            defmt::debug!("Button: {:?}", gesture);
+            let _ = BUTTON_GESTURES.try_send(gesture); // Non-blocking
 
             // Wait for it to be released. Don't send any more events.
             button.wait_for_high().await;
@@ -0,0 +1,38 @@
+// Wall-clock time: kept as the Unix time at boot. Set by `sntp`.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::Instant;
+
+
+/// Local time zone: offset from UTC, minutes
+pub const TIMEZONE_OFFSET_MINUTES: i32 = crate::config::parse_i32(option_env!("TIMEZONE_OFFSET_MINUTES"), 0);
+
+
+/// Wall-clock time: Unix timestamp, seconds.
+/// `None` until we've synced with an NTP server.
+pub fn now_unix() -> Option<u64> {
+    BOOT_UNIX_TIME.lock(|t| t.get()).map(|boot| boot + Instant::now().as_secs())
+}
+
+/// Wall-clock time of an `Instant`: Unix timestamp, seconds
+pub fn unix_at(instant: Instant) -> Option<u64> {
+    BOOT_UNIX_TIME.lock(|t| t.get()).map(|boot| boot + instant.as_secs())
+}
+
+/// Local day number: days since the Unix epoch, in the local time zone
+pub fn local_day(unix: u64) -> i64 {
+    (unix as i64 + TIMEZONE_OFFSET_MINUTES as i64 * 60).div_euclid(86400)
+}
+
+/// Set wall-clock time
+pub fn set_unix_time(unix: u64) {
+    let boot = unix.saturating_sub(Instant::now().as_secs());
+    BOOT_UNIX_TIME.lock(|t| t.set(Some(boot)));
+}
+
+// Unix time at boot. We only keep the offset: `Instant` keeps ticking.
+static BOOT_UNIX_TIME: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
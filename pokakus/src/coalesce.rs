@@ -0,0 +1,107 @@
+// Coalescing: a burst of presses, one message.
+//
+// Hammer the button, and the family gets a dozen identical messages. Instead:
+//   Pokakus ×3 (10:02–10:05)
+// Events of the same kind are merged when they come within a window,
+// or when they've piled up while we were offline. Clicks only: see `policy()`.
+// With a window, the first event waits it out: the others may yet come.
+// The sender holds it meanwhile, and keeps to its other deadlines: see `notifier`.
+
+use defmt;
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+
+use crate::gesture::Gesture;
+use crate::config;
+use crate::event::Event;
+use crate::outbox::{self, Entry, OutgoingMessage};
+use crate::text::{Escape, ParseMode};
+
+
+/// Coalescing policy: for one event kind
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Coalesce {
+    Off,               // every event, its own message
+    Offline,           // merge the events that have piled up while offline
+    Window(Duration),  // ... and the events within this long of the first one
+}
+
+impl Coalesce {
+    /// "off", "offline", or the window in seconds
+    pub const fn parse(value: Option<&str>) -> Coalesce {
+        match value {
+            None => Coalesce::Off,
+            Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"off") => Coalesce::Off,
+            Some(v) if config::eq(v.as_bytes(), b"offline") => Coalesce::Offline,
+            Some(_) => match config::parse_u32(value, 0) {
+                0 => Coalesce::Off,
+                secs => Coalesce::Window(Duration::from_secs(secs as u64)),
+            },
+        }
+    }
+}
+
+// Config: clicks. Double clicks and long presses aren't messages: undo, and diagnostics.
+const COALESCE_CLICK: Coalesce = Coalesce::parse(option_env!("COALESCE_CLICK"));
+
+/// Coalescing policy for this kind of event
+pub const fn policy(kind: Gesture) -> Coalesce {
+    match kind {
+        Gesture::Click => COALESCE_CLICK,
+        Gesture::DoubleClick | Gesture::LongPress => Coalesce::Off,
+    }
+}
+
+
+/// Several events in one message.
+/// The entry's event is the last one: its count and time go into the template.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Burst {
+    pub count: u32,
+    pub first: Event,
+}
+
+/// Merge the events that follow this one, if the policy says so.
+/// `offline`: we've been waiting for the network; whatever has piled up is merged.
+/// `Some`: the end of its window. Hold the message till then, then `close_window()`.
+pub async fn coalesce(entry: &mut Entry, offline: bool) -> Option<Instant> {
+    let OutgoingMessage::Event { event, .. } = &entry.message else { return None };
+    match (policy(event.gesture), offline) {
+        (Coalesce::Off, _) => (),
+        (Coalesce::Offline, false) => (),
+        (Coalesce::Offline | Coalesce::Window(_), true) => outbox::absorb(entry, None).await,
+        (Coalesce::Window(window), false) => return Some(event.at + window),  // the rest of the burst may yet come
+    }
+    log_burst(entry);
+    None
+}
+
+/// The window's over: merge the events that have come within it
+pub async fn close_window(entry: &mut Entry) {
+    let OutgoingMessage::Event { event, .. } = &entry.message else { return };
+    if let Coalesce::Window(window) = policy(event.gesture) {
+        outbox::absorb(entry, Some(window)).await;
+    }
+    log_burst(entry);
+}
+
+fn log_burst(entry: &Entry) {
+    if let Some(burst) = &entry.burst {
+        defmt::info!("Coalesced {} events", burst.count);
+    }
+}
+
+/// Burst suffix: " ×3 (10:02–10:05)". Without the wall clock, just the count.
+pub fn write_burst<W: Write>(out: &mut W, burst: &Burst, last: &Event, parse_mode: Option<ParseMode>) -> fmt::Result {
+    let mut out = Escape { inner: out, mode: parse_mode };
+    write!(out, " ×{}", burst.count)?;
+    if let (Some(first), Some(last)) = (burst.first.unix_time, last.unix_time) {
+        out.write_str(" (")?;
+        crate::template::write_time(&mut out, first)?;
+        out.write_str("–")?;
+        crate::template::write_time(&mut out, last)?;
+        out.write_str(")")?;
+    }
+    Ok(())
+}
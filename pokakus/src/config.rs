@@ -0,0 +1,165 @@
+// Compile-time configuration helpers.
+//
+// All settings come from environment variables *at compile time* (see `mise.toml`).
+// These helpers parse them in `const` context: a bad value fails the build, not the device.
+
+
+/// Parse a boolean flag: "1"/"true"/"yes" or "0"/"false"/"no". Empty or missing → default.
+pub const fn parse_bool(value: Option<&str>, default: bool) -> bool {
+    let v = match value {
+        None => return default,
+        Some(v) => v.as_bytes(),
+    };
+    if v.is_empty() {
+        default
+    } else if eq(v, b"1") || eq(v, b"true") || eq(v, b"yes") {
+        true
+    } else if eq(v, b"0") || eq(v, b"false") || eq(v, b"no") {
+        false
+    } else {
+        panic!("Invalid boolean value in config")
+    }
+}
+
+/// Parse an unsigned number. Empty or missing → default.
+pub const fn parse_u32(value: Option<&str>, default: u32) -> u32 {
+    let v = match value {
+        None => return default,
+        Some(v) => v.as_bytes(),
+    };
+    if v.is_empty() {
+        return default;
+    }
+
+    let mut n: u32 = 0;
+    let mut i = 0;
+    while i < v.len() {
+        let c = v[i];
+        if !c.is_ascii_digit() {
+            panic!("Invalid number in config");
+        }
+        n = match n.checked_mul(10) {
+            Some(n) => match n.checked_add((c - b'0') as u32) {
+                Some(n) => n,
+                None => panic!("Number too large in config"),
+            },
+            None => panic!("Number too large in config"),
+        };
+        i += 1;
+    }
+    n
+}
+
+/// Parse a signed number. Empty or missing → default.
+pub const fn parse_i32(value: Option<&str>, default: i32) -> i32 {
+    let v = match value {
+        Some(v) if !v.is_empty() => v.as_bytes(),
+        _ => return default,
+    };
+    let (negative, digits) = match v {
+        [b'-', digits @ ..] => (true, digits),
+        digits => (false, digits),
+    };
+    if digits.is_empty() {
+        panic!("Invalid number in config");
+    }
+    let n = match core::str::from_utf8(digits) {
+        Ok(digits) => parse_u32(Some(digits), 0) as i64,
+        Err(_) => panic!("Invalid number in config"),
+    };
+    let n = if negative { -n } else { n };
+    if n < i32::MIN as i64 || n > i32::MAX as i64 {
+        panic!("Number too large in config");
+    }
+    n as i32
+}
+
+/// Parse a signed 64-bit number: e.g. a chat id, an item of a list
+pub const fn parse_i64(v: &[u8]) -> i64 {
+    let (negative, digits) = match v {
+        [b'-', digits @ ..] => (true, digits),
+        digits => (false, digits),
+    };
+    if digits.is_empty() {
+        panic!("Invalid number in config");
+    }
+
+    let mut n: i64 = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        let c = digits[i];
+        if !c.is_ascii_digit() {
+            panic!("Invalid number in config");
+        }
+        // Negative numbers go one further: i64::MIN
+        let digit = (c - b'0') as i64;
+        n = match n.checked_mul(10) {
+            Some(n) if negative => n.checked_sub(digit),
+            Some(n) => n.checked_add(digit),
+            None => None,
+        }.expect("Number too large in config");
+        i += 1;
+    }
+    n
+}
+
+/// Strip spaces and tabs around
+pub const fn trim(mut v: &[u8]) -> &[u8] {
+    while let Some((b' ' | b'\t', rest)) = v.split_first() {
+        v = rest;
+    }
+    while let Some((b' ' | b'\t', rest)) = v.split_last() {
+        v = rest;
+    }
+    v
+}
+
+/// Compare byte strings, `const` version
+pub const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Does `s` start with `prefix`? `const` version
+pub const fn starts_with(s: &[u8], prefix: &[u8]) -> bool {
+    if s.len() < prefix.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if s[i] != prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Is `item` in a comma-separated list? Spaces around items are ignored. `const` version
+pub const fn list_contains(list: &str, item: &str) -> bool {
+    let mut rest = list.as_bytes();
+    loop {
+        // The item: up to the next comma
+        let mut end = 0;
+        while end < rest.len() && rest[end] != b',' {
+            end += 1;
+        }
+        let (current, tail) = rest.split_at(end);
+        if eq(trim(current), item.as_bytes()) {
+            return true;
+        }
+        match tail.split_first() {
+            Some((_, tail)) => rest = tail,
+            None => return false,
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use defmt;
+use core::fmt;
+use core::net::IpAddr;
+
+use embassy_net::{
+    IpAddress, IpCidr,
+    icmp::{IcmpEndpoint, IcmpSocket, PacketMetadata},
+    tcp::TcpSocket,
+};
+use embassy_sync::{
+    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::{Duration, Instant, with_timeout};
+use heapless::String;
+
+use crate::dns::{DnsCacheStats, RecordType, Resolver};
+
+
+// The host to test: resolve it, ping it, connect to it
+const DIAGNOSTICS_HOST: &str = match option_env!("DIAGNOSTICS_HOST") {
+    Some(v) if !v.is_empty() => v,
+    _ => "api.telegram.org",
+};
+const DIAGNOSTICS_TCP_PORT: u16 = crate::config::parse_u32(option_env!("DIAGNOSTICS_TCP_PORT"), 443) as u16;
+
+// Give up on a probe after
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// ICMP identifier for our echo requests
+const PING_IDENT: u16 = 0x504B;  // "PK"
+
+
+/// Where to deliver the diagnostics report
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum ReportTo {
+    Log,       // local: the serial console
+    Message,   // the console + send as a message
+}
+
+/// Run diagnostics in the background and report
+pub fn request_diagnostics(to: ReportTo) {
+    DIAGNOSTICS_REQUEST.signal(to);
+}
+
+// Signal: diagnostics requested
+static DIAGNOSTICS_REQUEST: Signal<CriticalSectionRawMutex, ReportTo> = Signal::new();
+
+
+// Task: run diagnostics on demand
+#[embassy_executor::task]
+pub async fn task_diagnostics(stack: embassy_net::Stack<'static>) {
+    loop {
+        let to = DIAGNOSTICS_REQUEST.wait().await;
+
+        defmt::info!("Diagnostics: running...");
+        let report = run_diagnostics(stack).await;
+        defmt::info!("Diagnostics: {:?}", report);
+
+        if to == ReportTo::Message {
+            let mut text: String<512> = String::new();
+            use core::fmt::Write;
+            if write!(text, "{}", report).is_err() {
+                defmt::warn!("Diagnostics: report truncated");
+            }
+            crate::notifier::send_text(text.as_str());
+        }
+    }
+}
+
+
+/// Run all probes, return a report.
+/// Each IP version is probed on its own: one may work while the other doesn't.
+pub async fn run_diagnostics(stack: embassy_net::Stack<'_>) -> DiagnosticsReport {
+    let resolver = Resolver::new(stack);
+
+    // IPv4
+    let config_v4 = stack.config_v4();
+    let ipv4 = run_probes(
+        stack,
+        &resolver,
+        config_v4.as_ref().map(|c| IpCidr::Ipv4(c.address)),
+        config_v4.as_ref().and_then(|c| c.gateway).map(IpAddress::Ipv4),
+        RecordType::A,
+    ).await;
+
+    // IPv6: see `wifi`; the gateway is IPV6_GATEWAY
+    #[cfg(feature = "ipv6")]
+    let ipv6 = {
+        let config_v6 = stack.config_v6();
+        run_probes(
+            stack,
+            &resolver,
+            config_v6.as_ref().map(|c| IpCidr::Ipv6(c.address)),
+            config_v6.as_ref().and_then(|c| c.gateway).map(IpAddress::Ipv6),
+            RecordType::Aaaa,
+        ).await
+    };
+
+    DiagnosticsReport {
+        ipv4,
+        #[cfg(feature = "ipv6")]
+        ipv6,
+        dns_cache: crate::dns::cache_stats(),
+    }
+}
+
+// Probes over one IP version: ping the gateway, resolve the host, ping it, connect to it.
+// No address of this version: nothing to probe.
+async fn run_probes(
+    stack: embassy_net::Stack<'_>,
+    resolver: &Resolver<'_>,
+    address: Option<IpCidr>,
+    gateway: Option<IpAddress>,
+    rtype: RecordType,
+) -> ProbesReport {
+    if address.is_none() {
+        return ProbesReport {
+            address,
+            gateway,
+            ping_gateway: Probe::Skipped,
+            dns: Probe::Skipped,
+            host_addr: None,
+            ping_host: Probe::Skipped,
+            tcp_connect: Probe::Skipped,
+        };
+    }
+
+    // Ping the gateway
+    let ping_gateway = match gateway {
+        Some(gw) => ping(stack, gw).await,
+        None => Probe::Skipped,
+    };
+
+    // Resolve
+    let started = Instant::now();
+    let host_addr = resolver.query(DIAGNOSTICS_HOST, rtype).await;
+    let dns = match host_addr {
+        Ok(_) => Probe::Ok(started.elapsed()),
+        Err(e) => {
+            defmt::warn!("Diagnostics: DNS {:?} failed: {:?}", rtype, e);
+            Probe::Failed
+        }
+    };
+    let host_addr = match host_addr {
+        Ok(IpAddr::V4(addr)) => Some(IpAddress::Ipv4(addr)),
+        #[cfg(feature = "ipv6")]
+        Ok(IpAddr::V6(addr)) => Some(IpAddress::Ipv6(addr)),
+        _ => None,
+    };
+
+    // Ping the host, connect to it
+    let (ping_host, tcp_connect) = match host_addr {
+        Some(addr) => (
+            ping(stack, addr).await,
+            tcp_connect(stack, addr, DIAGNOSTICS_TCP_PORT).await,
+        ),
+        None => (Probe::Skipped, Probe::Skipped),
+    };
+
+    ProbesReport { address, gateway, ping_gateway, dns, host_addr, ping_host, tcp_connect }
+}
+
+
+/// Diagnostics report
+#[derive(defmt::Format)]
+pub struct DiagnosticsReport {
+    pub ipv4: ProbesReport,
+    #[cfg(feature = "ipv6")]
+    pub ipv6: ProbesReport,
+    pub dns_cache: DnsCacheStats,
+}
+
+/// Probes over one IP version
+#[derive(defmt::Format)]
+pub struct ProbesReport {
+    pub address: Option<IpCidr>,
+    pub gateway: Option<IpAddress>,
+    pub ping_gateway: Probe,
+    pub dns: Probe,
+    pub host_addr: Option<IpAddress>,
+    pub ping_host: Probe,
+    pub tcp_connect: Probe,
+}
+
+/// Outcome of a probe
+#[derive(defmt::Format, Clone, Copy)]
+pub enum Probe {
+    Ok(Duration),  // round-trip time
+    Failed,
+    Skipped,       // not applicable, e.g. no gateway
+}
+
+// Human-readable: for messages
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diagnostics")?;
+        write_probes(f, "IPv4", "A", &self.ipv4)?;
+        #[cfg(feature = "ipv6")]
+        write_probes(f, "IPv6", "AAAA", &self.ipv6)?;
+        write!(f, "DNS cache: {} hits, {} misses", self.dns_cache.hits, self.dns_cache.misses)
+    }
+}
+
+fn write_probes(f: &mut fmt::Formatter<'_>, version: &str, rtype: &str, probes: &ProbesReport) -> fmt::Result {
+    match probes.address {
+        Some(ip) => writeln!(f, "{}: {}", version, ip)?,
+        None => return writeln!(f, "{}: none", version),
+    }
+    writeln!(f, "Ping gateway: {}", probes.ping_gateway)?;
+    writeln!(f, "DNS {} {}: {}", rtype, DIAGNOSTICS_HOST, probes.dns)?;
+    if let Some(addr) = probes.host_addr {
+        writeln!(f, "Address: {}", addr)?;
+    }
+    writeln!(f, "Ping host: {}", probes.ping_host)?;
+    writeln!(f, "TCP :{}: {}", DIAGNOSTICS_TCP_PORT, probes.tcp_connect)
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Ok(rtt) => write!(f, "{} ms", rtt.as_millis()),
+            Probe::Failed => write!(f, "failed"),
+            Probe::Skipped => write!(f, "-"),
+        }
+    }
+}
+
+
+
+// Ping: ICMP echo request → reply. ICMPv4 or ICMPv6, whichever `addr` needs.
+async fn ping(stack: embassy_net::Stack<'_>, addr: IpAddress) -> Probe {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 128];
+    let mut tx_buffer = [0u8; 128];
+    let mut socket = IcmpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(IcmpEndpoint::Ident(PING_IDENT)).is_err() {
+        return Probe::Failed;
+    }
+
+    // Echo request
+    let seq = (Instant::now().as_ticks() & 0xFFFF) as u16;
+    let request = icmp_echo_request(addr, PING_IDENT, seq);
+    let started = Instant::now();
+    if let Err(e) = socket.send_to(&request, addr).await {
+        defmt::warn!("Ping {}: send failed: {:?}", addr, e);
+        return Probe::Failed;
+    }
+
+    // Wait for our reply: skip anything else
+    let wait_reply = async {
+        let mut buf = [0u8; 128];
+        loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, from)) if from == addr && is_icmp_echo_reply(&buf[..len], addr, PING_IDENT, seq) => return Ok(()),
+                Ok(_) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    };
+    match with_timeout(PROBE_TIMEOUT, wait_reply).await {
+        Ok(Ok(())) => Probe::Ok(started.elapsed()),
+        Ok(Err(e)) => {
+            defmt::warn!("Ping {}: recv failed: {:?}", addr, e);
+            Probe::Failed
+        }
+        Err(_) => {
+            defmt::warn!("Ping {}: timeout", addr);
+            Probe::Failed
+        }
+    }
+}
+
+// Build an ICMP echo request with a small payload
+fn icmp_echo_request(addr: IpAddress, ident: u16, seq: u16) -> [u8; 16] {
+    let mut packet = [0u8; 16];
+    packet[0] = match addr {
+        IpAddress::Ipv4(_) => 8,  // type: echo request
+        #[cfg(feature = "ipv6")]
+        IpAddress::Ipv6(_) => 128,
+    };
+    packet[1] = 0;  // code
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    packet[8..].copy_from_slice(b"pokakus!");
+    match addr {
+        IpAddress::Ipv4(_) => {
+            let checksum = internet_checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        // ICMPv6's checksum covers the IP addresses too: smoltcp fills it in
+        #[cfg(feature = "ipv6")]
+        IpAddress::Ipv6(_) => (),
+    }
+    packet
+}
+
+fn is_icmp_echo_reply(packet: &[u8], addr: IpAddress, ident: u16, seq: u16) -> bool {
+    let echo_reply = match addr {
+        IpAddress::Ipv4(_) => 0,
+        #[cfg(feature = "ipv6")]
+        IpAddress::Ipv6(_) => 129,
+    };
+    packet.len() >= 8
+        && packet[0] == echo_reply
+        && packet[4..6] == ident.to_be_bytes()
+        && packet[6..8] == seq.to_be_bytes()
+}
+
+// RFC 1071 checksum
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+
+// TCP probe: how long does the handshake take?
+async fn tcp_connect(stack: embassy_net::Stack<'_>, addr: IpAddress, port: u16) -> Probe {
+    let mut rx_buffer = [0u8; 64];
+    let mut tx_buffer = [0u8; 64];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(PROBE_TIMEOUT));
+
+    let started = Instant::now();
+    let result = match with_timeout(PROBE_TIMEOUT, socket.connect((addr, port))).await {
+        Ok(Ok(())) => Probe::Ok(started.elapsed()),
+        Ok(Err(e)) => {
+            defmt::warn!("TCP {}:{}: connect failed: {:?}", addr, port, e);
+            Probe::Failed
+        }
+        Err(_) => {
+            defmt::warn!("TCP {}:{}: timeout", addr, port);
+            Probe::Failed
+        }
+    };
+
+    // We only wanted the handshake
+    socket.abort();
+    let _ = socket.flush().await;
+    result
+}
@@ -0,0 +1,309 @@
+use defmt;
+use core::cell::RefCell;
+use core::net::IpAddr;
+
+use esp_hal::rng::Rng;
+use embassy_net::{
+    IpAddress,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant, with_timeout};
+use embedded_nal_async::AddrType;
+use heapless::{String, Vec};
+
+use crate::config;
+use crate::dns_packet as packet;
+pub use crate::dns_packet::{DnsError, RecordType};
+
+
+// DNS cache: how many names to remember.
+// We only talk to a handful of hosts.
+const CACHE_SIZE: usize = 4;
+
+// Respect the record's TTL, but within reason:
+// - too short: we'd query on every request anyway
+// - too long: we'd never notice a change
+const DNS_CACHE_MIN_TTL: u32 = config::parse_u32(option_env!("DNS_CACHE_MIN_TTL"), 30);
+const DNS_CACHE_MAX_TTL: u32 = config::parse_u32(option_env!("DNS_CACHE_MAX_TTL"), 3600);
+const _: () = assert!(DNS_CACHE_MIN_TTL <= DNS_CACHE_MAX_TTL, "DNS cache: expected DNS_CACHE_MIN_TTL <= DNS_CACHE_MAX_TTL");
+
+// Stale-while-error: when the DNS server fails, serve an expired entry.
+// The IP of a big API rarely changes; a flaky DNS server is much more likely.
+const DNS_CACHE_STALE_ON_ERROR: bool = config::parse_bool(option_env!("DNS_CACHE_STALE_ON_ERROR"), true);
+const DNS_CACHE_MAX_STALE: u32 = config::parse_u32(option_env!("DNS_CACHE_MAX_STALE"), 24 * 3600);
+
+// How long to wait for each DNS server
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// DNS resolver for HTTP clients.
+///
+/// Decides which address family to ask for:
+/// with a routable IPv6 address we ask for AAAA first and fall back to A.
+///
+/// Answers are cached, and the cache is shared by all resolvers:
+/// create as many as you like.
+pub struct Resolver<'a> {
+    stack: embassy_net::Stack<'a>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(stack: embassy_net::Stack<'a>) -> Self {
+        Self { stack }
+    }
+
+    /// Resolve a name: one address of the given family.
+    /// Uses the cache.
+    pub async fn query(&self, host: &str, rtype: RecordType) -> Result<IpAddr, DnsError> {
+        // Cache?
+        let cached = CACHE.lock(|c| c.borrow_mut().lookup(host, rtype));
+        if let Lookup::Fresh(addr) = cached {
+            defmt::debug!("DNS: {} → {} (cached)", host, defmt::Display2Format(&addr));
+            return Ok(addr);
+        }
+
+        // Ask the server
+        match query_upstream(self.stack, host, rtype).await {
+            Ok((addr, ttl)) => {
+                defmt::debug!("DNS: {} → {} ttl={}", host, defmt::Display2Format(&addr), ttl);
+                CACHE.lock(|c| c.borrow_mut().insert(host, rtype, addr, ttl));
+                Ok(addr)
+            }
+            Err(e) => {
+                // Failed. Stale-while-error?
+                if let Lookup::Stale(addr) = cached {
+                    defmt::warn!("DNS: {} failed, using stale {}", host, defmt::Display2Format(&addr));
+                    CACHE.lock(|c| c.borrow_mut().stats.stale += 1);
+                    return Ok(addr);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Which address families to try, in order of preference
+    fn query_order(&self, addr_type: AddrType) -> &'static [RecordType] {
+        match addr_type {
+            AddrType::IPv4 => &[RecordType::A],
+            AddrType::IPv6 => &[RecordType::Aaaa],
+            AddrType::Either => {
+                if self.has_routable_ipv6() {
+                    &[RecordType::Aaaa, RecordType::A]
+                } else {
+                    &[RecordType::A]
+                }
+            }
+        }
+    }
+
+    /// Do we have an IPv6 address that can reach the internet? (not just link-local)
+    #[cfg(feature = "ipv6")]
+    fn has_routable_ipv6(&self) -> bool {
+        match self.stack.config_v6() {
+            Some(config) => !config.address.address().is_unicast_link_local(),
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "ipv6"))]
+    fn has_routable_ipv6(&self) -> bool {
+        false
+    }
+}
+
+// reqwless uses `embedded-nal-async` to resolve names
+impl embedded_nal_async::Dns for Resolver<'_> {
+    type Error = DnsError;
+
+    async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, Self::Error> {
+        let mut result = Err(DnsError::NotFound);
+        for rtype in self.query_order(addr_type) {
+            result = self.query(host, *rtype).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+        defmt::debug!("DNS cache: {:?}", cache_stats());
+        result
+    }
+
+    async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, Self::Error> {
+        Err(DnsError::Unsupported)
+    }
+}
+
+
+/// Cache statistics
+#[derive(Debug, defmt::Format, Clone, Copy, Default)]
+pub struct DnsCacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    pub stale: u32,  // served stale because the server failed
+}
+
+/// Get cache statistics
+pub fn cache_stats() -> DnsCacheStats {
+    CACHE.lock(|c| c.borrow().stats)
+}
+
+
+
+// The cache, shared by all resolvers.
+// Blocking mutex: it's never held across an `.await`.
+static CACHE: Mutex<CriticalSectionRawMutex, RefCell<DnsCache>> = Mutex::new(RefCell::new(DnsCache::new()));
+
+struct DnsCache {
+    entries: Vec<CacheEntry, CACHE_SIZE>,
+    stats: DnsCacheStats,
+}
+
+struct CacheEntry {
+    host: String<64>,
+    rtype: RecordType,
+    addr: IpAddr,
+    expires_at: Instant,
+}
+
+enum Lookup {
+    Fresh(IpAddr),
+    Stale(IpAddr),
+    Miss,
+}
+
+impl DnsCache {
+    const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            stats: DnsCacheStats { hits: 0, misses: 0, stale: 0 },
+        }
+    }
+
+    fn lookup(&mut self, host: &str, rtype: RecordType) -> Lookup {
+        let now = Instant::now();
+        let found = self.entries.iter()
+            .find(|e| e.rtype == rtype && e.host.as_str() == host);
+
+        let result = match found {
+            Some(e) if now < e.expires_at => Lookup::Fresh(e.addr),
+            Some(e) if DNS_CACHE_STALE_ON_ERROR && now < e.expires_at + Duration::from_secs(DNS_CACHE_MAX_STALE as u64) => Lookup::Stale(e.addr),
+            _ => Lookup::Miss,
+        };
+        match result {
+            Lookup::Fresh(_) => self.stats.hits += 1,
+            _ => self.stats.misses += 1,
+        }
+        result
+    }
+
+    fn insert(&mut self, host: &str, rtype: RecordType, addr: IpAddr, ttl: u32) {
+        // Names too long to cache are just resolved every time
+        let Ok(host) = String::try_from(host) else {
+            return;
+        };
+        let ttl = ttl.clamp(DNS_CACHE_MIN_TTL, DNS_CACHE_MAX_TTL);
+        let entry = CacheEntry {
+            host,
+            rtype,
+            addr,
+            expires_at: Instant::now() + Duration::from_secs(ttl as u64),
+        };
+
+        // Replace the existing entry, or evict the one that expires first
+        let existing = self.entries.iter().position(|e| e.rtype == rtype && e.host == entry.host);
+        let evict = existing.or_else(|| {
+            if self.entries.is_full() {
+                self.entries.iter()
+                    .enumerate()
+                    .min_by_key(|(_, e)| e.expires_at)
+                    .map(|(i, _)| i)
+            } else {
+                None
+            }
+        });
+        match evict {
+            Some(i) => self.entries[i] = entry,
+            None => { let _ = self.entries.push(entry); }
+        }
+    }
+}
+
+
+
+// Resolve a name with the DNS servers we've got: from DHCP, and IPV6_DNS.
+// Returns the address and its TTL.
+//
+// NOTE: embassy-net's `DnsSocket` doesn't tell us the TTL, so we talk to the server ourselves.
+async fn query_upstream(stack: embassy_net::Stack<'_>, host: &str, rtype: RecordType) -> Result<(IpAddr, u32), DnsError> {
+    // DNS servers
+    let mut servers: Vec<IpAddress, 6> = Vec::new();
+    if let Some(config) = stack.config_v4() {
+        for s in config.dns_servers.iter() {
+            let _ = servers.push(IpAddress::Ipv4(*s));
+        }
+    }
+    #[cfg(feature = "ipv6")]
+    if let Some(config) = stack.config_v6() {
+        for s in config.dns_servers.iter() {
+            let _ = servers.push(IpAddress::Ipv6(*s));
+        }
+    }
+    if servers.is_empty() {
+        return Err(DnsError::NoServers);
+    }
+
+    // Query
+    let id = Rng::new().random() as u16;
+    let mut query_buf = [0u8; 300];
+    let query_len = packet::encode_query(&mut query_buf, id, host, rtype)?;
+
+    // Socket
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 512];
+    let mut tx_buffer = [0u8; 300];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket.bind(0).map_err(|_| DnsError::Failed)?;
+
+    // Try servers one by one
+    let mut result = Err(DnsError::Failed);
+    for server in servers {
+        if let Err(e) = socket.send_to(&query_buf[..query_len], (server, 53)).await {
+            defmt::warn!("DNS: send to {} failed: {:?}", server, e);
+            continue;
+        }
+
+        // The reply: from the server we've asked, not just anyone who's guessed our port.
+        // Also not a late one from the server before.
+        let mut resp_buf = [0u8; 512];
+        let reply = with_timeout(DNS_TIMEOUT, async {
+            loop {
+                match socket.recv_from(&mut resp_buf).await {
+                    Ok((_, meta)) if meta.endpoint.addr != server || meta.endpoint.port != 53 => {
+                        defmt::warn!("DNS: ignoring a reply from {}, asked {}", meta.endpoint, server);
+                    }
+                    received => return received.map(|(len, _)| len),
+                }
+            }
+        });
+        result = match reply.await {
+            Ok(Ok(len)) => packet::parse_response(&resp_buf[..len], id, rtype),
+            Ok(Err(e)) => {
+                defmt::warn!("DNS: recv from {} failed: {:?}", server, e);
+                Err(DnsError::Failed)
+            }
+            Err(_) => {
+                defmt::warn!("DNS: {} timed out", server);
+                Err(DnsError::Failed)
+            }
+        };
+
+        // NotFound is an answer too: don't ask another server
+        if !matches!(result, Err(DnsError::Failed)) {
+            break;
+        }
+    }
+    result
+}
@@ -0,0 +1,132 @@
+// DNS packets: the query we send, the response we get. Just the subset `dns` needs.
+//
+// One question, A or AAAA. The answer: the first record of that type, with its TTL.
+// Names in answers may be compressed: pointers back into the packet. We skip names, never read them.
+
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum DnsError {
+    NoServers,   // network not configured
+    Failed,      // see logs
+    NotFound,
+    Unsupported,
+}
+
+
+/// DNS record types we can resolve
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+}
+
+impl RecordType {
+    // Wire format code
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+
+/// Build a DNS query: header + one question. Returns its length.
+pub fn encode_query(buf: &mut [u8], id: u16, host: &str, rtype: RecordType) -> Result<usize, DnsError> {
+    // Header: id, flags=RD (recursion desired), 1 question
+    buf[..12].copy_from_slice(&[0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    buf[..2].copy_from_slice(&id.to_be_bytes());
+    let mut pos = 12;
+
+    // Name: length-prefixed labels
+    for label in host.trim_end_matches('.').split('.') {
+        let label = label.as_bytes();
+        if label.is_empty() || label.len() > 63 || pos + 1 + label.len() + 5 > buf.len() {
+            return Err(DnsError::Unsupported);
+        }
+        buf[pos] = label.len() as u8;
+        buf[pos + 1..pos + 1 + label.len()].copy_from_slice(label);
+        pos += 1 + label.len();
+    }
+    buf[pos] = 0;
+    pos += 1;
+
+    // Type, class=IN
+    buf[pos..pos + 2].copy_from_slice(&rtype.code().to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&1u16.to_be_bytes());
+    Ok(pos + 4)
+}
+
+/// Parse a DNS response: the first answer of the type we've asked for, and its TTL.
+/// CNAMEs are skipped: the server sends the records they point to along.
+pub fn parse_response(buf: &[u8], id: u16, rtype: RecordType) -> Result<(IpAddr, u32), DnsError> {
+    let be16 = |pos: usize| -> Option<u16> { Some(u16::from_be_bytes([*buf.get(pos)?, *buf.get(pos + 1)?])) };
+    let be32 = |pos: usize| -> Option<u32> { Some(u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?)) };
+    let malformed = || {
+        defmt::warn!("DNS: malformed response");
+        DnsError::Failed
+    };
+
+    // Header
+    let flags = be16(2).ok_or_else(malformed)?;
+    if be16(0) != Some(id) || flags & 0x8000 == 0 {
+        return Err(malformed());
+    }
+    match flags & 0x000F {  // rcode
+        0 => (),
+        3 => return Err(DnsError::NotFound),  // NXDOMAIN
+        rcode => {
+            defmt::warn!("DNS: server error rcode={}", rcode);
+            return Err(DnsError::Failed);
+        }
+    }
+    let qdcount = be16(4).ok_or_else(malformed)?;
+    let ancount = be16(6).ok_or_else(malformed)?;
+
+    // Skip questions
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(buf, pos).ok_or_else(malformed)? + 4;
+    }
+
+    // Answers. Skip CNAMEs and anything else we didn't ask for.
+    for _ in 0..ancount {
+        pos = skip_name(buf, pos).ok_or_else(malformed)?;
+        let atype = be16(pos).ok_or_else(malformed)?;
+        let class = be16(pos + 2).ok_or_else(malformed)?;
+        let ttl = be32(pos + 4).ok_or_else(malformed)?;
+        let rdlength = be16(pos + 8).ok_or_else(malformed)? as usize;
+        let rdata = buf.get(pos + 10..pos + 10 + rdlength).ok_or_else(malformed)?;
+        pos += 10 + rdlength;
+
+        if atype != rtype.code() || class != 1 {
+            continue;
+        }
+        let addr = match rtype {
+            RecordType::A => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(rdata).map_err(|_| malformed())?)),
+            RecordType::Aaaa => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(rdata).map_err(|_| malformed())?)),
+        };
+        return Ok((addr, ttl));
+    }
+
+    // Nothing we've asked for. Truncated (TC)? The rest didn't fit: not an answer, ask another server.
+    if flags & 0x0200 != 0 {
+        defmt::warn!("DNS: truncated response");
+        return Err(DnsError::Failed);
+    }
+    Err(DnsError::NotFound)
+}
+
+// Skip a (possibly compressed) name, return the position right after it
+fn skip_name(buf: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *buf.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xC0 == 0xC0 => return Some(pos + 2),  // compression pointer: the name ends here
+            l => pos += 1 + l as usize,
+        }
+    }
+}
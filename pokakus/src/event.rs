@@ -0,0 +1,67 @@
+use defmt;
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant};
+
+use crate::gesture::Gesture;
+
+
+/// Something has happened: e.g. the button's been clicked.
+/// Captured at the moment it happens; the message is rendered later.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Event {
+    pub gesture: Gesture,
+    pub at: Instant,
+    pub unix_time: Option<u64>,  // if we know the time
+    pub count_today: u32,  // events today, of any kind, this one included
+    pub since_last: Option<Duration>,  // since the previous event, of any kind
+}
+
+impl Event {
+    /// Record an event: now.
+    /// One history for all kinds. Only clicks are recorded: the other gestures aren't messages.
+    pub fn record(gesture: Gesture) -> Event {
+        let at = Instant::now();
+        let unix_time = crate::clock::unix_at(at);
+        let day = unix_time.map(crate::clock::local_day);
+
+        HISTORY.lock(|h| {
+            let prev = h.get();
+
+            // New day? Start counting from zero.
+            // Without the wall clock, "today" means "since boot".
+            let count_today = if prev.day == day { prev.count_today + 1 } else { 1 };
+            let since_last = prev.last_at.map(|last| at - last);
+
+            h.set(History { last_at: Some(at), day, count_today });
+            Event { gesture, at, unix_time, count_today, since_last }
+        })
+    }
+}
+
+/// Events today: so far
+pub fn count_today() -> u32 {
+    let day = crate::clock::now_unix().map(crate::clock::local_day);
+    HISTORY.lock(|h| {
+        let h = h.get();
+        if h.day == day { h.count_today } else { 0 }
+    })
+}
+
+
+// Event history: just enough to fill in the placeholders
+#[derive(Clone, Copy)]
+struct History {
+    last_at: Option<Instant>,
+    day: Option<i64>,
+    count_today: u32,
+}
+
+static HISTORY: Mutex<CriticalSectionRawMutex, Cell<History>> = Mutex::new(Cell::new(History {
+    last_at: None,
+    day: None,
+    count_today: 0,
+}));
@@ -0,0 +1,20 @@
+// Button gestures: what `button` makes of the presses. No GPIO here: see `button`.
+
+/// Button gestures
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Click,        // short press
+    DoubleClick,  // two clicks within `button::DOUBLE_CLICK`
+    LongPress,    // hold for `button::LONG_PRESS` or longer
+}
+
+impl Gesture {
+    /// Human-readable name
+    pub fn name(self) -> &'static str {
+        match self {
+            Gesture::Click => "click",
+            Gesture::DoubleClick => "double click",
+            Gesture::LongPress => "long press",
+        }
+    }
+}
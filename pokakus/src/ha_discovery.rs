@@ -0,0 +1,94 @@
+// Home Assistant MQTT discovery: the device shows up by itself.
+//
+// A retained config message per entity, under the discovery prefix:
+// - device triggers: one per gesture. They fire on `{topic}/action`.
+// - sensors: WiFi signal, uptime, message counters. From `{topic}/status`.
+// All of them: one device, available while `{topic}/availability` says "online".
+// See https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::gesture::Gesture;
+use crate::text::escape_json;
+
+
+/// The action payload for a gesture: "double_click"
+pub fn action(gesture: Gesture) -> &'static str {
+    match gesture {
+        Gesture::Click => "click",
+        Gesture::DoubleClick => "double_click",
+        Gesture::LongPress => "long_press",
+    }
+}
+
+// Device triggers: Home Assistant's trigger type, per gesture
+const TRIGGERS: [(Gesture, &str); 3] = [
+    (Gesture::Click, "button_short_press"),
+    (Gesture::DoubleClick, "button_double_press"),
+    (Gesture::LongPress, "button_long_press"),
+];
+
+// A sensor: a value in the status JSON
+struct Sensor {
+    key: &'static str,  // in the status JSON, and the entity's id
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+}
+
+const SENSORS: [Sensor; 5] = [
+    Sensor { key: "rssi", name: "WiFi signal", unit: Some("dBm"), device_class: Some("signal_strength"), state_class: "measurement" },
+    Sensor { key: "uptime", name: "Uptime", unit: Some("s"), device_class: Some("duration"), state_class: "total_increasing" },
+    Sensor { key: "sent", name: "Messages sent", unit: None, device_class: None, state_class: "total_increasing" },
+    Sensor { key: "gave_up", name: "Messages failed", unit: None, device_class: None, state_class: "total_increasing" },
+    Sensor { key: "queued", name: "Messages waiting", unit: None, device_class: None, state_class: "measurement" },
+];
+
+
+/// The config messages: topic, payload.
+/// `prefix`: "homeassistant"; `topic`: ours; `node_id`: unique, e.g. from the MAC; `name`: the device's.
+pub fn configs(prefix: &str, topic: &str, node_id: &str, name: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+
+    // The device: the same in every entity
+    let mut device = String::new();
+    let _ = write!(device, r#"{{"identifiers":["{}"],"name":""#, node_id);
+    let _ = escape_json(&mut device, name);
+    let _ = write!(device, r#"","manufacturer":"Pokakus","model":"ESP32-C3","sw_version":"{}"}}"#, env!("CARGO_PKG_VERSION"));
+
+    for (gesture, kind) in TRIGGERS {
+        let (mut config_topic, mut payload) = (String::new(), String::new());
+        let _ = write!(config_topic, "{}/device_automation/{}/{}/config", prefix, node_id, action(gesture));
+        let _ = write!(
+            payload,
+            r#"{{"automation_type":"trigger","topic":"{}/action","payload":"{}","type":"{}","subtype":"button_1","device":{}}}"#,
+            topic, action(gesture), kind, device,
+        );
+        out.push((config_topic, payload));
+    }
+
+    for sensor in &SENSORS {
+        let (mut config_topic, mut payload) = (String::new(), String::new());
+        let _ = write!(config_topic, "{}/sensor/{}/{}/config", prefix, node_id, sensor.key);
+        let _ = write!(
+            payload,
+            r#"{{"name":"{}","unique_id":"{}_{}","state_topic":"{}/status","value_template":"{{{{ value_json.{} }}}}","#,
+            sensor.name, node_id, sensor.key, topic, sensor.key,
+        );
+        if let Some(unit) = sensor.unit {
+            let _ = write!(payload, r#""unit_of_measurement":"{}","#, unit);
+        }
+        if let Some(class) = sensor.device_class {
+            let _ = write!(payload, r#""device_class":"{}","#, class);
+        }
+        let _ = write!(
+            payload,
+            r#""state_class":"{}","entity_category":"diagnostic","availability_topic":"{}/availability","device":{}}}"#,
+            sensor.state_class, topic, device,
+        );
+        out.push((config_topic, payload));
+    }
+    out
+}
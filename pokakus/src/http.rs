@@ -0,0 +1,247 @@
+// HTTP(S) requests: the plumbing notifiers share.
+//
+// TLS is done by our connector, which verifies the server against a pinned CA: see `tls`.
+// reqwless only sees a plain connection.
+// "http://": no TLS. Only for a stand-in on your own network.
+
+use defmt;
+
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBuilder},
+};
+use embassy_net::{
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    semaphore::{GreedySemaphore, Semaphore},
+};
+use embassy_time::Duration;
+use embedded_nal_async::{Dns, TcpConnect};
+
+use crate::config;
+use crate::notifier::DELIVERY_MODE;
+use crate::retry::{Retryable, RetryPolicy};
+use crate::tls::TlsConnector;
+
+
+/// Most headers a request can have
+pub const MAX_HEADERS: usize = 8;
+
+// TLS sessions at once: 33K of heap each. The bot's long poll holds one; the sender gets the other.
+// More requests wait their turn.
+const TLS_SESSIONS: usize = 2;
+static SESSIONS: GreedySemaphore<CriticalSectionRawMutex> = GreedySemaphore::new(TLS_SESSIONS);
+
+/// A request
+pub struct Request<'a> {
+    pub method: Method,
+    pub url: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],  // Content-Type too
+    pub body: &'a [u8],
+}
+
+/// A response: the body borrows the buffer
+pub struct Response<'buf> {
+    pub status: u16,
+    pub retry_after: Option<u32>,  // Retry-After: seconds
+    pub body: &'buf [u8],
+}
+
+/// Why the request has failed
+#[derive(Debug)]
+pub enum HttpError {
+    ConnectError(reqwless::Error),  // DNS, TCP, TLS: the request hasn't been sent
+    RequestError(reqwless::Error),  // sending, or waiting for the response: it may have got through
+    CertificateRejected,  // not the server's certificate: someone's intercepting!
+}
+
+// reqwless' errors aren't defmt: see `Cargo.toml`
+impl defmt::Format for HttpError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            HttpError::ConnectError(e) => defmt::write!(f, "ConnectError({:?})", defmt::Debug2Format(e)),
+            HttpError::RequestError(e) => defmt::write!(f, "RequestError({:?})", defmt::Debug2Format(e)),
+            HttpError::CertificateRejected => defmt::write!(f, "CertificateRejected"),
+        }
+    }
+}
+
+
+/// Why a notifier's request has failed: it hasn't got through, or the server said no.
+/// Permanent: the server doesn't want it, or isn't who it says. Retrying won't help.
+/// Transient: network, 5xx, rate limits, timeouts.
+#[derive(Debug, defmt::Format)]
+pub enum DeliveryError {
+    Http(HttpError),
+    Status {  // the server said no
+        status: u16,
+        retry_after: Option<u32>,  // seconds
+    },
+}
+
+
+/// Is it an http(s) URL? `const`: check config with it.
+pub const fn is_http_url(url: &str) -> bool {
+    config::starts_with(url.as_bytes(), b"https://") || config::starts_with(url.as_bytes(), b"http://")
+}
+
+/// Check a notifier's URL from config. `const`: the build fails.
+pub const fn check_url(url: &str) -> &str {
+    if !is_http_url(url) {
+        panic!("Notifier URL: expected https:// or http://");
+    }
+    url
+}
+
+/// Retries for a notifier's requests: exponential backoff, from 2 s to a minute
+pub const fn retry_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        initial_delay: Duration::from_secs(2),
+        max_delay: Duration::from_secs(60),
+        mode: DELIVERY_MODE,
+    }
+}
+
+/// The server: TLS? And the host name, to verify its certificate
+pub fn server(url: &str) -> (bool, &str) {
+    let (tls, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (false, url.trim_start_matches("http://")),
+    };
+    let authority = rest.split(['/', '?']).next().unwrap_or(rest);
+    let authority = authority.rsplit('@').next().unwrap_or(authority);  // user:password@host
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(ipv6),  // [::1]:8081
+        None => authority.split(':').next().unwrap_or(authority),
+    };
+    (tls, host)
+}
+
+
+/// Send a request, read the response into `rx_buf`.
+/// `ca_cert`: DER certificate of the CA the server's certificate must chain up to.
+pub async fn request<'buf>(
+    stack: embassy_net::Stack<'_>,
+    req: &Request<'_>,
+    ca_cert: &[u8],
+    rx_buf: &'buf mut [u8],
+) -> Result<Response<'buf>, HttpError> {
+    let tcp_state = TcpClientState::<1, 4096, 4096>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+    let dns = crate::dns::Resolver::new(stack);  // prefers IPv6 when available
+    request_with(&tcp, &dns, req, ca_cert, crate::wifi::random_seed(), rx_buf).await
+}
+
+/// `request()` over any TCP connector and resolver.
+/// `seed`: random seed for the TLS handshake.
+pub async fn request_with<'buf, T: TcpConnect, D: Dns>(
+    tcp: &T,
+    dns: &D,
+    req: &Request<'_>,
+    ca_cert: &[u8],
+    seed: u64,
+    rx_buf: &'buf mut [u8],
+) -> Result<Response<'buf>, HttpError> {
+    // Init TLS.
+    // Quirks:
+    // 1. TLS recommends that the rx buffer is at least 16640 bytes long: because this is the size of the biggest packet (2^14+256).
+    // 2. By default, the `reqwless` crate uses `embedded-tls`, which is limited to algorithms that can run entirely on the stack.
+    //    To enable all algorithms, add the "alloc" feature.
+    //    To check whether the default list suffices with your server:
+    //    $ vopenssl s_client -tls1_3 -ciphersuites TLS_AES_128_GCM_SHA256 -sigalgs "ECDSA+SHA256:ECDSA+SHA384:ed25519" -connect api.telegram.org:443
+    // 3. On ESP32, you can further speed up TLS by using the RSA peripheral.
+    //    TODO: see `esp-mbedtls`
+    // 4. reqwless can't verify the server (`TlsVerify::None`): anyone on the WiFi path could pretend to be the server
+    //    and steal the credentials. So TLS is done by our connector, which verifies the certificate;
+    //    reqwless only sees a plain connection.
+    // 5. The buffers are on the heap: several tasks make requests at once, each would need 32K of its own.
+    //    So many at once: see `TLS_SESSIONS`.
+    match server(req.url) {
+        (true, host) => {
+            let _session = SESSIONS.acquire(1).await;
+            let (mut rx_buffer, mut tx_buffer) = (alloc::vec![0; 16640], alloc::vec![0; 16640]);
+            let tls = TlsConnector::new(tcp, host, ca_cert, seed, &mut rx_buffer, &mut tx_buffer);
+            let mut client = HttpClient::new(&tls, dns);
+            match send(&mut client, req, rx_buf).await {
+                Err(HttpError::ConnectError(_)) if tls.certificate_rejected() => Err(HttpError::CertificateRejected),
+                result => result,
+            }
+        }
+        // Plain HTTP: a stand-in
+        (false, _) => {
+            let mut client = HttpClient::new(tcp, dns);
+            send(&mut client, req, rx_buf).await
+        }
+    }
+}
+
+// Send it. Returns the HTTP status and the response body.
+async fn send<'buf, T: TcpConnect, D: Dns>(client: &mut HttpClient<'_, T, D>, req: &Request<'_>, rx_buf: &'buf mut [u8]) -> Result<Response<'buf>, HttpError> {
+    let handle = client.request(req.method, req.url).await
+        .map_err(HttpError::ConnectError)?;  // nothing's been sent yet
+    let mut handle = handle
+        .headers(req.headers)
+        .body(req.body);
+    let resp = handle.send(rx_buf)
+        .await?;
+
+    // Read response
+    let status = resp.status.0;
+    let retry_after = resp.headers()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))
+        .and_then(|(_, value)| core::str::from_utf8(value).ok()?.trim().parse().ok());
+    let body = resp.body().read_to_end()
+        .await?;
+    if !(200..300).contains(&status) {
+        defmt::debug!("HTTP {}: {}", status, core::str::from_utf8(body).unwrap_or("(binary)"));
+    }
+    Ok(Response { status, retry_after, body })
+}
+
+impl<'buf> Response<'buf> {
+    /// 2xx: it's got through. Otherwise the server said no: see `DeliveryError`.
+    pub fn success(self) -> Result<Response<'buf>, DeliveryError> {
+        match self.status {
+            200..=299 => Ok(self),
+            status => Err(DeliveryError::Status { status, retry_after: self.retry_after }),
+        }
+    }
+}
+
+impl Retryable for DeliveryError {
+    fn is_permanent(&self) -> bool {
+        match self {
+            DeliveryError::Http(HttpError::CertificateRejected) => true,
+            DeliveryError::Http(_) => false,
+            DeliveryError::Status { status, .. } => matches!(status, 400..=499) && !matches!(status, 408 | 429),
+        }
+    }
+
+    // The server may have got it: the response was lost
+    fn maybe_delivered(&self) -> bool {
+        matches!(self, DeliveryError::Http(HttpError::RequestError(_)))
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            DeliveryError::Status { retry_after: Some(secs), .. } => Some(Duration::from_secs(*secs as u64)),
+            _ => None,
+        }
+    }
+}
+
+// Auto-convert with From impls
+impl From<HttpError> for DeliveryError {
+    fn from(e: HttpError) -> Self {
+        DeliveryError::Http(e)
+    }
+}
+
+impl From<reqwless::Error> for HttpError {
+    fn from(e: reqwless::Error) -> Self {
+        HttpError::RequestError(e)
+    }
+}
@@ -20,6 +20,12 @@ pub enum LedState {
     RapidBlink,         // In Progress: HTTP sending
     Success,            // Result: Success
     Failure,            // Result: Error
+    Acknowledged,       // Result: someone's responded, help is coming
+    EscalationLevel1,   // Nobody's responded: notified again
+    EscalationLevel2,   // Nobody's responded: the escalation chat notified
+    Queued(u8),         // Offline, N messages waiting: N blinks, pause
+    Suppressed,         // Result: over the rate limit, not sent
+    ConfigError,        // The bot token's been rejected: 3 blinks, pause
     ViolentBlink,       // Error state (failing)
 }
 
@@ -44,8 +50,16 @@ pub async fn led_task(led: gpio::Output<'static>) {
     let mut led = ActiveLowLed{ pin: led };
     let mut current_state = LedState::PatientBlink;
     let mut persistent_state = LedState::PatientBlink;
+    let mut escalation: Option<LedState> = None;
 
     loop {
+        // Escalation: stays on, over the persistent states, till somebody acknowledges
+        match current_state {
+            LedState::EscalationLevel1 | LedState::EscalationLevel2 => escalation = Some(current_state),
+            LedState::Acknowledged => escalation = None,
+            _ => (),
+        }
+
         // Decide on the blinking pattern:
         // - on_duration: stay ON
         // - off_duration: stay OFF
@@ -56,21 +70,40 @@ pub async fn led_task(led: gpio::Output<'static>) {
             LedState::PatientBlink      => (Duration::from_millis( 500), Duration::from_millis(1000), true, None),
             LedState::RapidBlink        => (Duration::from_millis( 100), Duration::from_millis( 100), false, None),
             LedState::ViolentBlink      => (Duration::from_millis(  30), Duration::from_millis(  70), false, None),
+            LedState::EscalationLevel1  => (Duration::from_millis( 200), Duration::from_millis( 800), false, None),
+            LedState::EscalationLevel2  => (Duration::from_millis( 200), Duration::from_millis( 300), false, None),
+            LedState::Queued(_)         => (Duration::from_millis( 150), Duration::from_millis( 250), true, None),
+            LedState::ConfigError       => (Duration::from_millis( 100), Duration::from_millis( 150), true, None),
             // Temporary states
             LedState::Success           => (Duration::from_millis(3000), Duration::from_millis(   0), false, Some(Duration::from_secs(3))),
             LedState::Failure           => (Duration::from_millis(  30), Duration::from_millis(  70), false, Some(Duration::from_secs(3))),
+            LedState::Acknowledged      => (Duration::from_millis(1000), Duration::from_millis( 200), false, Some(Duration::from_secs(10))),
+            LedState::Suppressed        => (Duration::from_millis( 600), Duration::from_millis( 600), false, Some(Duration::from_millis(2400))),
         };
 
         // Remember the last persistent state
         if is_persistent {
             persistent_state = current_state;
+            if let Some(escalation) = escalation {
+                current_state = escalation;
+                continue;
+            }
         }
 
-        // Blink pattern
-        let pattern = [
-            (true, on_duration),
-            (false, off_duration),
-        ];
+        // Blink pattern. Counting? Blink N times, then pause.
+        let blinks = match current_state {
+            LedState::Queued(n) => n.clamp(1, 9),
+            LedState::ConfigError => 3,
+            _ => 1,
+        };
+        let mut pattern: heapless::Vec<(bool, Duration), 20> = heapless::Vec::new();
+        for _ in 0..blinks {
+            let _ = pattern.push((true, on_duration));
+            let _ = pattern.push((false, off_duration));
+        }
+        if blinks > 1 {
+            let _ = pattern.push((false, Duration::from_millis(1500)));
+        }
 
         // Hold the state?
         // Problem: some states are obviously persistent (i.e. WiFi state) whereas others are temporary:
@@ -82,21 +115,21 @@ pub async fn led_task(led: gpio::Output<'static>) {
             // Keep blinking until it's passed
             while delay_start.elapsed() < hold {
                 // Blink the whole pattern
-                for (state, dur) in pattern {
+                for &(state, dur) in &pattern {
                     led.set(state);
                     Timer::after(dur).await;
                 }
             }
 
             // Revert back to a persistent state
-            current_state = persistent_state;
+            current_state = escalation.unwrap_or(persistent_state);
             defmt::info!("LED reverted to {:?}", current_state);
             continue;
         }
 
         // Blink, but interrupt as soon as another signal comes.
         // This means LED state changes are responsive even mid-blink.
-        for (state, dur) in pattern {
+        for &(state, dur) in &pattern {
             led.set(state);
 
             // Sleep, but interrupt if a state change comes in
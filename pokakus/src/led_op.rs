@@ -6,6 +6,12 @@ use crate::led::{set_led_state, LedState};
 // - Outcome = Failure
 pub struct Status;
 
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Status {
     pub fn new() -> Self {
         set_led_state(LedState::RapidBlink);
@@ -1,8 +1,53 @@
 #![no_std]
+extern crate alloc;
 
 pub mod button;
+pub mod gesture;
 pub mod led;
 pub mod led_op;
 pub mod wifi;
+pub mod dns;
+pub mod dns_packet;
+pub mod diagnostics;
+pub mod clock;
+pub mod sntp;
+pub mod tls;
+pub mod retry;
+pub mod status;
+pub mod notifier;
+#[cfg(feature = "telegram")]
 pub mod telegram;
+#[cfg(feature = "telegram")]
+pub mod bot_api;
+#[cfg(feature = "telegram")]
+pub mod telegram_bot;
+#[cfg(feature = "telegram")]
+pub mod bot_identity;
+#[cfg(feature = "telegram")]
+pub mod auth;
+#[cfg(feature = "telegram")]
+pub mod ack;
+#[cfg(feature = "telegram")]
+pub mod undo;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "mqtt")]
+pub mod mqtt_packet;
+#[cfg(feature = "mqtt")]
+pub mod ha_discovery;
+#[cfg(feature = "ntfy")]
+pub mod ntfy;
+#[cfg(any(feature = "telegram", feature = "webhook", feature = "ntfy"))]
+pub mod http;
+pub mod outbox;
+pub mod coalesce;
+pub mod ratelimit;
+pub mod storage;
 pub mod make_static;
+pub mod config;
+pub mod text;
+pub mod event;
+pub mod template;
+pub mod recipient;
@@ -0,0 +1,577 @@
+// MQTT: a notifier, and more. For home automation.
+//
+// A connection to the broker, kept open by its own task. Under our topic, e.g. "pokakus":
+// - `event`: events, JSON; `message`: other messages, e.g. diagnostics, plain text. From the notifier.
+// - `action`: every gesture, "click", "double_click", "long_press": for Home Assistant's device triggers
+// - `status`: uptime, RSSI, counters, JSON. Retained; every MQTT_STATUS_SECONDS, and on request.
+// - `availability`: "online"; the last will says "offline". Retained.
+// - `command`: we listen. "status": publish the status now; "undo": undo the last message;
+//   "diagnostics": run network diagnostics, send the report. Who may: the broker's ACLs.
+// - Home Assistant discovery: see `ha_discovery`.
+// MQTT 3.1.1 or 5, plain or TLS: see `mqtt_packet`.
+//
+// Reads are never cancelled: a TLS record cut in half would break the connection.
+// So the broker isn't listened to all the time: it's polled, with a PINGREQ every MQTT_POLL_SECONDS.
+// Whatever it's sent meanwhile (commands) is read along with the PINGRESP.
+
+use defmt;
+use alloc::string::String;
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::fmt::Write as _;
+use core::net::SocketAddr;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+use embedded_tls::MaxFragmentLength;
+
+use crate::gesture::Gesture;
+use crate::config;
+use crate::ha_discovery;
+use crate::mqtt_packet::{self as packet, Connect, Malformed, Publish, Version, Will};
+use crate::notifier::{self, Capabilities, Notifier, DELIVERY_MODE};
+use crate::outbox::{Entry, OutgoingMessage};
+use crate::retry::{Retryable, RetryPolicy};
+use crate::template;
+use crate::tls::TlsConnector;
+
+
+// Broker: "mqtt://192.168.1.10:1883"; TLS: "mqtts://broker.example.com:8883"
+const URL: &str = check_url(env!("MQTT_URL"));
+
+const fn check_url(url: &str) -> &str {
+    if !config::starts_with(url.as_bytes(), b"mqtt://") && !config::starts_with(url.as_bytes(), b"mqtts://") {
+        panic!("MQTT URL: expected mqtt:// or mqtts://");
+    }
+    url
+}
+
+const VERSION: Version = Version::parse(option_env!("MQTT_PROTOCOL"));
+
+// Credentials. Empty: none.
+const USERNAME: Option<&str> = match option_env!("MQTT_USERNAME") {
+    Some(v) if !v.is_empty() => Some(v),
+    _ => None,
+};
+const PASSWORD: Option<&str> = match option_env!("MQTT_PASSWORD") {
+    Some(v) if !v.is_empty() => Some(v),
+    _ => None,
+};
+// MQTT 3.1.1 has no password without a username
+const _: () = assert!(PASSWORD.is_none() || USERNAME.is_some(), "MQTT_PASSWORD without MQTT_USERNAME");
+
+// Client id. Empty: the hostname.
+const CLIENT_ID: &str = match option_env!("MQTT_CLIENT_ID") {
+    Some(v) => v,
+    None => "",
+};
+
+// Our topic: everything goes under it
+const TOPIC: &str = match option_env!("MQTT_TOPIC") {
+    Some(v) if v.len() > 64 => panic!("MQTT topic: 64 bytes at most"),
+    Some(v) if !v.is_empty() => v,
+    _ => "pokakus",
+};
+
+// Home Assistant discovery. Empty: none.
+const DISCOVERY_PREFIX: &str = match option_env!("MQTT_DISCOVERY_PREFIX") {
+    Some(v) => v,
+    None => "homeassistant",
+};
+
+// Keep-alive: the broker gives up on us after 1.5 times this
+const KEEP_ALIVE_SECS: u32 = config::parse_u32(option_env!("MQTT_KEEPALIVE_SECONDS"), 60);
+
+// Poll the broker: for commands. Also keeps the connection alive.
+const POLL_SECS: u32 = config::parse_u32(option_env!("MQTT_POLL_SECONDS"), 2);
+const _: () = assert!(POLL_SECS > 0 && POLL_SECS < KEEP_ALIVE_SECS && KEEP_ALIVE_SECS <= 0xFFFF, "MQTT: expected 0 < poll < keep-alive < 65536 seconds");
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(config::parse_u32(option_env!("MQTT_STATUS_SECONDS"), 60) as u64);
+
+// Events: QoS 0 or 1
+const QOS: u8 = match config::parse_u32(option_env!("MQTT_QOS"), 1) {
+    0 => 0,
+    1 => 1,
+    _ => panic!("MQTT QoS: expected 0 or 1"),
+};
+
+// The CA we expect the broker's certificate to be issued by: DER file
+const CA_CERT: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", env!("MQTT_CA_CERT")));
+
+// Retries: exponential backoff. Not connected: wait for it.
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: config::parse_u32(option_env!("MQTT_RETRY_MAX_ATTEMPTS"), 5),
+    initial_delay: Duration::from_secs(2),
+    max_delay: Duration::from_secs(60),
+    mode: DELIVERY_MODE,
+};
+
+// Reconnect: exponential backoff
+const RECONNECT_MIN: Duration = Duration::from_secs(2);
+const RECONNECT_MAX: Duration = Duration::from_secs(120);
+
+// The broker should respond within this
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Incoming packets: bigger ones are dropped. Commands are short.
+const RX_BUF_SIZE: usize = 1024;
+
+// The biggest TLS record: 4K, see `session()`. And the record's overhead.
+const TLS_RECORD_SIZE: usize = 4096 + 256;
+
+// An event: JSON, see `template`
+const EVENT_PAYLOAD: &str = r#"{{"message":"{message}","gesture":"{gesture}","time":"{time}","count_today":{count_today},"event_id":"{event_id}"}}"#;
+
+
+// To the connection: from the notifier, and the button
+enum Outgoing {
+    Message { id: u32, topic: &'static str, payload: String },  // answered on `RESULT`
+    Action(Gesture),
+}
+
+static OUTGOING: Channel<CriticalSectionRawMutex, Outgoing, 4> = Channel::new();
+static RESULT: Signal<CriticalSectionRawMutex, (u32, Result<(), MqttError>)> = Signal::new();
+static CONNECTED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Connected to the broker?
+pub fn is_connected() -> bool {
+    CONNECTED.lock(|c| c.get())
+}
+
+/// A gesture: for Home Assistant's device triggers. Every gesture, whatever else it does.
+/// Not connected: dropped. A trigger is only good now.
+pub fn trigger(gesture: Gesture) {
+    if is_connected() && OUTGOING.try_send(Outgoing::Action(gesture)).is_err() {
+        defmt::warn!("MQTT: busy, {} not published", gesture.name());
+    }
+}
+
+// A topic under ours: "pokakus/status"
+fn topic(suffix: &str) -> heapless::String<80> {
+    let mut topic = heapless::String::new();
+    let _ = write!(topic, "{}/{}", TOPIC, suffix);  // fits: see `TOPIC`
+    topic
+}
+
+// Unique: "pokakus_a1b2c3d4e5f6"
+fn node_id() -> heapless::String<24> {
+    let mut id = heapless::String::new();
+    let _ = id.push_str("pokakus_");
+    for b in esp_hal::efuse::Efuse::mac_address() {
+        let _ = write!(id, "{:02x}", b);  // fits
+    }
+    id
+}
+
+// Broker: TLS? Host, port.
+fn broker() -> (bool, &'static str, u16) {
+    let (tls, rest) = match URL.strip_prefix("mqtts://") {
+        Some(rest) => (true, rest),
+        None => (false, URL.trim_start_matches("mqtt://")),
+    };
+    let rest = rest.trim_end_matches('/');
+    let (host, port) = match rest.strip_prefix('[') {
+        Some(ipv6) => match ipv6.split_once(']') {  // [::1]:1883
+            Some((host, tail)) => (host, tail.strip_prefix(':')),
+            None => (ipv6, None),
+        },
+        None => match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        },
+    };
+    let port = port.and_then(|p| p.parse().ok()).unwrap_or(if tls { 8883 } else { 1883 });
+    (tls, host, port)
+}
+
+
+/// MQTT: a notifier, see `notifier`.
+/// Publishes through the connection: see `task_mqtt()`.
+#[derive(Default)]
+pub struct MqttNotifier;
+
+impl Notifier for MqttNotifier {
+    type Error = MqttError;
+
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { replies: false, undo: false }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RETRY_POLICY
+    }
+
+    async fn send(&mut self, _stack: embassy_net::Stack<'_>, entry: &Entry) -> Result<(), MqttError> {
+        if !is_connected() {
+            return Err(MqttError::NotConnected);
+        }
+
+        // Events: JSON. The rest: plain text.
+        let (topic, payload) = match entry.message {
+            OutgoingMessage::Event { .. } => {
+                let message = notifier::plain_text(entry);
+                let event_id = notifier::event_id(entry);
+                let ctx = template::Context {
+                    json: true,
+                    message: Some(&message),
+                    event_id: Some(&event_id),
+                    ..notifier::context(entry, None)
+                };
+                let mut payload = String::new();
+                let _ = template::render(&mut payload, EVENT_PAYLOAD, &ctx);  // String never fails
+                ("event", payload)
+            }
+            _ => ("message", notifier::plain_text(entry)),
+        };
+
+        // Hand it to the connection, wait for the outcome
+        RESULT.reset();
+        let outcome = with_timeout(RESPONSE_TIMEOUT * 2, async {
+            OUTGOING.send(Outgoing::Message { id: entry.id, topic, payload }).await;
+            loop {
+                match RESULT.wait().await {
+                    (id, result) if id == entry.id => return result,
+                    _ => (),  // an earlier message's: it's timed out
+                }
+            }
+        }).await;
+        match outcome {
+            Ok(Ok(())) => {
+                defmt::info!("MQTT: published to {}", topic);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(MqttError::Timeout),
+        }
+    }
+}
+
+
+/// Task: stay connected to the broker
+#[embassy_executor::task]
+pub async fn task_mqtt(stack: embassy_net::Stack<'static>) {
+    if !notifier::is_enabled("mqtt") {
+        return;
+    }
+    let mut backoff = RECONNECT_MIN;
+    loop {
+        stack.wait_config_up().await;
+        let e = session(stack).await;
+        let was_connected = is_connected();
+        CONNECTED.lock(|c| c.set(false));
+        defmt::warn!("MQTT: disconnected: {:?}. Reconnecting in {} s", e, backoff.as_secs());
+        if was_connected {
+            backoff = RECONNECT_MIN;
+        }
+        Timer::after(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+// Connect, and stay connected. Returns why it's over.
+async fn session(stack: embassy_net::Stack<'_>) -> MqttError {
+    let (tls, host, port) = broker();
+    let dns = crate::dns::Resolver::new(stack);  // prefers IPv6 when available
+    let Ok(ip) = dns.get_host_by_name(host, AddrType::Either).await else {
+        return MqttError::Dns;
+    };
+    let remote = SocketAddr::new(ip, port);
+
+    // TCP; TLS on top, see `tls`. The buffers are on the heap, for as long as we're connected:
+    // 4K records, rather than 16K. The broker must support max_fragment_length: mosquitto does, with OpenSSL.
+    let tcp_state = TcpClientState::<1, 2048, 2048>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+    if tls {
+        let (mut rx_buffer, mut tx_buffer) = (alloc::vec![0; TLS_RECORD_SIZE], alloc::vec![0; TLS_RECORD_SIZE]);
+        let connector = TlsConnector::new(&tcp, host, CA_CERT, crate::wifi::random_seed(), &mut rx_buffer, &mut tx_buffer)
+            .with_max_fragment_length(MaxFragmentLength::Bits12);
+        match connector.connect(remote).await {
+            Ok(mut conn) => run(&mut conn).await,
+            Err(_) if connector.certificate_rejected() => MqttError::CertificateRejected,
+            Err(_) => MqttError::ConnectError,
+        }
+    } else {
+        match tcp.connect(remote).await {
+            Ok(mut conn) => run(&mut conn).await,
+            Err(_) => MqttError::ConnectError,
+        }
+    }
+}
+
+async fn run<C: Read + Write>(conn: &mut C) -> MqttError {
+    let mut client = Client { conn, rx: alloc::vec![0; RX_BUF_SIZE], next_id: 0, commands: heapless::Deque::new() };
+    let Err(e) = client.serve().await;
+    e
+}
+
+
+// Commands: from the command topic
+#[derive(defmt::Format, Clone, Copy)]
+enum Command {
+    Status,
+    Undo,
+    Diagnostics,
+}
+
+impl Command {
+    fn parse(payload: &[u8]) -> Option<Command> {
+        match payload.trim_ascii() {
+            b"status" => Some(Command::Status),
+            b"undo" => Some(Command::Undo),
+            b"diagnostics" => Some(Command::Diagnostics),
+            _ => None,
+        }
+    }
+}
+
+// The connection: one session
+struct Client<'c, C> {
+    conn: &'c mut C,
+    rx: alloc::vec::Vec<u8>,
+    next_id: u16,
+    commands: heapless::Deque<Command, 4>,  // received while waiting for something else
+}
+
+impl<C: Read + Write> Client<'_, C> {
+    // Connect; announce ourselves; then publish, and poll
+    async fn serve(&mut self) -> Result<Infallible, MqttError> {
+        self.connect().await?;
+        CONNECTED.lock(|c| c.set(true));
+        defmt::info!("MQTT: connected to {}", broker().1);
+
+        self.publish(&topic("availability"), b"online", 1, true).await?;
+        if !DISCOVERY_PREFIX.is_empty() {
+            for (config_topic, payload) in ha_discovery::configs(DISCOVERY_PREFIX, TOPIC, &node_id(), crate::wifi::hostname()) {
+                self.publish(&config_topic, payload.as_bytes(), 1, true).await?;
+            }
+        }
+        self.subscribe(&topic("command")).await?;
+        self.publish_status().await?;
+
+        let mut next_status = Instant::now() + STATUS_INTERVAL;
+        loop {
+            while let Some(command) = self.commands.pop_front() {
+                self.run_command(command).await?;
+            }
+
+            let poll = Instant::now() + Duration::from_secs(POLL_SECS as u64);
+            match select(OUTGOING.receive(), Timer::at(poll.min(next_status))).await {
+                Either::First(Outgoing::Message { id, topic: suffix, payload }) => {
+                    let result = self.publish(&topic(suffix), payload.as_bytes(), QOS, false).await;
+                    RESULT.signal((id, result));
+                    result?;
+                }
+                Either::First(Outgoing::Action(gesture)) => {
+                    self.publish(&topic("action"), ha_discovery::action(gesture).as_bytes(), 0, false).await?;
+                }
+                Either::Second(()) if Instant::now() >= next_status => {
+                    self.publish_status().await?;
+                    next_status = Instant::now() + STATUS_INTERVAL;
+                }
+                Either::Second(()) => self.ping().await?,
+            }
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), MqttError> {
+        let will_topic = topic("availability");
+        let connect = Connect {
+            client_id: if CLIENT_ID.is_empty() { crate::wifi::hostname() } else { CLIENT_ID },
+            keep_alive_secs: KEEP_ALIVE_SECS as u16,
+            username: USERNAME,
+            password: PASSWORD,
+            will: Some(Will { topic: &will_topic, payload: b"offline", retain: true }),
+        };
+        self.write(&packet::connect(VERSION, &connect)).await?;
+        let len = self.wait_for(packet::CONNACK, None).await?;
+        match packet::connack(&self.rx[..len])? {
+            0 => Ok(()),
+            code => {
+                defmt::error!("MQTT: connection refused: {}. Check MQTT_USERNAME and MQTT_PASSWORD", code);
+                Err(MqttError::Refused(code))
+            }
+        }
+    }
+
+    async fn publish(&mut self, topic: &str, payload: &[u8], qos: u8, retain: bool) -> Result<(), MqttError> {
+        let packet_id = self.packet_id();
+        self.write(&packet::publish(VERSION, &Publish { topic, payload, qos, retain, packet_id })).await?;
+        if qos == 0 {
+            return Ok(());
+        }
+        let len = self.wait_for(packet::PUBACK, Some(packet_id)).await?;
+        match packet::puback_reason(&self.rx[..len]) {
+            0..0x80 => Ok(()),
+            code => Err(MqttError::Rejected(code)),
+        }
+    }
+
+    async fn subscribe(&mut self, filter: &str) -> Result<(), MqttError> {
+        let packet_id = self.packet_id();
+        self.write(&packet::subscribe(VERSION, packet_id, filter)).await?;
+        let len = self.wait_for(packet::SUBACK, Some(packet_id)).await?;
+        match packet::suback_code(VERSION, &self.rx[..len])? {
+            0..0x80 => Ok(()),
+            code => Err(MqttError::Rejected(code)),
+        }
+    }
+
+    // Poll: and keep the connection alive
+    async fn ping(&mut self) -> Result<(), MqttError> {
+        self.write(&packet::pingreq()).await?;
+        self.wait_for(packet::PINGRESP, None).await?;
+        Ok(())
+    }
+
+    // Status: JSON, retained
+    async fn publish_status(&mut self) -> Result<(), MqttError> {
+        let counters = crate::status::counters();
+        let mut status = String::new();
+        let _ = write!(status, r#"{{"uptime":{},"rssi":"#, Instant::now().as_secs());  // String never fails
+        let _ = match crate::wifi::rssi() {
+            Some(rssi) => write!(status, "{}", rssi),
+            None => write!(status, "null"),
+        };
+        let _ = write!(
+            status,
+            r#","sent":{},"retries":{},"gave_up":{},"expired":{},"suppressed":{},"queued":{}}}"#,
+            counters.sent, counters.retries, counters.gave_up, counters.expired, counters.suppressed, crate::outbox::pending_count(),
+        );
+        self.publish(&topic("status"), status.as_bytes(), 0, true).await
+    }
+
+    async fn run_command(&mut self, command: Command) -> Result<(), MqttError> {
+        defmt::info!("MQTT: command: {:?}", command);
+        match command {
+            Command::Status => self.publish_status().await?,
+            Command::Undo => notifier::undo_last(),
+            Command::Diagnostics => crate::diagnostics::request_diagnostics(crate::diagnostics::ReportTo::Message),
+        }
+        Ok(())
+    }
+
+    // Packet ids: 1 and up, 0 isn't allowed
+    fn packet_id(&mut self) -> u16 {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.next_id
+    }
+
+    async fn write(&mut self, packet: &[u8]) -> Result<(), MqttError> {
+        self.conn.write_all(packet).await.map_err(|_| MqttError::Io)?;
+        self.conn.flush().await.map_err(|_| MqttError::Io)
+    }
+
+    // Read packets until this one comes: type, and packet id.
+    // Meanwhile, the broker's publishes: commands. Returns the body's length, in `rx`.
+    async fn wait_for(&mut self, kind: u8, packet_id: Option<u16>) -> Result<usize, MqttError> {
+        let wait = async {
+            loop {
+                let (header, len) = self.read_packet().await?;
+                let Some(len) = len else { continue };  // too big: dropped
+                match header >> 4 {
+                    packet::PUBLISH => self.on_publish(header, len).await?,
+                    t if t == kind && packet_id.is_none_or(|id| packet::packet_id(&self.rx[..len]).ok() == Some(id)) => return Ok(len),
+                    _ => (),  // not what we're waiting for: e.g. a late ack
+                }
+            }
+        };
+        with_timeout(RESPONSE_TIMEOUT, wait).await.unwrap_or(Err(MqttError::Timeout))
+    }
+
+    // Read a packet: the first byte; the body, into `rx`.
+    // Too big for `rx`: read, and dropped (`None`).
+    async fn read_packet(&mut self) -> Result<(u8, Option<usize>), MqttError> {
+        let mut byte = [0u8];
+        self.conn.read_exact(&mut byte).await.map_err(|_| MqttError::Io)?;
+        let header = byte[0];
+        let mut value = 0;
+        let mut index = 0;
+        let len = loop {
+            self.conn.read_exact(&mut byte).await.map_err(|_| MqttError::Io)?;
+            if let Some(len) = packet::varint_step(&mut value, index, byte[0])? {
+                break len;
+            }
+            index += 1;
+        };
+
+        if len > self.rx.len() {
+            defmt::warn!("MQTT: {} bytes, too big: dropped", len);
+            let mut left = len;
+            while left > 0 {
+                let n = left.min(self.rx.len());
+                self.conn.read_exact(&mut self.rx[..n]).await.map_err(|_| MqttError::Io)?;
+                left -= n;
+            }
+            return Ok((header, None));
+        }
+        self.conn.read_exact(&mut self.rx[..len]).await.map_err(|_| MqttError::Io)?;
+        Ok((header, Some(len)))
+    }
+
+    // The broker's PUBLISH: a command? Acknowledge it.
+    async fn on_publish(&mut self, header: u8, len: usize) -> Result<(), MqttError> {
+        let publish = packet::parse_publish(VERSION, header, &self.rx[..len])?;
+        let (qos, packet_id) = (publish.qos, publish.packet_id);
+        if publish.topic == topic("command").as_str() {
+            match Command::parse(publish.payload) {
+                Some(command) => {
+                    if self.commands.push_back(command).is_err() {
+                        defmt::warn!("MQTT: too many commands: {:?} dropped", command);
+                    }
+                }
+                None => defmt::warn!("MQTT: unknown command: {}", core::str::from_utf8(publish.payload).unwrap_or("(binary)")),
+            }
+        }
+        if qos > 0 {
+            self.write(&packet::puback(packet_id)).await?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Why MQTT has failed
+#[derive(Debug, defmt::Format, Clone, Copy)]
+pub enum MqttError {
+    NotConnected,  // nothing's been sent
+    Dns,
+    ConnectError,  // TCP, TLS
+    CertificateRejected,  // not the broker's certificate: someone's intercepting!
+    Io,  // the connection's broken
+    Timeout,  // no response
+    Protocol,  // the broker's made no sense
+    Refused(u8),  // CONNACK: bad credentials, not authorized, ...
+    Rejected(u8),  // PUBACK, SUBACK: not authorized, ...
+}
+
+// Permanent: the broker doesn't allow it. Retrying won't help.
+// Transient: not connected yet, the connection's broken.
+impl Retryable for MqttError {
+    fn is_permanent(&self) -> bool {
+        matches!(self, MqttError::CertificateRejected | MqttError::Refused(_) | MqttError::Rejected(_))
+    }
+
+    // Sent, but not acknowledged: the broker may have got it
+    fn maybe_delivered(&self) -> bool {
+        matches!(self, MqttError::Io | MqttError::Timeout | MqttError::Protocol)
+    }
+}
+
+// Auto-convert with From impls
+impl From<Malformed> for MqttError {
+    fn from(_: Malformed) -> Self {
+        MqttError::Protocol
+    }
+}
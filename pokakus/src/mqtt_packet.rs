@@ -0,0 +1,249 @@
+// MQTT packets: encoding and decoding. Just the subset `mqtt` needs.
+//
+// MQTT 3.1.1 and 5. Version 5 adds properties to most packets: we send none, and skip those we get.
+// QoS 0 and 1. No QoS 2: nothing here needs exactly-once.
+//
+// A packet: a fixed header (type and flags; the remaining length, a varint), then the body.
+
+use alloc::vec::Vec;
+
+
+/// Protocol version
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Version {
+    V311,
+    V5,
+}
+
+impl Version {
+    /// Parse config: "3.1.1" (default) or "5"
+    pub const fn parse(value: Option<&str>) -> Version {
+        match value {
+            None => Version::V311,
+            Some(v) if v.is_empty() || crate::config::eq(v.as_bytes(), b"3.1.1") => Version::V311,
+            Some(v) if crate::config::eq(v.as_bytes(), b"5") => Version::V5,
+            Some(_) => panic!("MQTT protocol: expected 3.1.1 or 5"),
+        }
+    }
+
+    // The protocol level byte, in CONNECT
+    fn level(self) -> u8 {
+        match self {
+            Version::V311 => 4,
+            Version::V5 => 5,
+        }
+    }
+}
+
+/// Packet types: the high nibble of the first byte
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PUBACK: u8 = 4;
+pub const SUBACK: u8 = 9;
+pub const PINGRESP: u8 = 13;
+
+/// Malformed packet
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub struct Malformed;
+
+/// The last will: published by the broker when we're gone
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+/// CONNECT
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_secs: u16,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub will: Option<Will<'a>>,
+}
+
+/// PUBLISH: ours, or the broker's
+pub struct Publish<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: u8,
+    pub retain: bool,
+    pub packet_id: u16,  // QoS 1 only
+}
+
+
+/// CONNECT: clean session, QoS 1 will
+pub fn connect(v: Version, c: &Connect) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, b"MQTT");
+    body.push(v.level());
+    let mut flags = 0x02;  // clean session
+    if let Some(will) = &c.will {
+        flags |= 0x04 | 0x08 | if will.retain { 0x20 } else { 0 };  // will, QoS 1, retain
+    }
+    if c.username.is_some() {
+        flags |= 0x80;
+    }
+    if c.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&c.keep_alive_secs.to_be_bytes());
+    put_properties(v, &mut body);
+
+    put_str(&mut body, c.client_id.as_bytes());
+    if let Some(will) = &c.will {
+        put_properties(v, &mut body);
+        put_str(&mut body, will.topic.as_bytes());
+        put_str(&mut body, will.payload);
+    }
+    if let Some(username) = c.username {
+        put_str(&mut body, username.as_bytes());
+    }
+    if let Some(password) = c.password {
+        put_str(&mut body, password.as_bytes());
+    }
+    packet(0x10, &body)
+}
+
+/// PUBLISH
+pub fn publish(v: Version, p: &Publish) -> Vec<u8> {
+    let mut body = Vec::with_capacity(p.topic.len() + p.payload.len() + 8);
+    put_str(&mut body, p.topic.as_bytes());
+    if p.qos > 0 {
+        body.extend_from_slice(&p.packet_id.to_be_bytes());
+    }
+    put_properties(v, &mut body);
+    body.extend_from_slice(p.payload);
+    packet(0x30 | ((p.qos & 0x03) << 1) | (p.retain as u8), &body)
+}
+
+/// PUBACK: for the broker's QoS 1 PUBLISH
+pub fn puback(packet_id: u16) -> Vec<u8> {
+    packet(0x40, &packet_id.to_be_bytes())  // MQTT 5 too: no reason code means success
+}
+
+/// SUBSCRIBE: one topic filter, QoS 1
+pub fn subscribe(v: Version, packet_id: u16, filter: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&packet_id.to_be_bytes());
+    put_properties(v, &mut body);
+    put_str(&mut body, filter.as_bytes());
+    body.push(0x01);  // QoS 1
+    packet(0x82, &body)
+}
+
+/// PINGREQ
+pub fn pingreq() -> Vec<u8> {
+    packet(0xC0, &[])
+}
+
+
+/// CONNACK: the return code (MQTT 5: the reason code). 0: accepted.
+pub fn connack(body: &[u8]) -> Result<u8, Malformed> {
+    body.get(1).copied().ok_or(Malformed)
+}
+
+/// PUBACK, SUBACK: the packet id
+pub fn packet_id(body: &[u8]) -> Result<u16, Malformed> {
+    take_u16(body)
+}
+
+/// PUBACK: the reason code. Below 0x80: success. MQTT 3.1.1 has none: success.
+pub fn puback_reason(body: &[u8]) -> u8 {
+    body.get(2).copied().unwrap_or(0)
+}
+
+/// SUBACK: the granted QoS (MQTT 5: the reason code). 0x80 and above: refused.
+pub fn suback_code(v: Version, body: &[u8]) -> Result<u8, Malformed> {
+    let mut rest = body.get(2..).ok_or(Malformed)?;
+    if v == Version::V5 {
+        rest = skip_properties(rest)?;
+    }
+    rest.first().copied().ok_or(Malformed)
+}
+
+/// PUBLISH from the broker. `header`: the first byte, for QoS and retain.
+pub fn parse_publish(v: Version, header: u8, body: &[u8]) -> Result<Publish<'_>, Malformed> {
+    let (topic, mut rest) = take_str(body)?;
+    let qos = (header >> 1) & 0x03;
+    let mut packet_id = 0;
+    if qos > 0 {
+        packet_id = self::packet_id(rest)?;
+        rest = &rest[2..];
+    }
+    if v == Version::V5 {
+        rest = skip_properties(rest)?;
+    }
+    let topic = core::str::from_utf8(topic).map_err(|_| Malformed)?;
+    Ok(Publish { topic, payload: rest, qos, retain: header & 0x01 != 0, packet_id })
+}
+
+/// Remaining length: decode one more byte of it.
+/// `Ok(None)`: more bytes follow.
+pub fn varint_step(value: &mut usize, index: usize, byte: u8) -> Result<Option<usize>, Malformed> {
+    if index >= 4 {
+        return Err(Malformed);
+    }
+    *value |= ((byte & 0x7F) as usize) << (7 * index);
+    Ok((byte & 0x80 == 0).then_some(*value))
+}
+
+
+// A packet: fixed header, remaining length, body
+fn packet(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = Vec::with_capacity(body.len() + 5);
+    p.push(first);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        p.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    p.extend_from_slice(body);
+    p
+}
+
+// String or binary data: length-prefixed
+fn put_str(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+fn take_str(data: &[u8]) -> Result<(&[u8], &[u8]), Malformed> {
+    let len = take_u16(data)? as usize;
+    let rest = &data[2..];
+    if rest.len() < len {
+        return Err(Malformed);
+    }
+    Ok(rest.split_at(len))
+}
+
+// Two bytes, big-endian
+fn take_u16(data: &[u8]) -> Result<u16, Malformed> {
+    match data {
+        [hi, lo, ..] => Ok(u16::from_be_bytes([*hi, *lo])),
+        _ => Err(Malformed),
+    }
+}
+
+// MQTT 5: no properties
+fn put_properties(v: Version, out: &mut Vec<u8>) {
+    if v == Version::V5 {
+        out.push(0);
+    }
+}
+
+// MQTT 5: skip the properties, whatever they are
+fn skip_properties(data: &[u8]) -> Result<&[u8], Malformed> {
+    let mut len = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if let Some(len) = varint_step(&mut len, i, byte)? {
+            return data.get(i + 1 + len..).ok_or(Malformed);
+        }
+    }
+    Err(Malformed)
+}
@@ -0,0 +1,421 @@
+// Notifiers: where messages go. Telegram is one of them.
+//
+// Button and LED code queue messages here; they don't know who's going to deliver them.
+// The sender takes them from the outbox and hands each one to every enabled backend:
+// - a backend is compiled in with its cargo feature: "telegram", "webhook", "mqtt", "ntfy", ...
+// - and enabled by config: `NOTIFIERS`, a comma-separated list. Empty: all that are compiled in.
+// The generic part: the queue, coalescing, delivery semantics, retries, the LED.
+// The backend: how to send, what it can do, which errors are worth retrying.
+
+use defmt;
+use alloc::{borrow::Cow, string::String};
+use core::fmt::Write;
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::coalesce;
+use crate::config;
+use crate::event::Event;
+use crate::outbox::{self, Entry, OutgoingMessage};
+use crate::ratelimit;
+use crate::recipient::{ChatId, Recipient};
+use crate::retry::{DeliveryMode, Retryable, RetryPolicy};
+use crate::template;
+use crate::text::ParseMode;
+
+
+/// A send has failed, but it may have got through: send it again, or not?
+/// At least once: every message carries an event id, so duplicates can be told apart.
+pub const DELIVERY_MODE: DeliveryMode = DeliveryMode::parse(option_env!("DELIVERY_MODE"));
+
+// At least once: a message that hasn't got through, tried again after this long
+const REDELIVERY_DELAY: Duration = Duration::from_secs(60);
+
+// Enabled backends. Empty: all compiled in.
+const NOTIFIERS: &str = match option_env!("NOTIFIERS") {
+    Some(v) => v,
+    None => "",
+};
+
+/// Is this backend enabled by config?
+pub const fn is_enabled(name: &str) -> bool {
+    NOTIFIERS.is_empty() || config::list_contains(NOTIFIERS, name)
+}
+
+
+/// Send a message
+pub fn send_text(text: &str) {
+    // We only got a reference. To take ownership, we need a copy.
+    // On the heap: messages can be long, but most of them are short.
+    outbox::queue(OutgoingMessage::Text(String::from(text)));
+}
+
+/// Send a message about an event.
+/// The template is rendered right before sending: see `template`.
+/// Over the rate limit? Not sent: see `ratelimit`.
+pub fn send_event(template: &'static str, event: Event) {
+    if !ratelimit::allow_event() {
+        defmt::warn!("Rate limit: {} suppressed", event.gesture.name());
+        crate::status::update_counters(|c| c.suppressed += 1);
+        crate::led::set_led_state(crate::led::LedState::Suppressed);
+        return;
+    }
+    outbox::queue(OutgoingMessage::Event { template: Cow::Borrowed(template), event });
+}
+
+/// Reply to a chat: e.g. to a bot command.
+/// Goes to this chat only, as plain text. By numeric id: that's what the outbox keeps.
+pub fn send_reply(to: Recipient, text: String) {
+    if let ChatId::Username(name) = to.chat_id {
+        defmt::error!("Reply to {}: not a chat id, not sent", name);
+        return;
+    }
+    outbox::queue(OutgoingMessage::Reply { to, text });
+}
+
+/// Undo the last event message: with the backends that can
+pub fn undo_last() {
+    outbox::queue(OutgoingMessage::Undo);
+}
+
+/// A backend is misconfigured: e.g. a rejected token. The LED keeps showing it.
+pub fn is_misconfigured() -> bool {
+    #[cfg(feature = "telegram")]
+    if crate::bot_identity::is_unauthorized() {
+        return true;
+    }
+    false
+}
+
+
+/// What a backend can do
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Capabilities {
+    pub replies: bool,  // answer a chat: bot commands
+    pub undo: bool,     // retract a message once it's sent
+}
+
+impl Capabilities {
+    /// Is the message for this backend at all?
+    pub fn accepts(&self, message: &OutgoingMessage) -> bool {
+        match message {
+            OutgoingMessage::Text(_) | OutgoingMessage::Event { .. } => true,
+            OutgoingMessage::Reply { .. } => self.replies,
+            OutgoingMessage::Undo => self.undo,
+        }
+    }
+}
+
+/// A backend: delivers messages somewhere.
+/// Errors tell whether they're worth retrying: see `Retryable`.
+// Single-threaded executor: the futures needn't be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Notifier {
+    type Error: Retryable + defmt::Format;
+
+    /// For logs and config: "telegram"
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// How to retry `send()`. A backend that retries on its own: one attempt.
+    fn retry_policy(&self) -> RetryPolicy;
+
+    /// Send a message: one attempt
+    async fn send(&mut self, stack: embassy_net::Stack<'_>, entry: &Entry) -> Result<(), Self::Error>;
+
+    /// Something to do later: e.g. escalate. `None`: nothing.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// The deadline has come
+    async fn on_deadline(&mut self, _stack: embassy_net::Stack<'_>) {}
+}
+
+/// What's become of a message: with a backend, or all of them. Worst last.
+#[derive(defmt::Format, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Delivery {
+    Delivered,  // or not for this backend
+    Rejected,   // failed for good: trying again won't help
+    Failed,     // worth trying again later
+}
+
+/// Deliver a message through a backend: retry what's worth retrying
+pub async fn deliver<N: Notifier>(notifier: &mut N, stack: embassy_net::Stack<'_>, entry: &Entry) -> Delivery {
+    if !notifier.capabilities().accepts(&entry.message) {
+        return Delivery::Delivered;
+    }
+    let policy = notifier.retry_policy();
+    let name = notifier.name();
+    let result = policy.run(
+        async || notifier.send(stack, entry).await,
+        |_, _| crate::status::update_counters(|c| c.retries += 1),
+    ).await;
+    match result {
+        Ok(()) => Delivery::Delivered,
+        Err(e) if e.is_permanent() => {
+            defmt::error!("{}: rejected: {:?}", name, e);
+            Delivery::Rejected
+        }
+        Err(e) => {
+            defmt::error!("{}: failed: {:?}", name, e);
+            Delivery::Failed
+        }
+    }
+}
+
+/// The event id: "1a2b3c". The same on every attempt: duplicates can be told apart.
+pub fn event_id(entry: &Entry) -> heapless::String<8> {
+    let mut id = heapless::String::new();
+    let _ = write!(id, "{:06x}", entry.id & 0xFF_FFFF);  // fits
+    id
+}
+
+/// The event id, as shown in messages: "#ev1a2b3c".
+/// Only at least once: that's when duplicates are possible.
+pub fn event_tag(entry: &Entry) -> Option<heapless::String<16>> {
+    if DELIVERY_MODE != DeliveryMode::AtLeastOnce {
+        return None;
+    }
+    let mut tag = heapless::String::new();
+    let _ = write!(tag, "#ev{}", event_id(entry));  // fits
+    Some(tag)
+}
+
+/// Placeholder values for a message: see `template`
+pub fn context<'a>(entry: &'a Entry, parse_mode: Option<ParseMode>) -> template::Context<'a> {
+    let event = match &entry.message {
+        OutgoingMessage::Event { event, .. } => Some(event),
+        _ => None,
+    };
+    template::Context {
+        parse_mode,
+        json: false,
+        event,
+        hostname: crate::wifi::hostname(),
+        device_id: esp_hal::efuse::Efuse::mac_address(),
+        rssi: crate::wifi::rssi(),
+        message: None,
+        event_id: None,
+    }
+}
+
+/// The message as plain text: the template rendered, the burst appended.
+/// For backends without markup.
+pub fn plain_text(entry: &Entry) -> String {
+    match &entry.message {
+        OutgoingMessage::Text(text) | OutgoingMessage::Reply { text, .. } => text.clone(),
+        OutgoingMessage::Undo => String::new(),
+        OutgoingMessage::Event { template, event } => {
+            let mut text = String::new();
+            let _ = template::render(&mut text, template, &context(entry, None));  // String never fails
+            if let Some(burst) = &entry.burst {
+                let _ = coalesce::write_burst(&mut text, burst, event, None);
+            }
+            text
+        }
+    }
+}
+
+
+// Every enabled backend, in turn
+macro_rules! each_backend {
+    ($backends:expr, |$n:ident| $body:expr) => {{
+        #[cfg(feature = "telegram")]
+        if let Some($n) = $backends.telegram.as_mut() {
+            $body;
+        }
+        #[cfg(feature = "webhook")]
+        if let Some($n) = $backends.webhook.as_mut() {
+            $body;
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some($n) = $backends.mqtt.as_mut() {
+            $body;
+        }
+        #[cfg(feature = "ntfy")]
+        if let Some($n) = $backends.ntfy.as_mut() {
+            $body;
+        }
+    }};
+}
+
+// The backends: compiled in, and enabled
+struct Backends {
+    #[cfg(feature = "telegram")]
+    telegram: Option<crate::telegram::TelegramNotifier>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<crate::webhook::WebhookNotifier>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<crate::mqtt::MqttNotifier>,
+    #[cfg(feature = "ntfy")]
+    ntfy: Option<crate::ntfy::NtfyNotifier>,
+}
+
+impl Backends {
+    fn new() -> Backends {
+        let mut backends = Backends {
+            #[cfg(feature = "telegram")]
+            telegram: is_enabled("telegram").then(crate::telegram::TelegramNotifier::default),
+            #[cfg(feature = "webhook")]
+            webhook: is_enabled("webhook").then(crate::webhook::WebhookNotifier::default),
+            #[cfg(feature = "mqtt")]
+            mqtt: is_enabled("mqtt").then(crate::mqtt::MqttNotifier::default),
+            #[cfg(feature = "ntfy")]
+            ntfy: is_enabled("ntfy").then(crate::ntfy::NtfyNotifier::default),
+        };
+        #[allow(unused_mut)]
+        let mut count = 0;
+        each_backend!(backends, |n| {
+            defmt::info!("Notifier: {}", n.name());
+            count += 1;
+        });
+        if count == 0 {
+            defmt::error!("Notifier: none enabled! Check NOTIFIERS and cargo features");
+        }
+        backends
+    }
+
+    // The earliest deadline
+    fn deadline(&mut self) -> Option<Instant> {
+        #[allow(unused_mut)]
+        let mut earliest: Option<Instant> = None;
+        each_backend!(self, |n| earliest = earliest_of(earliest, n.deadline()));
+        earliest
+    }
+
+    async fn on_deadline(&mut self, _stack: embassy_net::Stack<'_>) {
+        each_backend!(self, |n| {
+            if n.deadline().is_some_and(|d| d <= Instant::now()) {
+                n.on_deadline(_stack).await;
+            }
+        });
+    }
+
+    // Every backend that's still to have it: `done` are those that have, or have turned it down.
+    // One LED outcome for all: replies don't get one, nobody's pressed the button.
+    async fn send(&mut self, _stack: embassy_net::Stack<'_>, entry: &Entry, _done: &mut heapless::Vec<&'static str, 4>) -> Delivery {
+        let led_status = match entry.message {
+            OutgoingMessage::Reply { .. } => None,
+            _ => Some(crate::led_op::Status::new()),
+        };
+        #[allow(unused_mut)]
+        let mut outcome = Delivery::Delivered;
+        each_backend!(self, |n| {
+            if !_done.contains(&n.name()) {
+                let delivery = deliver(n, _stack, entry).await;
+                if delivery != Delivery::Failed {
+                    let _ = _done.push(n.name());  // one per backend: fits
+                }
+                if delivery > outcome {
+                    outcome = delivery;
+                }
+            }
+        });
+        match (led_status, outcome) {
+            (Some(led), Delivery::Delivered) => led.success(),
+            (Some(led), _) => led.failure(),
+            (None, _) => (),
+        }
+        outcome
+    }
+}
+
+
+// The earlier of two deadlines
+fn earliest_of(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+
+// Task: send messages from the outbox
+#[embassy_executor::task()]
+pub async fn task_sender(stack: embassy_net::Stack<'static>) {
+    let mut backends = Backends::new();
+    let mut held: Option<(Entry, Instant)> = None;  // waiting out its coalescing window, till then
+
+    loop {
+        // Wait for a message. Or for a deadline: a backend's, e.g. escalation; the end of a window.
+        // Holding a message, the next ones queue up behind it: only a deadline wakes us.
+        let deadline = earliest_of(backends.deadline(), held.as_ref().map(|(_, end)| *end));
+        let woken = match (&held, deadline) {
+            (None, None) => {
+                outbox::wait_pending().await;
+                Either::First(())
+            }
+            (None, Some(deadline)) => select(outbox::wait_pending(), Timer::at(deadline)).await,
+            (Some(_), deadline) => {
+                Timer::at(deadline.unwrap_or(Instant::MAX)).await;
+                Either::Second(())
+            }
+        };
+        if let Either::Second(()) = woken {
+            backends.on_deadline(stack).await;
+            match held.take() {
+                Some((mut entry, end)) if end <= Instant::now() => {
+                    coalesce::close_window(&mut entry).await;
+                    send_entry(&mut backends, stack, &entry).await;
+                }
+                still_held => held = still_held,
+            }
+            continue;
+        }
+        let Some(mut entry) = outbox::next().await else { continue };
+
+        // Wait for network. Meanwhile, the LED shows how many messages are waiting.
+        let offline = !stack.is_config_up();
+        if offline {
+            crate::led::set_led_state(outbox::offline_led_state());
+            stack.wait_config_up().await;
+        }
+
+        // A burst of events: one message
+        match coalesce::coalesce(&mut entry, offline).await {
+            Some(end) => held = Some((entry, end)),
+            None => send_entry(&mut backends, stack, &entry).await,
+        }
+    }
+}
+
+// Send. Done with it: sent, or given up on.
+// At most once: done before sending. A reboot mid-send loses the message, rather than sending it twice.
+// At least once: done once every backend has it, or has turned it down. Till then it's tried again,
+// with the backends that haven't got it, and nothing else is sent: not even escalations (see `bot_api::reconcile()`).
+// Stale meanwhile: left to the outbox, see `OUTBOX_EXPIRED`.
+async fn send_entry(backends: &mut Backends, stack: embassy_net::Stack<'static>, entry: &Entry) {
+    let mut done = heapless::Vec::new();
+    let delivery = match DELIVERY_MODE {
+        DeliveryMode::AtMostOnce => {
+            outbox::done(entry).await;
+            backends.send(stack, entry, &mut done).await
+        }
+        DeliveryMode::AtLeastOnce => loop {
+            match backends.send(stack, entry, &mut done).await {
+                Delivery::Failed if entry.is_expired() => return,
+                Delivery::Failed => {
+                    defmt::warn!("Notifier: not delivered: trying again in {} s", REDELIVERY_DELAY.as_secs());
+                    Timer::after(REDELIVERY_DELAY).await;
+                    stack.wait_config_up().await;
+                }
+                delivery => {
+                    outbox::done(entry).await;
+                    break delivery;
+                }
+            }
+        },
+    };
+
+    // Once per message: not per backend, nor per recipient
+    if !matches!(entry.message, OutgoingMessage::Undo) {
+        crate::status::update_counters(|c| match delivery {
+            Delivery::Delivered => c.sent += 1,
+            Delivery::Rejected | Delivery::Failed => c.gave_up += 1,
+        });
+    }
+    defmt::info!("Status: {:?}", crate::status::status_report(stack));
+}
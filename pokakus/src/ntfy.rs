@@ -0,0 +1,210 @@
+// ntfy: a notifier that pushes to phones, no Telegram needed. See https://ntfy.sh
+//
+// Publishes to a topic on a server: ntfy.sh, or your own. Whoever subscribes to the topic in the app gets it.
+// JSON publishing: a POST to the server's root, the topic in the body. Titles and tags can be any UTF-8 that way.
+//
+//   {"topic":"pokakus","message":"Pokakus #3 at 07:15","title":"pokakus","priority":4,"tags":["baby"]}
+//
+// A public server: anyone who knows the topic can read it. Pick a hard one to guess, or use auth:
+// an access token, or a user name and password.
+
+use defmt;
+use alloc::string::String;
+use core::fmt::Write;
+
+use reqwless::request::Method;
+
+use crate::config;
+use crate::http::{self, DeliveryError};
+use crate::notifier::{self, Capabilities, Notifier};
+use crate::outbox::Entry;
+use crate::retry::RetryPolicy;
+use crate::template;
+use crate::text::escape_json;
+
+
+// The server: "https://ntfy.sh", or your own
+const URL: &str = http::check_url(match option_env!("NTFY_URL") {
+    Some(v) if !v.is_empty() => v,
+    _ => "https://ntfy.sh",
+});
+
+// The topic: letters, digits, - and _
+const TOPIC: &str = check_topic(env!("NTFY_TOPIC"));
+
+const fn check_topic(topic: &str) -> &str {
+    let t = topic.as_bytes();
+    if t.is_empty() || t.len() > 64 {
+        panic!("ntfy topic: expected 1 to 64 characters");
+    }
+    let mut i = 0;
+    while i < t.len() {
+        if !t[i].is_ascii_alphanumeric() && t[i] != b'-' && t[i] != b'_' {
+            panic!("ntfy topic: expected letters, digits, - and _");
+        }
+        i += 1;
+    }
+    topic
+}
+
+// The title: a template, see `template`. Empty: the topic, ntfy's default.
+const TITLE: &str = match option_env!("NTFY_TITLE") {
+    Some(v) => v,
+    None => "{hostname}",
+};
+const _: () = template::check_message(TITLE);
+
+// Priority: 1 (min) to 5 (max, urgent); 3 is the default
+const PRIORITY: u8 = match option_env!("NTFY_PRIORITY") {
+    None => 3,
+    Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"default") => 3,
+    Some(v) if config::eq(v.as_bytes(), b"min") => 1,
+    Some(v) if config::eq(v.as_bytes(), b"low") => 2,
+    Some(v) if config::eq(v.as_bytes(), b"high") => 4,
+    Some(v) if config::eq(v.as_bytes(), b"max") || config::eq(v.as_bytes(), b"urgent") => 5,
+    Some(v) => match config::parse_u32(Some(v), 3) {
+        p @ 1..=5 => p as u8,
+        _ => panic!("ntfy priority: expected 1 to 5, or min, low, default, high, max"),
+    },
+};
+
+// Tags: comma-separated. Emoji short codes show as emojis: "baby,poop"
+const TAGS: &str = match option_env!("NTFY_TAGS") {
+    Some(v) => v,
+    None => "",
+};
+
+// Tapping the notification opens this. Empty: nothing.
+const CLICK: &str = match option_env!("NTFY_CLICK") {
+    Some(v) => v,
+    None => "",
+};
+
+// Auth: an access token ("tk_..."), or a user name and password. Empty: none.
+const TOKEN: &str = match option_env!("NTFY_TOKEN") {
+    Some(v) => v,
+    None => "",
+};
+const USERNAME: &str = match option_env!("NTFY_USERNAME") {
+    Some(v) => v,
+    None => "",
+};
+const PASSWORD: &str = match option_env!("NTFY_PASSWORD") {
+    Some(v) => v,
+    None => "",
+};
+const _: () = assert!(TOKEN.is_empty() || USERNAME.is_empty(), "ntfy auth: expected a token, or a user name and password, not both");
+
+// The CA we expect the server's certificate to be issued by: DER file
+const CA_CERT: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", env!("NTFY_CA_CERT")));
+
+// Retries: see `http::retry_policy()`
+const RETRY_POLICY: RetryPolicy = http::retry_policy(config::parse_u32(option_env!("NTFY_RETRY_MAX_ATTEMPTS"), 5));
+
+// The response: the message, as stored. Only logged.
+const RX_BUF_SIZE: usize = 2 * 1024;
+
+
+/// ntfy: a notifier, see `notifier`
+#[derive(Default)]
+pub struct NtfyNotifier;
+
+impl Notifier for NtfyNotifier {
+    type Error = DeliveryError;
+
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { replies: false, undo: false }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RETRY_POLICY
+    }
+
+    async fn send(&mut self, stack: embassy_net::Stack<'_>, entry: &Entry) -> Result<(), DeliveryError> {
+        let body = body(entry);
+
+        // Headers: content type, auth
+        let auth = authorization();
+        let mut headers = heapless::Vec::<(&str, &str), 2>::new();
+        let _ = headers.push(("Content-Type", "application/json"));  // fits: 2
+        if let Some(auth) = &auth {
+            let _ = headers.push(("Authorization", auth.as_str()));
+        }
+
+        // Request: to the server's root
+        let mut url = String::from(URL.trim_end_matches('/'));
+        url.push('/');
+        defmt::debug!("ntfy: {}, topic {}...", http::server(URL).1, TOPIC);
+        let req = http::Request { method: Method::POST, url: &url, headers: &headers, body: body.as_bytes() };
+        let mut rx_buf = alloc::vec![0u8; RX_BUF_SIZE];
+        http::request(stack, &req, CA_CERT, &mut rx_buf).await?.success()?;
+        defmt::info!("ntfy: sent to {}", TOPIC);
+        Ok(())
+    }
+}
+
+// The JSON: topic, message, and the extras that are set
+fn body(entry: &Entry) -> String {
+    let mut body = String::new();
+    // String never fails
+    let _ = write!(body, r#"{{"topic":"{}","message":""#, TOPIC);
+    let _ = escape_json(&mut body, &notifier::plain_text(entry));
+    body.push('"');
+
+    if !TITLE.is_empty() {
+        // Plain text: then all of it escaped, not just the values
+        let mut title = String::new();
+        let _ = template::render(&mut title, TITLE, &notifier::context(entry, None));
+        body.push_str(r#","title":""#);
+        let _ = escape_json(&mut body, &title);
+        body.push('"');
+    }
+    if PRIORITY != 3 {
+        let _ = write!(body, r#","priority":{}"#, PRIORITY);
+    }
+    let mut tags = TAGS.split(',').map(str::trim).filter(|t| !t.is_empty()).peekable();
+    if tags.peek().is_some() {
+        body.push_str(r#","tags":["#);
+        for (i, tag) in tags.enumerate() {
+            body.push_str(if i == 0 { "\"" } else { ",\"" });
+            let _ = escape_json(&mut body, tag);
+            body.push('"');
+        }
+        body.push(']');
+    }
+    if !CLICK.is_empty() {
+        body.push_str(r#","click":""#);
+        let _ = escape_json(&mut body, CLICK);
+        body.push('"');
+    }
+    body.push('}');
+    body
+}
+
+// Authorization header: "Bearer tk_...", "Basic <base64 of user:password>", or none
+fn authorization() -> Option<String> {
+    if !TOKEN.is_empty() {
+        let mut auth = String::from("Bearer ");
+        auth.push_str(TOKEN);
+        return Some(auth);
+    }
+    if USERNAME.is_empty() {
+        return None;
+    }
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let credentials = [USERNAME.as_bytes(), b":", PASSWORD.as_bytes()].concat();
+    let mut auth = String::from("Basic ");
+    for chunk in credentials.chunks(3) {
+        let b = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            // 1 byte: 2 characters and "=="; 2 bytes: 3 and "="
+            auth.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i)) as usize & 63] as char } else { '=' });
+        }
+    }
+    Some(auth)
+}
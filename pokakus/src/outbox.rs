@@ -0,0 +1,763 @@
+// Outbox: messages waiting to be sent. Survives reboots and long outages.
+//
+// Messages are queued in RAM first: a small channel, because callers can't wait.
+// `task_outbox` writes them to flash right away; the sender takes them from flash
+// and marks them done once they're handled.
+//
+// Flash: a ring of sectors in the storage partition, sector 1 onwards (see `storage`).
+// Records are appended; when a sector is full, we move on to the next one and erase it.
+// Every sector gets erased in turn: that's our wear levelling.
+//   Record: [magic, state, len(2)] [seq(4)] [checksum(4)] payload, padded to 4 bytes, [since]
+// Done: the state byte is overwritten with 0x00. Flash can flip bits 1→0 without an erase.
+// Since: left erased, and written once, when the message is first sent: see `set_since()`.
+// The ring is full? The oldest messages are dropped.
+//
+// Stale messages: dropped, or summarized into one, see `OUTBOX_EXPIRED`.
+// Without storage, the outbox lives in RAM only.
+
+use defmt;
+use core::cell::Cell;
+use alloc::{borrow::Cow, string::String, vec::Vec};
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+
+use crate::gesture::Gesture;
+use crate::coalesce::Burst;
+use crate::config;
+use crate::event::Event;
+use crate::led::LedState;
+use crate::recipient::{ChatId, Recipient, MAX_RECIPIENTS};
+use crate::storage::{self, Key, Region, SECTOR_SIZE};
+
+
+// Messages older than this are stale. 0: never.
+const MAX_AGE: Option<Duration> = match config::parse_u32(option_env!("OUTBOX_MAX_AGE_MINUTES"), 24 * 60) {
+    0 => None,
+    minutes => Some(Duration::from_secs(minutes as u64 * 60)),
+};
+
+// What to do with stale messages
+const EXPIRED: Expired = Expired::parse(option_env!("OUTBOX_EXPIRED"));
+
+// The outbox starts at this sector: sector 0 is the key-value store
+const FIRST_SECTOR: u32 = 1;
+
+// Pending messages: at most
+const MAX_PENDING: usize = 64;
+
+// The longest text we store: bytes. Longer texts are cut.
+const MAX_TEXT: usize = 2048;
+
+// Record
+const HEADER_SIZE: u32 = 12;
+const MAGIC: u8 = 0xA5;
+const STATE_PENDING: u8 = 0xFF;  // as written: erased flash is all 1s
+const STATE_DONE: u8 = 0x00;
+const SINCE_SIZE: u32 = MAX_RECIPIENTS as u32 * 16;  // chat_id(8), message_id(8); erased: none
+
+
+/// A queued message
+pub enum OutgoingMessage {
+    Text(String),
+    Event {
+        template: Cow<'static, str>,  // borrowed when queued; owned when loaded from flash
+        event: Event,
+    },
+    Reply {
+        to: Recipient,
+        text: String,
+    },
+    Undo,  // not a message: retract the last one. Queued: so it comes after the message it undoes.
+}
+
+/// Stale messages: what to do with them
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Expired {
+    Drop,       // forget them
+    Summarize,  // one message instead: "While offline: 3 events, 09:02–11:40"
+}
+
+impl Expired {
+    pub const fn parse(value: Option<&str>) -> Expired {
+        match value {
+            None => Expired::Summarize,
+            Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"summarize") => Expired::Summarize,
+            Some(v) if config::eq(v.as_bytes(), b"drop") => Expired::Drop,
+            Some(_) => panic!("Outbox: expired messages: expected 'drop' or 'summarize'"),
+        }
+    }
+}
+
+
+/// Queue a message. Doesn't wait: if the RAM queue is full, the message is lost.
+pub fn queue(message: OutgoingMessage) {
+    match MESSAGES_QUEUE.try_send(message) {
+        Ok(()) => (),
+        Err(_) => defmt::error!("Queue full: cannot send message"),
+    }
+}
+
+// Messages queue: RAM, before they're written to flash
+static MESSAGES_QUEUE: Channel<CriticalSectionRawMutex, OutgoingMessage, 8> = Channel::new();
+
+
+// Task: move queued messages to the outbox
+#[embassy_executor::task]
+pub async fn task_outbox(stack: embassy_net::Stack<'static>) {
+    init().await;
+    loop {
+        let message = MESSAGES_QUEUE.receive().await;
+        push(&message).await;
+
+        // Offline? Show how many are waiting.
+        if !stack.is_config_up() {
+            crate::led::set_led_state(offline_led_state());
+        }
+    }
+}
+
+
+/// The next message to send: the oldest one.
+/// Stale messages are handled here: see `EXPIRED`.
+pub struct Entry {
+    pub message: OutgoingMessage,
+    pub unix_time: Option<u64>,  // when it was queued: the original event time
+    pub id: u32,  // the same on every attempt, and after a reboot: see `event_id()`
+    pub burst: Option<Burst>,  // several events merged into this one: see `coalesce`
+    pub since: Option<Since>,  // see `set_since()`. `None`: not sent yet.
+    boot: u32,  // queued in: see `Meta`
+    seqs: heapless::Vec<u32, 16>,  // records: more than one when summarized
+}
+
+impl Entry {
+    /// Stale by now? See `OUTBOX_MAX_AGE_MINUTES`.
+    pub fn is_expired(&self) -> bool {
+        Meta { unix_time: self.unix_time, boot: self.boot }.is_expired()
+    }
+}
+
+/// The last message in each chat before this one: where to look for it, should it get through unnoticed.
+/// See `bot_api::reconcile()`.
+pub type Since = heapless::Vec<(ChatId, i64), MAX_RECIPIENTS>;
+
+/// Take a look at the next message. `None`: nothing to send.
+/// Call `done()` when it's handled.
+pub async fn next() -> Option<Entry> {
+    let mut outbox = OUTBOX.lock().await;
+    let outbox = outbox.as_mut()?;
+
+    loop {
+        let first = outbox.pending.first()?;
+        let (message, meta) = match outbox.load(first).await {
+            Some(loaded) => loaded,
+            None => {
+                // Unreadable: skip it
+                let seq = first.seq;
+                defmt::warn!("Outbox: record {} unreadable: dropping", seq);
+                outbox.remove(seq).await;
+                continue;
+            }
+        };
+
+        if !meta.is_expired() {
+            let mut seqs = heapless::Vec::new();
+            let _ = seqs.push(first.seq);
+            return Some(Entry { message, unix_time: meta.unix_time, id: event_id(meta.boot, first.seq), burst: None, since: first.since.clone(), boot: meta.boot, seqs });
+        }
+
+        // Stale
+        match (EXPIRED, &message) {
+            (Expired::Summarize, OutgoingMessage::Event { .. }) => return Some(outbox.summarize().await),
+            _ => {
+                let seq = first.seq;
+                defmt::warn!("Outbox: message {} expired: dropping", seq);
+                outbox.remove(seq).await;
+                crate::status::update_counters(|c| c.expired += 1);
+            }
+        }
+    }
+}
+
+/// Merge the pending events that follow the entry's event into it: same kind, same template.
+/// `window`: only those within this long of the first one; `None`: all of them.
+/// Stops at the first one that doesn't fit: the order's kept.
+pub async fn absorb(entry: &mut Entry, window: Option<Duration>) {
+    let OutgoingMessage::Event { template, event } = &entry.message else { return };
+    let template = template.clone();
+    let mut burst = entry.burst.unwrap_or(Burst { count: 1, first: *event });
+
+    let mut outbox = OUTBOX.lock().await;
+    let Some(outbox) = outbox.as_mut() else { return };
+    let Some(mut index) = entry.seqs.last().and_then(|seq| outbox.pending.iter().position(|p| p.seq == *seq)) else { return };
+    while let Some(pending) = outbox.pending.get(index + 1) {
+        let Some((OutgoingMessage::Event { template: t, event }, meta)) = outbox.load(pending).await else { break };
+        let within = match window {
+            None => true,
+            Some(window) => gap(&burst.first, &event) <= window,
+        };
+        if t != template || event.gesture != burst.first.gesture || meta.is_expired() || !within {
+            break;
+        }
+        if entry.seqs.push(pending.seq).is_err() {
+            break;  // that's enough for one message
+        }
+        burst.count += 1;
+        entry.message = OutgoingMessage::Event { template: t, event };
+        index += 1;
+    }
+    if burst.count > 1 {
+        entry.burst = Some(burst);
+    }
+}
+
+// Time between two events: by the wall clock if we know it. `Instant`s don't survive a reboot.
+fn gap(first: &Event, next: &Event) -> Duration {
+    match (first.unix_time, next.unix_time) {
+        (Some(a), Some(b)) => Duration::from_secs(b.saturating_sub(a)),
+        _ => next.at.checked_duration_since(first.at).unwrap_or(Duration::MAX),
+    }
+}
+
+/// The message is about to be sent for the first time: keep `since` with it. A reboot mid-send doesn't lose it.
+/// Numeric chat ids only: @channels aren't kept.
+pub async fn set_since(entry: &Entry, since: &Since) {
+    let mut outbox = OUTBOX.lock().await;
+    let Some(outbox) = outbox.as_mut() else { return };
+    let Some(pending) = entry.seqs.first().and_then(|seq| outbox.pending.iter_mut().find(|p| p.seq == *seq)) else { return };
+    if pending.since.is_some() {
+        return;  // once: flash can't be written twice
+    }
+    pending.since = Some(since.clone());
+    if let (Location::Flash { sector, offset, len }, Some(ring)) = (&pending.location, outbox.ring.as_ref()) {
+        let mut record = alloc::vec![0xFF; SINCE_SIZE as usize];
+        let ids = since.iter().filter_map(|(chat, id)| match chat {
+            ChatId::Id(chat) => Some((*chat, *id)),
+            ChatId::Username(_) => None,
+        });
+        for ((chat, id), slot) in ids.zip(record.as_chunks_mut::<16>().0) {
+            slot[..8].copy_from_slice(&chat.to_le_bytes());
+            slot[8..].copy_from_slice(&id.to_le_bytes());
+        }
+        let at = sector * SECTOR_SIZE + offset + record_size(*len as usize) - SINCE_SIZE;
+        let result = storage::with_sectors(FIRST_SECTOR, ring.sectors, |region| region.write(at, &record)).await;
+        if !matches!(result, Ok(Ok(()))) {
+            defmt::warn!("Outbox: can't keep where message {} goes: it may be sent twice after a reboot", pending.seq);
+        }
+    }
+}
+
+/// The message's been handled: sent, or given up on
+pub async fn done(entry: &Entry) {
+    if let Some(outbox) = OUTBOX.lock().await.as_mut() {
+        for seq in &entry.seqs {
+            outbox.remove(*seq).await;
+        }
+    }
+}
+
+/// Wait until there's something in the outbox
+pub async fn wait_pending() {
+    while pending_count() == 0 {
+        PUSHED.wait().await;
+    }
+}
+
+/// How many messages are waiting
+pub fn pending_count() -> u32 {
+    PENDING_COUNT.lock(|c| c.get())
+}
+
+/// LED while offline: how many messages are waiting, if any
+pub fn offline_led_state() -> LedState {
+    match pending_count() {
+        0 => LedState::PatientBlink,
+        n => LedState::Queued(n.min(9) as u8),
+    }
+}
+
+// Signal: a message's been pushed
+static PUSHED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Pending: for those who can't wait for the lock
+static PENDING_COUNT: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<u32>> = blocking_mutex::Mutex::new(Cell::new(0));
+
+
+// Init: find the ring, load pending messages
+async fn init() {
+    let boot = storage::load(Key::BootCount).await.unwrap_or(0) as u32 + 1;
+    if let Err(e) = storage::store(Key::BootCount, boot as u64).await {
+        defmt::warn!("Outbox: boot count not saved: {:?}", e);
+    }
+    BOOT.lock(|b| b.set(boot));
+
+    let sectors = storage::sector_count().await.saturating_sub(FIRST_SECTOR);
+    let mut outbox = Outbox { ring: None, pending: heapless::Vec::new(), next_seq: 0 };
+    if sectors >= 2 {
+        match storage::with_sectors(FIRST_SECTOR, sectors, |region| outbox.scan(region, sectors)).await {
+            Ok(ring) => outbox.ring = Some(ring),
+            Err(e) => defmt::error!("Outbox: flash unavailable: {:?}", e),
+        }
+    }
+    match outbox.ring {
+        Some(_) => defmt::info!("Outbox: {} sectors, {} messages pending", sectors, outbox.pending.len()),
+        None => defmt::warn!("Outbox: no flash: messages won't survive a reboot"),
+    }
+
+    outbox.update_count();
+    *OUTBOX.lock().await = Some(outbox);
+    PUSHED.signal(());
+}
+
+// Push a message
+async fn push(message: &OutgoingMessage) {
+    let mut outbox = OUTBOX.lock().await;
+    let Some(outbox) = outbox.as_mut() else { return };
+    let meta = Meta {
+        unix_time: match message {
+            OutgoingMessage::Event { event, .. } => event.unix_time,
+            _ => crate::clock::now_unix(),
+        },
+        boot: current_boot(),
+    };
+    outbox.push(encode(message, &meta)).await;
+    outbox.update_count();
+    PUSHED.signal(());
+}
+
+
+static OUTBOX: Mutex<CriticalSectionRawMutex, Option<Outbox>> = Mutex::new(None);
+
+struct Outbox {
+    ring: Option<Ring>,  // flash; `None`: RAM only
+    pending: heapless::Vec<Pending, MAX_PENDING>,  // oldest first
+    next_seq: u32,
+}
+
+// Flash ring: where to write next
+struct Ring {
+    sectors: u32,
+    head_sector: u32,
+    head_offset: u32,
+}
+
+// A pending message: where it is
+struct Pending {
+    seq: u32,
+    location: Location,
+    since: Option<Since>,  // see `set_since()`
+}
+
+enum Location {
+    Flash { sector: u32, offset: u32, len: u16 },
+    Ram(Vec<u8>),  // no flash
+}
+
+// When and where a message's been queued
+struct Meta {
+    unix_time: Option<u64>,
+    boot: u32,
+}
+
+impl Meta {
+    fn is_expired(&self) -> bool {
+        let Some(max_age) = MAX_AGE else { return false };
+        match (self.unix_time, crate::clock::now_unix()) {
+            (Some(then), Some(now)) => now.saturating_sub(then) > max_age.as_secs(),
+            // Before a reboot, time unknown: how old is it? Can't tell. Assume stale.
+            (None, _) if self.boot != current_boot() => true,
+            // Can't tell: send it
+            _ => false,
+        }
+    }
+}
+
+impl Outbox {
+    // Scan the ring: find pending records and the write position
+    fn scan(&mut self, region: &mut Region<'_>, sectors: u32) -> Ring {
+        let mut head: Option<(u32, u32, u32)> = None;  // seq, sector, offset after it
+        for sector in 0..sectors {
+            let mut offset = 0;
+            while offset + HEADER_SIZE <= SECTOR_SIZE {
+                let mut header = [0u8; HEADER_SIZE as usize];
+                if region.read(sector * SECTOR_SIZE + offset, &mut header).is_err() {
+                    break;
+                }
+                let Some((state, len, seq, checksum)) = parse_header(&header) else { break };  // free space, or garbage
+                let size = record_size(len as usize);
+                if offset + size > SECTOR_SIZE {
+                    break;
+                }
+
+                if state == STATE_PENDING {
+                    let mut payload = alloc::vec![0u8; len as usize];
+                    let mut since = [0u8; SINCE_SIZE as usize];
+                    let ok = region.read(sector * SECTOR_SIZE + offset + HEADER_SIZE, &mut payload).is_ok()
+                        && storage::fnv1a(&payload) == checksum
+                        && region.read(sector * SECTOR_SIZE + offset + size - SINCE_SIZE, &mut since).is_ok();
+                    let pending = Pending { seq, location: Location::Flash { sector, offset, len }, since: decode_since(&since) };
+                    if ok && self.pending.push(pending).is_err() {
+                        defmt::warn!("Outbox: too many pending messages: skipping {}", seq);
+                    }
+                }
+                if head.is_none_or(|(max, _, _)| seq.wrapping_sub(max) as i32 > 0) {
+                    head = Some((seq, sector, offset + size));
+                }
+                offset += size;
+            }
+        }
+
+        self.pending.sort_unstable_by_key(|p| p.seq);
+        let (head_sector, head_offset) = match head {
+            Some((seq, sector, offset)) => {
+                self.next_seq = seq.wrapping_add(1);
+                (sector, offset)
+            }
+            None => (0, 0),
+        };
+        let mut ring = Ring { sectors, head_sector, head_offset };
+
+        // Is there room after the last record? Garbage there, e.g. a write cut short by a reset: move on.
+        let mut header = [0u8; HEADER_SIZE as usize];
+        let free = ring.head_offset + HEADER_SIZE > SECTOR_SIZE
+            || region.read(ring.head_sector * SECTOR_SIZE + ring.head_offset, &mut header).is_ok_and(|()| header.iter().all(|b| *b == 0xFF));
+        if !free || ring.head_offset == 0 {
+            ring.head_sector = (ring.head_sector + 1) % ring.sectors;
+            ring.head_offset = 0;
+        }
+        ring
+    }
+
+    // Append a record
+    async fn push(&mut self, mut payload: Vec<u8>) {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        // Too many? Drop the oldest.
+        if self.pending.is_full() {
+            let oldest = self.pending[0].seq;
+            defmt::warn!("Outbox: full: dropping message {}", oldest);
+            self.remove(oldest).await;
+        }
+
+        let location = match self.ring.as_mut() {
+            None => Location::Ram(payload),
+            Some(ring) => {
+                let pending = &mut self.pending;
+                let result = storage::with_sectors(FIRST_SECTOR, ring.sectors, |region| {
+                    ring.append(region, seq, &mut payload, pending)
+                }).await;
+                match result {
+                    Ok(Some(location)) => location,
+                    _ => {
+                        defmt::error!("Outbox: can't write to flash: keeping message {} in RAM", seq);
+                        Location::Ram(payload)
+                    }
+                }
+            }
+        };
+        let _ = self.pending.push(Pending { seq, location, since: None });  // there's room: see above
+    }
+
+    // Load a message
+    async fn load(&self, pending: &Pending) -> Option<(OutgoingMessage, Meta)> {
+        match &pending.location {
+            Location::Ram(payload) => decode(payload),
+            Location::Flash { sector, offset, len } => {
+                let ring = self.ring.as_ref()?;
+                let mut payload = alloc::vec![0u8; *len as usize];
+                let read = storage::with_sectors(FIRST_SECTOR, ring.sectors, |region| {
+                    region.read(sector * SECTOR_SIZE + offset + HEADER_SIZE, &mut payload)
+                }).await;
+                match read {
+                    Ok(Ok(())) => decode(&payload),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    // Done with a message: mark it in flash, forget it
+    async fn remove(&mut self, seq: u32) {
+        let Some(index) = self.pending.iter().position(|p| p.seq == seq) else { return };
+        let pending = self.pending.remove(index);
+        if let (Location::Flash { sector, offset, len }, Some(ring)) = (pending.location, self.ring.as_ref()) {
+            let [len_lo, len_hi] = len.to_le_bytes();
+            let result = storage::with_sectors(FIRST_SECTOR, ring.sectors, |region| {
+                region.write(sector * SECTOR_SIZE + offset, &[MAGIC, STATE_DONE, len_lo, len_hi])
+            }).await;
+            if !matches!(result, Ok(Ok(()))) {
+                defmt::warn!("Outbox: can't mark message {} done: it may be sent again after a reboot", seq);
+            }
+        }
+        self.update_count();
+    }
+
+    // Stale events: one message for all of them. The rest stay pending.
+    async fn summarize(&self) -> Entry {
+        let mut seqs = heapless::Vec::new();
+        let mut id = None;
+        let (mut first, mut last) = (None::<u64>, None::<u64>);
+        for pending in &self.pending {
+            match self.load(pending).await {
+                Some((OutgoingMessage::Event { .. }, meta)) if meta.is_expired() => {
+                    if seqs.push(pending.seq).is_err() {
+                        break;
+                    }
+                    id = id.or(Some(event_id(meta.boot, pending.seq)));
+                    first = first.or(meta.unix_time);
+                    last = meta.unix_time.or(last);
+                }
+                _ => break,
+            }
+        }
+
+        let mut text = String::new();
+        use core::fmt::Write;
+        let _ = write!(text, "While offline: {} events", seqs.len());  // String never fails
+        if let (Some(first), Some(last)) = (first, last) {
+            let _ = text.write_str(", ");
+            let _ = crate::template::write_time(&mut text, first);
+            let _ = text.write_str("–");
+            let _ = crate::template::write_time(&mut text, last);
+        }
+        defmt::info!("Outbox: {} stale events: summarizing", seqs.len());
+        let since = self.pending.first().and_then(|p| p.since.clone());
+        Entry { message: OutgoingMessage::Text(text), unix_time: first, id: id.unwrap_or_default(), burst: None, since, boot: current_boot(), seqs }
+    }
+
+    fn update_count(&self) {
+        PENDING_COUNT.lock(|c| c.set(self.pending.len() as u32));
+    }
+}
+
+impl Ring {
+    // Write a record at the head. Returns where it is.
+    fn append(&mut self, region: &mut Region<'_>, seq: u32, payload: &mut Vec<u8>, pending: &mut heapless::Vec<Pending, MAX_PENDING>) -> Option<Location> {
+        let len = payload.len() as u16;
+        let size = record_size(payload.len());
+
+        // No room in this sector? The next one.
+        if self.head_offset + size > SECTOR_SIZE {
+            self.head_sector = (self.head_sector + 1) % self.sectors;
+            self.head_offset = 0;
+        }
+
+        // A fresh sector: erase it. Whatever's still pending there is lost: the ring is full.
+        if self.head_offset == 0 {
+            let sector = self.head_sector;
+            let before = pending.len();
+            pending.retain(|p| !matches!(p.location, Location::Flash { sector: s, .. } if s == sector));
+            if pending.len() < before {
+                defmt::warn!("Outbox: full: dropped {} oldest messages", before - pending.len());
+            }
+            region.erase_sector(sector).ok()?;
+        }
+
+        // Header + payload, padded
+        let checksum = storage::fnv1a(payload);
+        let mut record = Vec::with_capacity(size as usize);
+        let [len_lo, len_hi] = len.to_le_bytes();
+        record.extend_from_slice(&[MAGIC, STATE_PENDING, len_lo, len_hi]);
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(&checksum.to_le_bytes());
+        record.append(payload);
+        record.resize(size as usize, 0xFF);  // padding, and `since`: erased
+
+        let (sector, offset) = (self.head_sector, self.head_offset);
+        region.write(sector * SECTOR_SIZE + offset, &record).ok()?;
+        self.head_offset += size;
+        Some(Location::Flash { sector, offset, len })
+    }
+}
+
+// Header: state, len, seq, checksum. `None`: free space or garbage.
+fn parse_header(header: &[u8; HEADER_SIZE as usize]) -> Option<(u8, u16, u32, u32)> {
+    if header[0] != MAGIC || !matches!(header[1], STATE_PENDING | STATE_DONE) {
+        return None;
+    }
+    let len = u16::from_le_bytes([header[2], header[3]]);
+    let seq = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let checksum = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    Some((header[1], len, seq, checksum))
+}
+
+fn record_size(len: usize) -> u32 {
+    HEADER_SIZE + (len as u32).next_multiple_of(4) + SINCE_SIZE
+}
+
+// Since: the slots that have been written. All erased: not sent yet.
+fn decode_since(record: &[u8; SINCE_SIZE as usize]) -> Option<Since> {
+    if record.iter().all(|b| *b == 0xFF) {
+        return None;
+    }
+    let slots = record.as_chunks::<16>().0.iter().filter(|slot| slot.iter().any(|b| *b != 0xFF));
+    Some(slots.map(|slot| {
+        let chat = i64::from_le_bytes(slot[..8].try_into().unwrap_or_default());
+        let id = i64::from_le_bytes(slot[8..].try_into().unwrap_or_default());
+        (ChatId::Id(chat), id)
+    }).collect())
+}
+
+
+
+// Payload:
+//   [kind] [unix_time(8), 0: unknown] [boot(4)] ...
+//   Text:  text
+//   Event: [gesture] [at(8)] [count_today(4)] [since_last(4), secs, MAX: none] [template_len(2)] template
+//   Reply: [chat_id(8)] [thread(8), MIN: none] text
+//   Undo:  -
+const KIND_TEXT: u8 = 1;
+const KIND_EVENT: u8 = 2;
+const KIND_REPLY: u8 = 3;
+const KIND_UNDO: u8 = 4;
+
+fn encode(message: &OutgoingMessage, meta: &Meta) -> Vec<u8> {
+    let mut out = Vec::new();
+    let kind = match message {
+        OutgoingMessage::Text(_) => KIND_TEXT,
+        OutgoingMessage::Event { .. } => KIND_EVENT,
+        OutgoingMessage::Reply { .. } => KIND_REPLY,
+        OutgoingMessage::Undo => KIND_UNDO,
+    };
+    out.push(kind);
+    out.extend_from_slice(&meta.unix_time.unwrap_or(0).to_le_bytes());
+    out.extend_from_slice(&meta.boot.to_le_bytes());
+
+    match message {
+        OutgoingMessage::Text(text) => push_text(&mut out, text),
+        OutgoingMessage::Event { template, event } => {
+            out.push(match event.gesture {
+                Gesture::Click => 0,
+                Gesture::DoubleClick => 1,
+                Gesture::LongPress => 2,
+            });
+            out.extend_from_slice(&event.at.as_ticks().to_le_bytes());
+            out.extend_from_slice(&event.count_today.to_le_bytes());
+            let since_last = event.since_last.map_or(u32::MAX, |d| d.as_secs().min(u32::MAX as u64 - 1) as u32);
+            out.extend_from_slice(&since_last.to_le_bytes());
+            let template = &template[..floor_char_boundary(template, MAX_TEXT)];
+            out.extend_from_slice(&(template.len() as u16).to_le_bytes());
+            out.extend_from_slice(template.as_bytes());
+        }
+        OutgoingMessage::Reply { to, text } => {
+            // Replies go to chats that have written to us: numeric ids. Others aren't queued, see `notifier::send_reply()`.
+            let chat_id = match to.chat_id {
+                ChatId::Id(id) => id,
+                ChatId::Username(_) => 0,
+            };
+            out.extend_from_slice(&chat_id.to_le_bytes());
+            out.extend_from_slice(&to.message_thread_id.unwrap_or(i64::MIN).to_le_bytes());
+            push_text(&mut out, text);
+        }
+        OutgoingMessage::Undo => (),
+    }
+    out
+}
+
+fn decode(payload: &[u8]) -> Option<(OutgoingMessage, Meta)> {
+    let mut r = Reader(payload);
+    let kind = r.u8()?;
+    let unix_time = Some(r.u64()?).filter(|t| *t != 0);
+    let boot = r.u32()?;
+    let meta = Meta { unix_time, boot };
+
+    let message = match kind {
+        KIND_TEXT => OutgoingMessage::Text(r.text()?),
+        KIND_EVENT => {
+            let gesture = match r.u8()? {
+                0 => Gesture::Click,
+                1 => Gesture::DoubleClick,
+                _ => Gesture::LongPress,
+            };
+            let at = r.u64()?;
+            let count_today = r.u32()?;
+            let since_last = Some(r.u32()?).filter(|s| *s != u32::MAX).map(|s| Duration::from_secs(s as u64));
+            let template_len = u16::from_le_bytes(r.take(2)?.try_into().ok()?) as usize;
+            let template = String::from(core::str::from_utf8(r.take(template_len)?).ok()?);
+            OutgoingMessage::Event {
+                template: Cow::Owned(template),
+                event: Event {
+                    gesture,
+                    // Before a reboot? Its `Instant` means nothing now.
+                    at: if boot == current_boot() { Instant::from_ticks(at) } else { Instant::now() },
+                    unix_time,
+                    count_today,
+                    since_last,
+                },
+            }
+        }
+        KIND_REPLY => {
+            let chat_id = r.i64()?;
+            let thread = r.i64()?;
+            OutgoingMessage::Reply {
+                to: Recipient { chat_id: ChatId::Id(chat_id), message_thread_id: Some(thread).filter(|t| *t != i64::MIN) },
+                text: r.text()?,
+            }
+        }
+        KIND_UNDO => OutgoingMessage::Undo,
+        _ => return None,
+    };
+    Some((message, meta))
+}
+
+// Event id: the boot it was queued in, and its record. Short, and hashed: it's shown to people.
+fn event_id(boot: u32, seq: u32) -> u32 {
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&boot.to_le_bytes());
+    bytes[4..].copy_from_slice(&seq.to_le_bytes());
+    storage::fnv1a(&bytes)
+}
+
+fn current_boot() -> u32 {
+    BOOT.lock(|b| b.get())
+}
+
+static BOOT: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<u32>> = blocking_mutex::Mutex::new(Cell::new(0));
+
+// Text: the rest of the payload. Cut on a char boundary if too long.
+fn push_text(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&text.as_bytes()[..floor_char_boundary(text, MAX_TEXT)]);
+}
+
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    if text.len() <= max {
+        return text.len();
+    }
+    (0..=max).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0)
+}
+
+// Read a payload
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+    fn i64(&mut self) -> Option<i64> {
+        Some(i64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+    fn text(&mut self) -> Option<String> {
+        let rest = self.take(self.0.len())?;
+        Some(String::from(core::str::from_utf8(rest).ok()?))
+    }
+}
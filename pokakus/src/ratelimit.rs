@@ -0,0 +1,164 @@
+// Rate limiting: token buckets.
+//
+// A bucket holds up to `burst` tokens, and gets one back every `refill`.
+// Every message takes one; no tokens left: cooldown.
+// - Events: a click beyond the limit is suppressed before it's queued. The LED shows it.
+//   Only clicks are events: a double click is undo, a long press is diagnostics.
+// - Per recipient: Telegram allows about a message a second per chat, 20 a minute in a group.
+//   Messages wait for their turn.
+
+use defmt;
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::config;
+use crate::recipient::MAX_RECIPIENTS;
+
+
+/// Limit: `burst` messages at once, then one per `refill`
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Limit {
+    pub burst: u32,
+    pub refill: Duration,
+}
+
+impl Limit {
+    /// `None`: no limit. Either value 0: no limit.
+    pub const fn parse(burst: Option<&str>, seconds: Option<&str>, default_burst: u32, default_seconds: u32) -> Option<Limit> {
+        match (config::parse_u32(burst, default_burst), config::parse_u32(seconds, default_seconds)) {
+            (0, _) | (_, 0) => None,
+            (burst, secs) => Some(Limit { burst, refill: Duration::from_secs(secs as u64) }),
+        }
+    }
+}
+
+// Config: events, i.e. clicks. No limit by default.
+const LIMIT_EVENT: Option<Limit> = Limit::parse(option_env!("RATE_LIMIT_CLICK_BURST"), option_env!("RATE_LIMIT_CLICK_SECONDS"), 0, 0);
+
+// Config: per recipient. Telegram's group limit: 20 a minute.
+const LIMIT_RECIPIENT: Option<Limit> = Limit::parse(option_env!("TELEGRAM_RATE_LIMIT_BURST"), option_env!("TELEGRAM_RATE_LIMIT_SECONDS"), 20, 3);
+
+/// Cooldowns: at most one for events and one per recipient
+pub const MAX_COOLDOWNS: usize = 1 + MAX_RECIPIENTS;
+
+
+/// An event: may it be sent? Takes a token.
+/// `false`: suppressed, events are cooling down.
+pub fn allow_event() -> bool {
+    let Some(limit) = LIMIT_EVENT else { return true };
+    BUCKETS.lock(|b| b.borrow_mut().events.take(&limit, Instant::now()).is_ok())
+}
+
+/// Wait for the recipient's turn: see `TELEGRAM_SEND_TO`. Takes a token.
+pub async fn wait_recipient(index: usize) {
+    let Some(limit) = LIMIT_RECIPIENT else { return };
+    loop {
+        let taken = BUCKETS.lock(|b| match b.borrow_mut().recipients.get_mut(index) {
+            Some(bucket) => bucket.take(&limit, Instant::now()),
+            None => Ok(()),
+        });
+        match taken {
+            Ok(()) => return,
+            Err(wait) => {
+                defmt::info!("Rate limit: recipient #{} cooling down, {} ms", index + 1, wait.as_millis());
+                Timer::after(wait).await;
+            }
+        }
+    }
+}
+
+
+/// What's cooling down
+#[derive(defmt::Format, Clone, Copy)]
+pub enum Limited {
+    Events,
+    Recipient(usize),  // index in `TELEGRAM_SEND_TO`
+}
+
+/// Cooldown: out of tokens until then
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Cooldown {
+    pub what: Limited,
+    pub ready_in: Duration,
+}
+
+/// Everything that's cooling down now
+pub fn cooldowns() -> heapless::Vec<Cooldown, MAX_COOLDOWNS> {
+    let now = Instant::now();
+    let mut out = heapless::Vec::new();
+    BUCKETS.lock(|b| {
+        let mut buckets = b.borrow_mut();
+        if let Some(limit) = LIMIT_EVENT
+            && let Some(ready_in) = buckets.events.cooldown(&limit, now)
+        {
+            let _ = out.push(Cooldown { what: Limited::Events, ready_in });  // the first: fits
+        }
+        if let Some(limit) = LIMIT_RECIPIENT {
+            for (index, bucket) in buckets.recipients.iter_mut().enumerate() {
+                if let Some(ready_in) = bucket.cooldown(&limit, now) {
+                    let _ = out.push(Cooldown { what: Limited::Recipient(index), ready_in });  // one per recipient: fits
+                }
+            }
+        }
+    });
+    out
+}
+
+
+// Token bucket. `None`: never used, full.
+#[derive(Clone, Copy)]
+struct Bucket(Option<BucketState>);
+
+#[derive(Clone, Copy)]
+struct BucketState {
+    tokens: u32,
+    updated: Instant,  // the last refill
+}
+
+impl Bucket {
+    const FULL: Bucket = Bucket(None);
+
+    // Tokens now: refilled
+    fn refill(&mut self, limit: &Limit, now: Instant) -> &mut BucketState {
+        let state = self.0.get_or_insert(BucketState { tokens: limit.burst, updated: now });
+        let refills = (now - state.updated).as_ticks() / limit.refill.as_ticks().max(1);
+        state.tokens = (state.tokens as u64 + refills).min(limit.burst as u64) as u32;
+        state.updated = if state.tokens == limit.burst {
+            now
+        } else {
+            state.updated + Duration::from_ticks(refills * limit.refill.as_ticks())
+        };
+        state
+    }
+
+    // Take a token. Error: none left, wait this long.
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<(), Duration> {
+        let state = self.refill(limit, now);
+        if state.tokens == 0 {
+            return Err((state.updated + limit.refill) - now);
+        }
+        state.tokens -= 1;
+        Ok(())
+    }
+
+    // Out of tokens? Until when.
+    fn cooldown(&mut self, limit: &Limit, now: Instant) -> Option<Duration> {
+        self.0?;  // never used
+        let state = self.refill(limit, now);
+        (state.tokens == 0).then(|| (state.updated + limit.refill) - now)
+    }
+}
+
+struct Buckets {
+    events: Bucket,
+    recipients: [Bucket; MAX_RECIPIENTS],
+}
+
+static BUCKETS: Mutex<CriticalSectionRawMutex, RefCell<Buckets>> = Mutex::new(RefCell::new(Buckets {
+    events: Bucket::FULL,
+    recipients: [Bucket::FULL; MAX_RECIPIENTS],
+}));
@@ -0,0 +1,177 @@
+// Message recipients.
+//
+// A comma-separated list:
+//   691814383                  a user
+//   -1001234567890             a group
+//   @mychannel                 a public channel
+//   -1001234567890/42          a forum topic: message_thread_id=42
+//
+// Parsed at compile time: a bad list fails the build.
+
+use core::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::config;
+
+
+/// How many recipients we support
+pub const MAX_RECIPIENTS: usize = 8;
+
+
+/// Chat: numeric id or @username
+#[derive(defmt::Format, Clone, Copy, PartialEq, Debug)]
+pub enum ChatId {
+    Id(i64),
+    Username(&'static str),  // with the '@'
+}
+
+// Bot API accepts both: `"chat_id": 123` or `"chat_id": "@channel"`
+impl Serialize for ChatId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ChatId::Id(id) => serializer.serialize_i64(*id),
+            ChatId::Username(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl fmt::Display for ChatId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatId::Id(id) => write!(f, "{}", id),
+            ChatId::Username(name) => f.write_str(name),
+        }
+    }
+}
+
+
+/// A recipient: a chat, maybe a forum topic in it
+#[derive(defmt::Format, Clone, Copy, PartialEq, Debug)]
+pub struct Recipient {
+    pub chat_id: ChatId,
+    pub message_thread_id: Option<i64>,
+}
+
+impl Recipient {
+    /// Parse one recipient: "chat" or "chat/thread". `const`: use at compile time.
+    pub const fn parse(value: &'static str) -> Recipient {
+        parse_recipient(config::trim(value.as_bytes()))
+    }
+}
+
+
+/// List of recipients
+#[derive(Clone, Copy)]
+pub struct Recipients {
+    list: [Option<Recipient>; MAX_RECIPIENTS],
+}
+
+impl Recipients {
+    /// Parse a comma-separated list. `const`: use at compile time.
+    /// Empty: none. Whether that's fine is up to the user: see `telegram`.
+    pub const fn parse(value: &'static str) -> Recipients {
+        let mut list = [None; MAX_RECIPIENTS];
+        let mut n = 0;
+        let mut rest = value.as_bytes();
+
+        while !rest.is_empty() {
+            // Next item
+            let mut end = 0;
+            while end < rest.len() && rest[end] != b',' {
+                end += 1;
+            }
+            let (item, tail) = rest.split_at(end);
+            rest = match tail.split_first() {
+                Some((_, tail)) => tail,
+                None => tail,
+            };
+
+            let item = config::trim(item);
+            if item.is_empty() {
+                continue;
+            }
+            if n == MAX_RECIPIENTS {
+                panic!("Too many recipients");
+            }
+            list[n] = Some(parse_recipient(item));
+            n += 1;
+        }
+
+        Recipients { list }
+    }
+
+    /// Iterate over the recipients
+    pub fn iter(&self) -> impl Iterator<Item = &Recipient> {
+        self.list.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.list[0].is_none()
+    }
+
+    /// Replace a recipient's chat: e.g. when a group has migrated
+    pub fn set_chat_id(&mut self, index: usize, chat_id: ChatId) {
+        if let Some(Some(r)) = self.list.get_mut(index) {
+            r.chat_id = chat_id;
+        }
+    }
+}
+
+
+/// Delivery policy: when is a message considered "sent"?
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum DeliveryPolicy {
+    All,  // every recipient has got it
+    Any,  // at least one recipient has got it
+}
+
+impl DeliveryPolicy {
+    pub const fn parse(value: Option<&str>) -> DeliveryPolicy {
+        match value {
+            None => DeliveryPolicy::All,
+            Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"all") => DeliveryPolicy::All,
+            Some(v) if config::eq(v.as_bytes(), b"any") => DeliveryPolicy::Any,
+            Some(_) => panic!("Delivery policy: expected 'all' or 'any'"),
+        }
+    }
+
+    /// Overall outcome
+    pub fn is_success(self, succeeded: usize, total: usize) -> bool {
+        match self {
+            DeliveryPolicy::All => succeeded == total,
+            DeliveryPolicy::Any => succeeded > 0,
+        }
+    }
+}
+
+
+
+// Parse one recipient: "chat" or "chat/thread"
+const fn parse_recipient(item: &'static [u8]) -> Recipient {
+    // Topic?
+    let mut slash = 0;
+    while slash < item.len() && item[slash] != b'/' {
+        slash += 1;
+    }
+    let (chat, thread) = item.split_at(slash);
+    let message_thread_id = match thread.split_first() {
+        Some((_, thread)) => Some(config::parse_i64(thread)),
+        None => None,
+    };
+
+    // Chat: @username or id
+    let chat_id = match chat.first() {
+        Some(b'@') => match core::str::from_utf8(chat) {
+            Ok(name) if chat.len() > 1 => ChatId::Username(name),
+            _ => panic!("Invalid channel username"),
+        },
+        _ => ChatId::Id(config::parse_i64(chat)),
+    };
+
+    Recipient { chat_id, message_thread_id }
+}
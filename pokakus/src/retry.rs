@@ -0,0 +1,97 @@
+use defmt;
+
+use embassy_time::{Duration, Timer};
+
+use crate::config;
+
+
+/// Errors that know whether it makes sense to try again
+pub trait Retryable {
+    /// Permanent failure: retrying won't help (bad request, bad token, ...)
+    fn is_permanent(&self) -> bool;
+
+    /// The server told us when to try again
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The request may have got through: e.g. the connection dropped while waiting for the response
+    fn maybe_delivered(&self) -> bool {
+        false
+    }
+}
+
+
+/// Delivery semantics: when it's unclear whether a message has got through
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum DeliveryMode {
+    AtMostOnce,   // don't send it again: it may be lost
+    AtLeastOnce,  // send it again: it may arrive twice
+}
+
+impl DeliveryMode {
+    pub const fn parse(value: Option<&str>) -> DeliveryMode {
+        match value {
+            None => DeliveryMode::AtLeastOnce,
+            Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"at-least-once") => DeliveryMode::AtLeastOnce,
+            Some(v) if config::eq(v.as_bytes(), b"at-most-once") => DeliveryMode::AtMostOnce,
+            Some(_) => panic!("Delivery mode: expected 'at-least-once' or 'at-most-once'"),
+        }
+    }
+}
+
+
+/// Retry policy: exponential backoff
+#[derive(defmt::Format, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub mode: DeliveryMode,
+}
+
+impl RetryPolicy {
+    /// Delay before attempt #`attempt+1`, given that attempt #`attempt` has failed (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        let delay = Duration::from_ticks(self.initial_delay.as_ticks().saturating_mul(factor));
+        delay.min(self.max_delay)
+    }
+
+    /// Run `f` until it succeeds, fails permanently, or we run out of attempts.
+    /// At most once: a failure that may have got through isn't retried.
+    /// `on_retry` is called before each retry: e.g. to count them.
+    /// `f` may borrow mutably: e.g. a notifier, see `notifier::deliver()`.
+    pub async fn run<T, E>(&self, mut f: impl AsyncFnMut() -> Result<T, E>, mut on_retry: impl FnMut(u32, &E)) -> Result<T, E>
+    where
+        E: Retryable + defmt::Format,
+    {
+        let mut attempt = 1;
+        loop {
+            let e = match f().await {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+
+            if e.is_permanent() {
+                defmt::error!("Attempt {}: permanent failure: {:?}", attempt, e);
+                return Err(e);
+            }
+            if self.mode == DeliveryMode::AtMostOnce && e.maybe_delivered() {
+                defmt::error!("Attempt {}: failed, may have got through: not retrying: {:?}", attempt, e);
+                return Err(e);
+            }
+            if attempt >= self.max_attempts {
+                defmt::error!("Attempt {}: failed, giving up: {:?}", attempt, e);
+                return Err(e);
+            }
+
+            // Server says when? Otherwise, back off.
+            let wait = e.retry_after().unwrap_or_else(|| self.delay(attempt));
+            defmt::warn!("Attempt {}: failed, retrying in {} ms: {:?}", attempt, wait.as_millis(), e);
+            on_retry(attempt, &e);
+            Timer::after(wait).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// SNTP: the wall-clock time from an NTP server, for `clock`.
+
+use defmt;
+use core::net::IpAddr;
+
+use embassy_net::{
+    IpAddress,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Timer, with_timeout};
+
+use crate::clock::set_unix_time;
+use crate::dns::{RecordType, Resolver};
+
+
+// NTP server to get the time from
+const NTP_SERVER: &str = match option_env!("NTP_SERVER") {
+    Some(v) if !v.is_empty() => v,
+    _ => "pool.ntp.org",
+};
+
+// Re-sync every hour: the crystal drifts
+const SYNC_INTERVAL: Duration = Duration::from_secs(3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// NTP counts seconds from 1900, Unix from 1970
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+
+// Task: keep the wall clock in sync
+#[embassy_executor::task]
+pub async fn task_sntp(stack: embassy_net::Stack<'static>) {
+    loop {
+        stack.wait_config_up().await;
+
+        match sntp_query(stack).await {
+            Ok(unix) => {
+                set_unix_time(unix);
+                defmt::info!("Clock: synced, unix={}", unix);
+                Timer::after(SYNC_INTERVAL).await;
+            }
+            Err(()) => {
+                defmt::warn!("Clock: sync failed");
+                Timer::after(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// Ask an NTP server for the time. Errors: see logs.
+async fn sntp_query(stack: embassy_net::Stack<'_>) -> Result<u64, ()> {
+    let server = match Resolver::new(stack).query(NTP_SERVER, RecordType::A).await {
+        Ok(IpAddr::V4(addr)) => IpAddress::Ipv4(addr),
+        _ => return Err(()),
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 128];
+    let mut tx_buffer = [0u8; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket.bind(0).map_err(|_| ())?;
+
+    // Request: LI=0, VN=4, Mode=3 (client)
+    let mut packet = [0u8; 48];
+    packet[0] = 0b00_100_011;
+    socket.send_to(&packet, (server, 123))
+        .await
+        .map_err(|e| defmt::warn!("Clock: send failed: {:?}", e))?;
+
+    // Response: "transmit timestamp" seconds
+    let (len, _) = with_timeout(Duration::from_secs(5), socket.recv_from(&mut packet))
+        .await
+        .map_err(|_| defmt::warn!("Clock: timeout"))?
+        .map_err(|e| defmt::warn!("Clock: recv failed: {:?}", e))?;
+    if len < 48 {
+        return Err(());
+    }
+    let ntp_secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    match ntp_secs.checked_sub(NTP_UNIX_OFFSET) {
+        Some(unix) if unix > 0 => Ok(unix),
+        _ => Err(()),
+    }
+}
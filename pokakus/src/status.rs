@@ -0,0 +1,167 @@
+use defmt;
+use core::cell::Cell;
+use core::fmt;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant};
+
+#[cfg(feature = "telegram")]
+use crate::bot_identity::Identity;
+use crate::dns::DnsCacheStats;
+use crate::ratelimit::{Cooldown, Limited, MAX_COOLDOWNS};
+use crate::recipient::MAX_RECIPIENTS;
+
+
+/// Message delivery counters
+#[derive(defmt::Format, Clone, Copy, Default)]
+pub struct Counters {
+    pub sent: u32,     // delivered
+    pub retries: u32,  // attempts that failed and were retried
+    pub gave_up: u32,  // messages dropped after all attempts
+    pub expired: u32,  // stale messages dropped from the outbox
+    pub suppressed: u32,  // events over the rate limit: not sent
+}
+
+/// Update counters
+pub fn update_counters(f: impl FnOnce(&mut Counters)) {
+    COUNTERS.lock(|c| {
+        let mut counters = c.get();
+        f(&mut counters);
+        c.set(counters);
+    });
+}
+
+/// Get counters
+pub fn counters() -> Counters {
+    COUNTERS.lock(|c| c.get())
+}
+
+static COUNTERS: Mutex<CriticalSectionRawMutex, Cell<Counters>> = Mutex::new(Cell::new(Counters {
+    sent: 0,
+    retries: 0,
+    gave_up: 0,
+    expired: 0,
+    suppressed: 0,
+}));
+
+
+/// Delivery status: per recipient
+#[derive(defmt::Format, Clone, Copy, Default)]
+pub struct RecipientStatus {
+    pub sent: u32,
+    pub failed: u32,
+    pub failing: bool,  // the last attempt has failed
+    pub last_error_code: Option<u16>,  // Telegram's error code, if it has responded
+}
+
+/// Record a delivery attempt. Error: Telegram's error code, if any.
+pub fn record_delivery(recipient_index: usize, result: Result<(), Option<u16>>) {
+    RECIPIENTS.lock(|r| {
+        let mut statuses = r.get();
+        if let Some(s) = statuses.get_mut(recipient_index) {
+            match result {
+                Ok(()) => s.sent += 1,
+                Err(code) => {
+                    s.failed += 1;
+                    s.last_error_code = code;
+                }
+            }
+            s.failing = result.is_err();
+        }
+        r.set(statuses);
+    });
+}
+
+/// Get delivery status: per recipient, in the order of `TELEGRAM_SEND_TO`
+pub fn recipient_statuses() -> [RecipientStatus; MAX_RECIPIENTS] {
+    RECIPIENTS.lock(|r| r.get())
+}
+
+static RECIPIENTS: Mutex<CriticalSectionRawMutex, Cell<[RecipientStatus; MAX_RECIPIENTS]>> = Mutex::new(Cell::new(
+    [RecipientStatus { sent: 0, failed: 0, failing: false, last_error_code: None }; MAX_RECIPIENTS]
+));
+
+
+/// Device status: everything worth knowing at a glance
+#[derive(defmt::Format)]
+pub struct StatusReport {
+    pub uptime: Duration,
+    pub ipv4: Option<embassy_net::Ipv4Cidr>,
+    #[cfg(feature = "ipv6")]
+    pub ipv6: Option<embassy_net::Ipv6Cidr>,
+    pub dns_cache: DnsCacheStats,
+    pub counters: Counters,
+    pub recipients: [RecipientStatus; MAX_RECIPIENTS],
+    #[cfg(feature = "telegram")]
+    pub rejected: u32,  // unauthorized commands
+    pub queued: u32,    // messages in the outbox
+    pub cooldowns: heapless::Vec<Cooldown, MAX_COOLDOWNS>,  // rate limits: what's cooling down
+    #[cfg(feature = "telegram")]
+    pub bot: Identity,  // getMe: is the token any good
+}
+
+/// Collect the status report
+pub fn status_report(stack: embassy_net::Stack<'_>) -> StatusReport {
+    StatusReport {
+        uptime: Duration::from_ticks(Instant::now().as_ticks()),
+        ipv4: stack.config_v4().map(|c| c.address),
+        #[cfg(feature = "ipv6")]
+        ipv6: stack.config_v6().map(|c| c.address),
+        dns_cache: crate::dns::cache_stats(),
+        counters: counters(),
+        recipients: recipient_statuses(),
+        #[cfg(feature = "telegram")]
+        rejected: crate::auth::rejected_count(),
+        queued: crate::outbox::pending_count(),
+        cooldowns: crate::ratelimit::cooldowns(),
+        #[cfg(feature = "telegram")]
+        bot: crate::bot_identity::identity(),
+    }
+}
+
+// Human-readable: for messages
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Uptime: {} min", self.uptime.as_secs() / 60)?;
+        match self.ipv4 {
+            Some(ip) => writeln!(f, "IPv4: {}", ip)?,
+            None => writeln!(f, "IPv4: none")?,
+        }
+        #[cfg(feature = "ipv6")]
+        match self.ipv6 {
+            Some(ip) => writeln!(f, "IPv6: {}", ip)?,
+            None => writeln!(f, "IPv6: none")?,
+        }
+        #[cfg(feature = "telegram")]
+        match &self.bot {
+            Identity::Valid(bot) => writeln!(f, "Bot: @{}", bot.username)?,
+            Identity::Unauthorized => writeln!(f, "Bot: token rejected")?,
+            Identity::Unknown => (),
+        }
+        writeln!(f, "DNS cache: {} hits, {} misses", self.dns_cache.hits, self.dns_cache.misses)?;
+        write!(f, "Messages: {} sent, {} retries, {} gave up, {} expired, {} suppressed", self.counters.sent, self.counters.retries, self.counters.gave_up, self.counters.expired, self.counters.suppressed)?;
+        write!(f, "\nOutbox: {} waiting", self.queued)?;
+        for (i, r) in self.recipients.iter().enumerate().filter(|(_, r)| r.sent + r.failed > 0) {
+            write!(f, "\nRecipient #{}: {} sent, {} failed", i + 1, r.sent, r.failed)?;
+            match (r.failing, r.last_error_code) {
+                (true, Some(code)) => write!(f, ", failing: error {}", code)?,
+                (true, None) => write!(f, ", failing")?,
+                (false, _) => (),
+            }
+        }
+        for c in &self.cooldowns {
+            match c.what {
+                Limited::Events => write!(f, "\nCooldown: clicks")?,
+                Limited::Recipient(i) => write!(f, "\nCooldown: recipient #{}", i + 1)?,
+            }
+            write!(f, ", {} s left", c.ready_in.as_secs().max(1))?;
+        }
+        #[cfg(feature = "telegram")]
+        if self.rejected > 0 {
+            write!(f, "\nRejected commands: {}", self.rejected)?;
+        }
+        Ok(())
+    }
+}
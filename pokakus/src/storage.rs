@@ -0,0 +1,251 @@
+// Persistent storage: flash.
+//
+// We use the "nvs" partition from the partition table: esp-radio doesn't need it.
+// Layout: 4K sectors.
+// - Sector 0: key-value log. Small values (u64), appended as records;
+//   the last record of a key wins. When the sector is full, it's compacted: erased and rewritten.
+//   An erase per ~250 writes: that's our wear levelling.
+// - Other sectors: the outbox, see `outbox`.
+
+use defmt;
+
+use embassy_sync::{
+    mutex::Mutex,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_bootloader_esp_idf::partitions;
+use esp_storage::FlashStorage;
+use heapless::Vec;
+
+
+/// Flash sector size
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Keys. Never reuse a number: old values may still be in flash.
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Key {
+    TelegramUpdatesOffset = 1,  // getUpdates: the next update_id
+    BootCount = 2,              // boots so far: see `outbox`
+}
+
+
+/// Init storage: find the partition
+pub async fn init(flash: esp_hal::peripherals::FLASH<'static>) -> Result<(), StorageError> {
+    let mut flash = FlashStorage::new(flash);
+
+    // Find the partition
+    let mut pt_buf = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+    let pt = partitions::read_partition_table(&mut flash, &mut pt_buf)
+        .map_err(|e| {
+            defmt::error!("Storage: can't read the partition table: {:?}", e);
+            StorageError::NoPartition
+        })?;
+    let partition = pt.find_partition(partitions::PartitionType::Data(partitions::DataPartitionSubType::Nvs))
+        .ok()
+        .flatten()
+        .ok_or(StorageError::NoPartition)?;
+    let (offset, size) = (partition.offset(), partition.len());
+    defmt::info!("Storage: nvs partition at {=u32:#x}, {} bytes", offset, size);
+
+    *STORAGE.lock().await = Some(Storage { flash, offset, size });
+    Ok(())
+}
+
+/// Load a value
+pub async fn load(key: Key) -> Option<u64> {
+    let mut storage = STORAGE.lock().await;
+    let storage = storage.as_mut()?;
+    match storage.kv_load(key as u8) {
+        Ok(v) => v,
+        Err(e) => {
+            defmt::error!("Storage: can't load {:?}: {:?}", key, e);
+            None
+        }
+    }
+}
+
+/// Store a value
+pub async fn store(key: Key, value: u64) -> Result<(), StorageError> {
+    let mut storage = STORAGE.lock().await;
+    let storage = storage.as_mut().ok_or(StorageError::NoPartition)?;
+    storage.kv_store(key as u8, value)
+}
+
+/// Access a sector range of the partition directly: raw flash, offsets relative to `first_sector`.
+/// Sector 0 is taken.
+pub async fn with_sectors<R>(first_sector: u32, n_sectors: u32, f: impl FnOnce(&mut Region<'_>) -> R) -> Result<R, StorageError> {
+    let mut storage = STORAGE.lock().await;
+    let storage = storage.as_mut().ok_or(StorageError::NoPartition)?;
+    if first_sector == 0 || (first_sector + n_sectors) * SECTOR_SIZE > storage.size {
+        return Err(StorageError::OutOfRange);
+    }
+    let mut region = Region {
+        flash: &mut storage.flash,
+        offset: storage.offset + first_sector * SECTOR_SIZE,
+        size: n_sectors * SECTOR_SIZE,
+    };
+    Ok(f(&mut region))
+}
+
+/// How many sectors does the partition have
+pub async fn sector_count() -> u32 {
+    match STORAGE.lock().await.as_ref() {
+        Some(s) => s.size / SECTOR_SIZE,
+        None => 0,
+    }
+}
+
+
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum StorageError {
+    NoPartition,  // not initialized, or no partition
+    OutOfRange,
+    Flash,        // see logs
+}
+
+
+/// A region of flash
+pub struct Region<'a> {
+    flash: &'a mut FlashStorage<'static>,
+    offset: u32,
+    size: u32,
+}
+
+impl Region<'_> {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.check(offset, buf.len())?;
+        self.flash.read(self.offset + offset, buf).map_err(flash_error)
+    }
+
+    /// Write. Flash can only flip bits 1→0: write into erased space only.
+    pub fn write(&mut self, offset: u32, buf: &[u8]) -> Result<(), StorageError> {
+        self.check(offset, buf.len())?;
+        self.flash.write(self.offset + offset, buf).map_err(flash_error)
+    }
+
+    /// Erase a sector: all bytes become 0xFF
+    pub fn erase_sector(&mut self, sector: u32) -> Result<(), StorageError> {
+        let from = sector * SECTOR_SIZE;
+        self.check(from, SECTOR_SIZE as usize)?;
+        self.flash.erase(self.offset + from, self.offset + from + SECTOR_SIZE).map_err(flash_error)
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(), StorageError> {
+        if offset as usize + len > self.size as usize {
+            return Err(StorageError::OutOfRange);
+        }
+        Ok(())
+    }
+}
+
+fn flash_error(e: esp_storage::FlashStorageError) -> StorageError {
+    defmt::error!("Storage: flash error: {:?}", defmt::Debug2Format(&e));
+    StorageError::Flash
+}
+
+
+
+static STORAGE: Mutex<CriticalSectionRawMutex, Option<Storage>> = Mutex::new(None);
+
+struct Storage {
+    flash: FlashStorage<'static>,
+    offset: u32,  // partition offset
+    size: u32,    // partition size
+}
+
+
+// Key-value record: 16 bytes
+// [0] magic, [1] key, [2..4] zero, [4..12] value (LE), [12..16] checksum
+const RECORD_SIZE: u32 = 16;
+const RECORD_MAGIC: u8 = 0x5A;
+const MAX_KEYS: usize = 16;
+
+impl Storage {
+    fn kv_region(&mut self) -> Region<'_> {
+        Region { flash: &mut self.flash, offset: self.offset, size: SECTOR_SIZE }
+    }
+
+    // Scan the log: latest values, and where the free space begins
+    fn kv_scan(&mut self) -> Result<(Vec<(u8, u64), MAX_KEYS>, u32), StorageError> {
+        let mut region = self.kv_region();
+        let mut values: Vec<(u8, u64), MAX_KEYS> = Vec::new();
+        let mut pos = 0;
+        while pos < SECTOR_SIZE {
+            let mut record = [0u8; RECORD_SIZE as usize];
+            region.read(pos, &mut record)?;
+            if record.iter().all(|b| *b == 0xFF) {
+                break;  // free space
+            }
+            if let Some((key, value)) = decode_record(&record) {
+                match values.iter_mut().find(|(k, _)| *k == key) {
+                    Some(entry) => entry.1 = value,
+                    None => { let _ = values.push((key, value)); }
+                }
+            }
+            // Corrupt records (e.g. power loss mid-write) are skipped
+            pos += RECORD_SIZE;
+        }
+        Ok((values, pos))
+    }
+
+    fn kv_load(&mut self, key: u8) -> Result<Option<u64>, StorageError> {
+        let (values, _) = self.kv_scan()?;
+        Ok(values.iter().find(|(k, _)| *k == key).map(|(_, v)| *v))
+    }
+
+    fn kv_store(&mut self, key: u8, value: u64) -> Result<(), StorageError> {
+        let (mut values, free) = self.kv_scan()?;
+
+        // Unchanged? Save the flash.
+        if values.iter().any(|(k, v)| *k == key && *v == value) {
+            return Ok(());
+        }
+
+        // Room left? Append.
+        if free + RECORD_SIZE <= SECTOR_SIZE {
+            return self.kv_region().write(free, &encode_record(key, value));
+        }
+
+        // Full: compact. Erase and write the latest values only.
+        defmt::info!("Storage: compacting");
+        match values.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => values.push((key, value)).map_err(|_| StorageError::OutOfRange)?,
+        }
+        let mut region = self.kv_region();
+        region.erase_sector(0)?;
+        for (i, (k, v)) in values.iter().enumerate() {
+            region.write(i as u32 * RECORD_SIZE, &encode_record(*k, *v))?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_record(key: u8, value: u64) -> [u8; RECORD_SIZE as usize] {
+    let mut record = [0u8; RECORD_SIZE as usize];
+    record[0] = RECORD_MAGIC;
+    record[1] = key;
+    record[4..12].copy_from_slice(&value.to_le_bytes());
+    let checksum = fnv1a(&record[..12]);
+    record[12..16].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+fn decode_record(record: &[u8; RECORD_SIZE as usize]) -> Option<(u8, u64)> {
+    let checksum = u32::from_le_bytes(record[12..16].try_into().ok()?);
+    if record[0] != RECORD_MAGIC || checksum != fnv1a(&record[..12]) {
+        return None;
+    }
+    Some((record[1], u64::from_le_bytes(record[4..12].try_into().ok()?)))
+}
+
+/// FNV-1a: a simple checksum
+pub fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5u32, |h, b| (h ^ *b as u32).wrapping_mul(0x01000193))
+}
@@ -3,179 +3,429 @@ use heapless::{
     String,
 };
 
-use esp_hal::{rng::Rng};
-use reqwless::{
-    client::{HttpClient, TlsConfig},
-    headers::ContentType, request::RequestBuilder
+use reqwless::request::Method;
+
+use embassy_time::{Duration, Instant};
+
+use crate::ack;
+use crate::bot_api::{self, MessageOptions, SentMessage, TelegramSendMessageError, Transport};
+use crate::coalesce::{self, Burst};
+use crate::config;
+use crate::http::{self, HttpError};
+use crate::notifier::{self, Capabilities, Notifier, DELIVERY_MODE};
+use crate::outbox::{self, Entry, OutgoingMessage, Since};
+use crate::recipient::{ChatId, DeliveryPolicy, Recipient, Recipients, MAX_RECIPIENTS};
+use crate::ratelimit;
+use crate::retry::RetryPolicy;
+use crate::template;
+use crate::text::ParseMode;
+use crate::undo;
+
+// Bot token
+const BOT_TOKEN: &str = env!("TELEGRAM_BOT_TOKEN");
+
+// Recipients: users, groups, @channels, forum topics. See `recipient`.
+const RECIPIENTS: Recipients = Recipients::parse(env!("TELEGRAM_SEND_TO"));
+// Needed only if it's going to send: e.g. not with NOTIFIERS=ntfy
+const _: () = assert!(!notifier::is_enabled("telegram") || !RECIPIENTS.is_empty(), "No recipients: set TELEGRAM_SEND_TO");
+
+// Message options: formatting, notification
+const MESSAGE_OPTIONS: MessageOptions = MessageOptions {
+    parse_mode: ParseMode::parse(option_env!("TELEGRAM_PARSE_MODE")),
+    disable_notification: config::parse_bool(option_env!("TELEGRAM_DISABLE_NOTIFICATION"), false),
+    protect_content: config::parse_bool(option_env!("TELEGRAM_PROTECT_CONTENT"), false),
+    disable_link_preview: config::parse_bool(option_env!("TELEGRAM_DISABLE_LINK_PREVIEW"), false),
+    ack_buttons: config::parse_bool(option_env!("TELEGRAM_ACK_BUTTONS"), true),
 };
-use serde::Serialize;
-use embassy_net::{
-    dns::DnsSocket,
-    tcp::client::{TcpClient, TcpClientState},
+
+// When is a message "sent": every recipient has got it, or any?
+const DELIVERY_POLICY: DeliveryPolicy = DeliveryPolicy::parse(option_env!("TELEGRAM_DELIVERY_POLICY"));
+
+// Escalation: nobody's acknowledged an event for this long? Notify again, louder.
+// Then, the escalation chat. `None`: don't escalate.
+const ESCALATION_DELAY: Option<Duration> = match config::parse_u32(option_env!("TELEGRAM_ESCALATION_MINUTES"), 0) {
+    0 => None,
+    minutes => Some(Duration::from_secs(minutes as u64 * 60)),
 };
-use embassy_sync::{
-    channel::Channel,
-    blocking_mutex::raw::CriticalSectionRawMutex,
+const ESCALATION_CHAT: Option<Recipient> = match option_env!("TELEGRAM_ESCALATION_CHAT") {
+    Some(v) if !v.is_empty() => Some(Recipient::parse(v)),
+    _ => None,
 };
 
-// Bot token
-const BOT_TOKEN: &str = env!("TELEGRAM_BOT_TOKEN");
-const SEND_TO: &str = env!("TELEGRAM_SEND_TO");
+// Bot API server: Telegram's, a self-hosted one, or a stand-in for testing (see `mock-bot-api/`).
+// "http://": no TLS, the token goes in plain text. Only for a stand-in on your own network.
+const API_URL: &str = match option_env!("TELEGRAM_API_URL") {
+    Some(v) if !v.is_empty() => check_api_url(v),
+    _ => "https://api.telegram.org",
+};
 
-/// Send a message
-pub fn send_telegram_message(msg: &str){
-    // We only got a reference. To take ownership, we need a copy.
-    let owned: String<32> = String::try_from(msg).unwrap();
-    match MESSAGES_QUEUE.try_send(owned) {
-        Ok(()) => (),
-        Err(_) => defmt::error!("Queue full: cannot send message"),
+const fn check_api_url(url: &str) -> &str {
+    let bytes = url.as_bytes();
+    if bytes[bytes.len() - 1] == b'/' {
+        panic!("Bot API URL: no trailing slash, please");
     }
+    if !http::is_http_url(url) {
+        panic!("Bot API URL: expected https:// or http://");
+    }
+    url
 }
 
-/// Messages queue
-static MESSAGES_QUEUE: Channel<CriticalSectionRawMutex, String::<32>, 8> = Channel::new();
-
-// Task: send messages to Telegram
-#[embassy_executor::task()]
-pub async fn task_telegram_sender(stack: embassy_net::Stack<'static>) {
-    let send_to: i64 = SEND_TO.parse().expect("Failed to parse SEND_TO");
-
-    // Input
-    let receiver = MESSAGES_QUEUE.receiver();
-    loop {
-        let message = receiver.receive().await;
-
-        // Wait for network
-        // TODO: timeout, warning?
-        stack.wait_config_up().await;
-
-        // Request
-        defmt::debug!("Telegram: sending message...");
-        let led_status = crate::led_op::Status::new();
-        match telegram_send_message(stack, send_to, message.as_str()).await {
-            Ok(()) => {
-                defmt::info!("Message sent!");
-                led_status.success();
-            },
-            Err(e) => {
-                defmt::error!("Failed to send: {:?}", defmt::Debug2Format(&e));
-                led_status.failure();
+// The CA we expect Telegram's certificate to be issued by: DER file
+const TELEGRAM_CA_CERT: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", env!("TELEGRAM_CA_CERT")));
+
+// Retries: exponential backoff
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: config::parse_u32(option_env!("TELEGRAM_RETRY_MAX_ATTEMPTS"), 5),
+    initial_delay: Duration::from_millis(config::parse_u32(option_env!("TELEGRAM_RETRY_INITIAL_DELAY_MS"), 2_000) as u64),
+    max_delay: Duration::from_millis(config::parse_u32(option_env!("TELEGRAM_RETRY_MAX_DELAY_MS"), 60_000) as u64),
+    mode: DELIVERY_MODE,
+};
+
+/// The longest message Telegram accepts: UTF-16 code units.
+/// Longer messages are truncated.
+pub const MESSAGE_MAX_LEN: usize = 4096;
+
+impl OutgoingMessage {
+    /// Message text: render the template, append the burst and the event id (`tag`), truncate.
+    /// Returns the text and its parse mode.
+    fn render(&self, burst: Option<&Burst>, tag: Option<&str>) -> (alloc::string::String, Option<ParseMode>) {
+        let (mut text, parse_mode) = match self {
+            // Free text is plain: it's not markup, and it's not escaped
+            OutgoingMessage::Text(text) | OutgoingMessage::Reply { text, .. } => (text.clone(), None),
+            OutgoingMessage::Undo => (alloc::string::String::new(), None),
+            OutgoingMessage::Event { template, event } => {
+                let ctx = template::Context {
+                    parse_mode: MESSAGE_OPTIONS.parse_mode,
+                    json: false,
+                    event: Some(event),
+                    hostname: crate::wifi::hostname(),
+                    device_id: esp_hal::efuse::Efuse::mac_address(),
+                    rssi: crate::wifi::rssi(),
+                    message: None,
+                    event_id: None,
+                };
+                let mut text = alloc::string::String::new();
+                let _ = template::render(&mut text, template, &ctx);  // String never fails
+                if let Some(burst) = burst {
+                    let _ = coalesce::write_burst(&mut text, burst, event, MESSAGE_OPTIONS.parse_mode);
+                }
+                (text, MESSAGE_OPTIONS.parse_mode)
             }
+        };
+
+        // Event id: "#ev1a2b3c". A hashtag: tap it to find the duplicates.
+        if let Some(tag) = tag {
+            use core::fmt::Write;
+            text.push_str("\n\n");
+            let _ = crate::text::Escape { inner: &mut text, mode: parse_mode }.write_str(tag);  // String never fails
         }
+
+        // Too long? Truncate.
+        // Cutting markup in half would break it, and Telegram would reject the message:
+        // so it goes as plain text.
+        if crate::text::utf16_len(&text) > MESSAGE_MAX_LEN {
+            defmt::warn!("Message too long: truncating");
+            return (crate::text::truncate_with_marker(&text, MESSAGE_MAX_LEN), None);
+        }
+        (text, parse_mode)
     }
 }
 
-// Send a message
-async fn telegram_send_message(stack: embassy_net::Stack<'_>, send_to: i64, message: &str) -> Result<(), TelegramSendMessageError> {
-    // TLS needs a random value
-    let rng = Rng::new();  // it's ok: nothing's really initialized
-    let tls_seed = {
-        let mut bytes = [0; 8];
-        rng.read(&mut bytes);
-        u64::from_le_bytes(bytes)
+
+/// Telegram: a notifier, see `notifier`.
+/// Fans out to every recipient; messages have buttons, can be undone, and escalate.
+pub struct TelegramNotifier {
+    recipients: Recipients,           // a copy, because chats may migrate
+    escalation: Option<Escalation>,   // not acknowledged yet
+    next_group: u32,
+    since: Option<(u32, Since)>,      // the entry being sent, and where to look for it: see `since()`
+}
+
+impl Default for TelegramNotifier {
+    fn default() -> TelegramNotifier {
+        TelegramNotifier { recipients: RECIPIENTS, escalation: None, next_group: 0, since: None }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    type Error = TelegramSendMessageError;
+
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { replies: true, undo: true }
+    }
+
+    // Every recipient is retried on its own: see `fan_out()`
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy { max_attempts: 1, ..RETRY_POLICY }
+    }
+
+    async fn send(&mut self, stack: embassy_net::Stack<'_>, entry: &Entry) -> Result<(), TelegramSendMessageError> {
+        send_outgoing(stack, entry, &mut self.recipients, &mut self.escalation, &mut self.next_group, &mut self.since).await
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.escalation.as_ref().map(|e| e.deadline)
+    }
+
+    // Nobody's acknowledged: escalate
+    async fn on_deadline(&mut self, stack: embassy_net::Stack<'_>) {
+        if let Some(e) = self.escalation.take() {
+            self.escalation = escalate(stack, &mut self.recipients, e).await;
+        }
+    }
+}
+
+// Send a message from the outbox
+async fn send_outgoing(stack: embassy_net::Stack<'_>, entry: &Entry, recipients: &mut Recipients, escalation: &mut Option<Escalation>, next_group: &mut u32, since: &mut Option<(u32, Since)>) -> Result<(), TelegramSendMessageError> {
+    let message = &entry.message;
+
+    // A reply: to one chat only
+    if let OutgoingMessage::Reply { to, .. } = message {
+        let to = *to;
+        let (text, _) = message.render(None, None);
+        let options = MessageOptions { parse_mode: None, ack_buttons: false, ..MESSAGE_OPTIONS };
+        let api = TelegramApi::new(stack);
+        let result = RETRY_POLICY.run(
+            || bot_api::send_message(&api, to, &text, &options),
+            |_, _| (),
+        ).await;
+        if let Err(e) = &result {
+            defmt::error!("Failed to reply to {}: {:?}", to.chat_id, defmt::Debug2Format(e));
+        }
+        return result.map(|_| ());
+    }
+
+    // Undo: retract the last event message. It won't need escalating anymore.
+    if let OutgoingMessage::Undo = message {
+        *escalation = None;
+        return undo_last(stack).await;
+    }
+
+    // Render: just before sending.
+    // Events can be acknowledged: with buttons.
+    let is_event = matches!(message, OutgoingMessage::Event { .. });
+    let ack_buttons = MESSAGE_OPTIONS.ack_buttons && is_event;
+    let tag = crate::notifier::event_tag(entry);
+    let (message, parse_mode) = message.render(entry.burst.as_ref(), tag.as_deref());
+    let options = MessageOptions { parse_mode, ack_buttons, ..MESSAGE_OPTIONS };
+    let group = *next_group;
+    *next_group = next_group.wrapping_add(1);
+    let since = entry_since(entry, recipients, since).await;
+
+    // Request. Retry transient failures.
+    defmt::debug!("Telegram: sending message...");
+    let (sent, error) = fan_out(stack, recipients, &message, &options, group, &since).await;
+    let succeeded = sent.len();
+
+    // Remember it: to undo it
+    if is_event && succeeded > 0 {
+        undo::record_last(undo::LastSent { text: message.clone(), parse_mode, messages: sent });
+    }
+
+    // Watch for acknowledgement: the newest event only
+    if let (true, Some(delay), true) = (ack_buttons, ESCALATION_DELAY, succeeded > 0) {
+        *escalation = Some(Escalation { group, level: 0, deadline: Instant::now() + delay, text: message, parse_mode });
+    }
+
+    // Outcome: depends on the policy
+    if DELIVERY_POLICY.is_success(succeeded, recipients.len()) {
+        defmt::info!("Message sent! {}/{} recipients", succeeded, recipients.len());
+        Ok(())
+    } else {
+        defmt::error!("Failed to send: {}/{} recipients", succeeded, recipients.len());
+        Err(error.unwrap_or(TelegramSendMessageError::InvalidArguments))  // no recipients
+    }
+}
+
+// Where to look for the entry's message, should it get through unnoticed: see `bot_api::reconcile()`.
+// The last messages we know of on the first attempt; kept with the entry, see `outbox::set_since()`.
+// Nothing else is sent till the entry's done (see `notifier::send_entry()`): after them, a message of ours is this one.
+async fn entry_since(entry: &Entry, recipients: &Recipients, kept: &mut Option<(u32, Since)>) -> Since {
+    if let Some((id, since)) = kept.as_ref() && *id == entry.id {
+        return since.clone();
+    }
+    let since = match &entry.since {
+        Some(since) => since.clone(),  // before a reboot
+        None => {
+            let since: Since = recipients.iter().filter_map(|r| Some((r.chat_id, bot_api::last_message_id(r.chat_id)?))).collect();
+            outbox::set_since(entry, &since).await;
+            since
+        }
     };
+    *kept = Some((entry.id, since.clone()));
+    since
+}
 
-    // Init TLS.
-    // Quirks:
-    // 1. TLS recommends that the rx buffer is at least 16640 bytes long: because this is the size of the biggest packet (2^14+256).
-    // 2. By default, the `reqwless` crate uses `embedded-tls`, which is limited to algorithms that can run entirely on the stack.
-    //    To enable all algorithms, add the "alloc" feature.
-    //    To check whether the default list suffices with your server:
-    //    $ vopenssl s_client -tls1_3 -ciphersuites TLS_AES_128_GCM_SHA256 -sigalgs "ECDSA+SHA256:ECDSA+SHA384:ed25519" -connect api.telegram.org:443
-    // 3. On ESP32, you can further speed up TLS by using the RSA peripheral.
-    //    TODO: see `esp-mbedtls`
-    let (mut rx_buffer, mut tx_buffer) = ([0; 16640], [0; 16640]);
-    let tls = TlsConfig::new(
-        tls_seed,
-        &mut rx_buffer,
-        &mut tx_buffer,
-        reqwless::client::TlsVerify::None,
+// Fan out: every recipient, one by one. Returns the messages sent: chat, message_id; and the last error.
+// Messages with buttons are remembered: see `ack`. Lost ones are looked for after `since`.
+async fn fan_out(stack: embassy_net::Stack<'_>, recipients: &mut Recipients, message: &str, options: &MessageOptions, group: u32, since: &Since) -> (heapless::Vec<(ChatId, i64), MAX_RECIPIENTS>, Option<TelegramSendMessageError>) {
+    let send = |recipient: Recipient| RETRY_POLICY.run(
+        move || async move {
+            stack.wait_config_up().await;
+            let since = since.iter().find(|(chat, _)| *chat == recipient.chat_id).map(|&(_, id)| id);
+            bot_api::send_or_reconcile(&TelegramApi::new(stack), recipient, message, options, since).await
+        },
+        |_, _| crate::status::update_counters(|c| c.retries += 1),
     );
 
-    let tcp_state = TcpClientState::<1, 4096, 4096>::new();
-    let tcp = TcpClient::new(stack, &tcp_state);
-    let dns = DnsSocket::new(stack);
-    let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
-
-    // Data
-    let mut url: String<128> = String::new();
-    use core::fmt::Write;
-    write!(url, "https://api.telegram.org/bot{}/sendMessage", BOT_TOKEN).unwrap();
-    // write!(url, "https://jsonplaceholder.typicode.com/posts").unwrap();  // for testing
-    // let mut body: String<256> = String::new();
-    // write!(body, r#"{{"chat_id":{},"text":"{}"}}"#, send_to, message).unwrap();
-    let msg = TelegramMessageInput {
-        chat_id: send_to,
-        text: message,
+    let mut sent_messages = heapless::Vec::new();
+    let mut error = None;
+    for index in 0..recipients.len() {
+        let Some(&recipient) = recipients.iter().nth(index) else { break };
+        let mut sent_to = recipient;
+        ratelimit::wait_recipient(index).await;
+        let mut result = send(recipient).await;
+
+        // The group has become a supergroup: it has a new id. Use it from now on.
+        if let Some(new_chat_id) = result.as_ref().err().and_then(|e| e.migrate_to_chat_id()) {
+            defmt::warn!("Telegram: chat {} has moved to {}. Update TELEGRAM_SEND_TO!", recipient.chat_id, new_chat_id);
+            recipients.set_chat_id(index, ChatId::Id(new_chat_id));
+            sent_to = Recipient { chat_id: ChatId::Id(new_chat_id), ..recipient };
+            result = send(sent_to).await;
+        }
+
+        crate::status::record_delivery(index, result.as_ref().map(|_| ()).map_err(|e| e.error_code()));
+        match result {
+            Ok(sent) => {
+                defmt::info!("Message sent to {}", recipient.chat_id);
+                match (options.ack_buttons, sent.chat) {
+                    (true, Some(chat)) => ack::record_sent(group, chat.id, sent.message_id, alloc::string::String::from(message), options.parse_mode),
+                    (true, None) => defmt::warn!("Telegram: chat of {} unknown: acknowledgements will be plain text", recipient.chat_id),
+                    (false, _) => (),
+                }
+                let _ = sent_messages.push((sent_to.chat_id, sent.message_id));  // one per recipient: fits
+            }
+            Err(e) => {
+                defmt::error!("Failed to send to {}: {:?}", recipient.chat_id, defmt::Debug2Format(&e));
+                error = Some(e);
+            }
+        }
+    }
+    (sent_messages, error)
+}
+
+// Undo the last event message: every copy of it
+async fn undo_last(stack: embassy_net::Stack<'_>) -> Result<(), TelegramSendMessageError> {
+    let Some(last) = undo::take_last() else {
+        defmt::warn!("Undo: nothing to undo");
+        return Ok(());
     };
-    let mut body_buf = [0u8; 256];
-    let _body_len = serde_json_core::to_slice(&msg, &mut body_buf)?;
-
-    // Request
-    let mut buf = [0; 4096];
-    let mut req = client.request(reqwless::request::Method::POST, url.as_str())
-        .await?
-        .content_type(ContentType::ApplicationJson)
-        .body(body_buf.as_slice());
-    let resp = req.send(&mut buf)
-        .await?;
-
-    // Read response
-    let response = resp.body().read_to_end()
-        .await?;
-    let resp_text = core::str::from_utf8(&response)
-        .map_err(|_| TelegramSendMessageError::ResponseError)?;
-
-    // Check for success
-    if !resp_text.contains(r#""ok":true"#) {
-        defmt::error!("Telegram failed: {}", resp_text);
-        return Err(TelegramSendMessageError::ResponseError)
-    }
-
-    // Ok
-    return Ok(())
+    defmt::info!("Undo: {:?} {} messages", undo::UNDO_ACTION, last.messages.len());
+
+    let (cancelled, parse_mode) = undo::cancelled_text(&last.text, last.parse_mode);
+    let cancelled = cancelled.as_str();
+    let mut error = None;
+    for &(chat_id, message_id) in &last.messages {
+        let result = RETRY_POLICY.run(
+            move || async move {
+                match undo::UNDO_ACTION {
+                    undo::UndoAction::Edit => bot_api::edit_message_text(&TelegramApi::new(stack), chat_id, message_id, cancelled, Some(parse_mode), None).await,
+                    undo::UndoAction::Delete => bot_api::delete_message(&TelegramApi::new(stack), chat_id, message_id).await,
+                }
+            },
+            |_, _| (),
+        ).await;
+        if let Err(e) = result {
+            defmt::error!("Undo: failed in {}: {:?}", chat_id, defmt::Debug2Format(&e));
+            error = Some(e);
+        }
+    }
+    error.map_or(Ok(()), Err)
 }
 
+// Escalation: an event nobody has acknowledged yet
+struct Escalation {
+    group: u32,        // its messages: see `ack`
+    level: u8,         // 0: sent; 1: re-sent with notification; 2: the escalation chat notified
+    deadline: Instant, // escalate further, unless acknowledged
+    text: alloc::string::String,
+    parse_mode: Option<ParseMode>,
+}
+
+// Nobody's acknowledged in time: escalate one level up.
+// Returns the escalation to watch next; `None` when it's over.
+async fn escalate(stack: embassy_net::Stack<'_>, recipients: &mut Recipients, e: Escalation) -> Option<Escalation> {
+    if ack::is_acknowledged(e.group) {
+        return None;
+    }
+
+    let level = e.level + 1;
+    let mut text = alloc::string::String::from("⏰ ");
+    text.push_str(&e.text);
+    let options = MessageOptions { parse_mode: e.parse_mode, disable_notification: false, ack_buttons: true, ..MESSAGE_OPTIONS };
+    match (level, ESCALATION_CHAT) {
+        // Level 1: again, loud
+        (1, _) => {
+            defmt::warn!("Escalation 1: not acknowledged, re-sending with notification");
+            let _ = fan_out(stack, recipients, &text, &options, e.group, &Since::new()).await;  // not an entry: see `entry_since()`
+            crate::led::set_led_state(crate::led::LedState::EscalationLevel1);
+        }
+        // Level 2: somebody else
+        (2, Some(chat)) => {
+            defmt::warn!("Escalation 2: not acknowledged, notifying {}", chat.chat_id);
+            let api = TelegramApi::new(stack);
+            let result = RETRY_POLICY.run(|| bot_api::send_message(&api, chat, &text, &options), |_, _| ()).await;
+            match result {
+                Ok(SentMessage { message_id, chat: Some(sent_chat) }) => ack::record_sent(e.group, sent_chat.id, message_id, text, e.parse_mode),
+                Ok(_) => (),  // sent, but we don't know where: see `SentMessage`
+                Err(err) => defmt::error!("Escalation: failed to notify {}: {:?}", chat.chat_id, defmt::Debug2Format(&err)),
+            }
+            crate::led::set_led_state(crate::led::LedState::EscalationLevel2);
+        }
+        _ => return None,
+    }
+    let delay = ESCALATION_DELAY?;
+    Some(Escalation { level, deadline: Instant::now() + delay, ..e })
+}
 
-// Error handling: only return as much info as the caller needs to have.
-// Everything else: log, don't return.
-// "Log generously, return sparingly."
-#[derive(Debug, defmt::Format)]
-pub enum TelegramSendMessageError {
-    InvalidArguments,
-    RequestError(reqwless::Error),
-    ResponseError,  // see logs
+/// The Bot API on the device: HTTPS to `API_URL` with our token, Telegram's certificate checked
+#[derive(Clone, Copy)]
+pub struct TelegramApi<'a> {
+    stack: embassy_net::Stack<'a>,
 }
 
-// Auto-convert with From impls
-impl From<reqwless::Error> for TelegramSendMessageError {
-    fn from(e: reqwless::Error) -> Self {
-        TelegramSendMessageError::RequestError(e)
+impl<'a> TelegramApi<'a> {
+    pub fn new(stack: embassy_net::Stack<'a>) -> Self {
+        Self { stack }
     }
 }
-impl From<serde_json_core::ser::Error> for TelegramSendMessageError {
-    fn from(_: serde_json_core::ser::Error) -> Self {
-        TelegramSendMessageError::InvalidArguments
+
+impl Transport for TelegramApi<'_> {
+    async fn post<'b>(&self, method: &str, body: &[u8], rx_buf: &'b mut [u8]) -> Result<(u16, &'b [u8]), TelegramSendMessageError> {
+        let mut url: String<256> = String::new();
+        use core::fmt::Write;
+        write!(url, "{}/bot{}/{}", API_URL, BOT_TOKEN, method).map_err(|_| TelegramSendMessageError::InvalidArguments)?;
+        let req = http::Request {
+            method: Method::POST,
+            url: &url,
+            headers: &[("Content-Type", "application/json")],
+            body,
+        };
+        let response = http::request(self.stack, &req, TELEGRAM_CA_CERT, rx_buf).await?;
+
+        // A bad token: everything will fail, say so
+        if response.status == 401 {
+            crate::bot_identity::unauthorized();
+        }
+        Ok((response.status, response.body))
     }
 }
 
 
-#[derive(Serialize, defmt::Format)]
-struct TelegramMessageInput<'a> {
-    chat_id: i64,
-    text: &'a str,
+// Auto-convert with From impls. What's gone wrong: only logged.
+impl From<HttpError> for TelegramSendMessageError {
+    fn from(e: HttpError) -> Self {
+        defmt::warn!("Telegram: {:?}", e);
+        match e {
+            HttpError::ConnectError(_) => TelegramSendMessageError::ConnectError,
+            HttpError::RequestError(_) => TelegramSendMessageError::RequestError,
+            HttpError::CertificateRejected => TelegramSendMessageError::CertificateRejected,
+        }
+    }
 }
 
 
-/* Telegram API:
- * $ http POST 'https://api.telegram.org/bot${TELEGRAM_BOT_TOKEN}/sendMessage' chat_id:=${TELEGRAM_SEND_TO} text="hey"
- * { "ok":true,
- *   "result":{
- *     "message_id":39,
- *     "from":{"id":6415095545,"is_bot":true,"first_name":"....","username":"...bot"},
- *     "chat":{"id":691814383,"first_name":"...","last_name":"...","username":"...","type":"private"},
- *     "date":1767364636,
- *     "text":"hi there"
- *   }
- * }
- */
@@ -0,0 +1,403 @@
+// Two-way bot: receive messages, run commands, reply.
+//
+// Long polling: `getUpdates` waits on the server until there's something new.
+// Every update has an id; `offset` confirms everything before it.
+// The offset is persisted: after a reboot, old commands aren't run again.
+//
+// Commands: "/name args". Built-in: /status /ping /count /help.
+// Other modules add theirs: implement `Command`, call `register_command()`.
+//
+// Button presses: "callback queries". See `ack`.
+
+use defmt;
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use serde::{Deserialize, Serialize};
+
+use crate::ack::{self, AckKind};
+use crate::auth::{self, Permission};
+use crate::recipient::{ChatId, Recipient};
+use crate::storage::{self, Key};
+use crate::bot_api::{self, Chat, TelegramSendMessageError};
+use crate::telegram::TelegramApi;
+
+
+// Long polling: the server holds the request this long, seconds
+const POLL_TIMEOUT: u32 = 50;
+
+// Updates per request
+const POLL_LIMIT: usize = 8;
+
+// After a failure, wait
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// How many commands can be registered: besides the built-in ones
+const MAX_COMMANDS: usize = 16;
+
+
+/// A bot command: "/name args"
+pub trait Command: Sync {
+    /// Command name: without the slash. Latin letters, digits, underscores.
+    fn name(&self) -> &'static str;
+
+    /// One line: for /help
+    fn description(&self) -> &'static str;
+
+    /// Who may run it: see `auth`
+    fn permission(&self) -> Permission {
+        Permission::Read
+    }
+
+    /// Run the command, write the reply.
+    /// Nothing written: no reply.
+    fn run(&self, ctx: &CommandContext, args: &str, reply: &mut String) -> fmt::Result;
+}
+
+/// Who's asking, and where
+pub struct CommandContext<'a> {
+    pub stack: embassy_net::Stack<'a>,
+    pub chat: Recipient,       // where to reply
+    pub user_id: Option<i64>,  // who's sent it; none in channels
+    pub permission: Permission,  // what they may do
+}
+
+/// Register a command. Call before the bot task is spawned, or anytime.
+/// Returns `false` if there's no room.
+pub fn register_command(command: &'static dyn Command) -> bool {
+    COMMANDS.lock(|c| c.borrow_mut().push(command).is_ok())
+}
+
+static COMMANDS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<&'static dyn Command, MAX_COMMANDS>>> = Mutex::new(RefCell::new(heapless::Vec::new()));
+
+// Built-in commands
+static BUILTIN_COMMANDS: [&dyn Command; 4] = [&StatusCommand, &PingCommand, &CountCommand, &HelpCommand];
+
+// Find a command: built-in first
+fn find_command(name: &str) -> Option<&'static dyn Command> {
+    BUILTIN_COMMANDS.iter().copied()
+        .find(|c| c.name() == name)
+        .or_else(|| COMMANDS.lock(|c| c.borrow().iter().copied().find(|c| c.name() == name)))
+}
+
+
+
+// Task: receive updates, run commands
+#[embassy_executor::task]
+pub async fn task_telegram_updates(stack: embassy_net::Stack<'static>) {
+    // Where we've stopped last time
+    let mut offset = storage::load(Key::TelegramUpdatesOffset).await.map(|v| v as i64);
+    defmt::info!("Bot: starting at offset {:?}", offset);
+
+    loop {
+        stack.wait_config_up().await;
+
+        match poll_updates(stack, offset).await {
+            Ok(Some(next)) => {
+                offset = Some(next);
+                if let Err(e) = storage::store(Key::TelegramUpdatesOffset, next as u64).await {
+                    defmt::warn!("Bot: offset not saved: {:?}", e);
+                }
+            }
+            Ok(None) => (),  // nothing new
+            Err(e) => {
+                defmt::warn!("Bot: getUpdates failed: {:?}", e);
+                Timer::after(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// Get updates, handle them. Returns the next offset, if there's been anything.
+async fn poll_updates(stack: embassy_net::Stack<'_>, offset: Option<i64>) -> Result<Option<i64>, TelegramSendMessageError> {
+    let params = GetUpdatesInput {
+        offset,
+        limit: POLL_LIMIT as u8,
+        timeout: POLL_TIMEOUT,
+        allowed_updates: &["message", "callback_query"],
+    };
+
+    // Wait a bit longer than the server does: a dead connection would hang forever
+    let timeout = Duration::from_secs(POLL_TIMEOUT as u64 + 15);
+    let mut buf = alloc::vec![0u8; 2 * 16 * 1024];  // twice: see `api_call()`
+    let result = match with_timeout(timeout, bot_api::api_call::<_, _, heapless::Vec<Update, POLL_LIMIT>>(&TelegramApi::new(stack), "getUpdates", &params, 256, &mut buf)).await {
+        Ok(result) => result,
+        Err(_) => {
+            defmt::warn!("Bot: getUpdates timed out");
+            return Ok(None);
+        }
+    };
+    if let Err(TelegramSendMessageError::ResponseError) = result {
+        // Something we can't parse: too big, or unexpected.
+        // Skip these updates, or we'd get them again and again. Only their ids: into the same buffer, we're done with it.
+        drop(result);
+        defmt::warn!("Bot: unparseable updates: skipping them");
+        let ids: heapless::Vec<UpdateId, POLL_LIMIT> = bot_api::api_call(&TelegramApi::new(stack), "getUpdates", &GetUpdatesInput { timeout: 0, ..params }, 256, &mut buf).await?;
+        return Ok(ids.iter().map(|u| u.update_id + 1).max());
+    }
+    let updates = result?;
+
+    for update in &updates {
+        if let Some(message) = &update.message {
+            handle_message(stack, message);
+        }
+        if let Some(callback) = &update.callback_query {
+            handle_callback(stack, callback).await;
+        }
+    }
+    Ok(updates.iter().map(|u| u.update_id + 1).max())
+}
+
+// Handle a message: run the command, queue the reply
+fn handle_message(stack: embassy_net::Stack<'_>, message: &Message) {
+    let Some(text) = message.text.as_deref() else { return };
+    let Some((name, args)) = parse_command(text) else { return };
+    let user_id = message.from.as_ref().map(|u| u.id);
+    defmt::info!("Bot: /{} from {:?} in {}", name, user_id, message.chat.id);
+
+    let chat = Recipient {
+        chat_id: ChatId::Id(message.chat.id),
+        // Forum topics: reply in the same topic
+        message_thread_id: message.message_thread_id.filter(|_| message.is_topic_message),
+    };
+
+    // Who's asking? Strangers don't even learn which commands exist.
+    let Some(permission) = auth::authorize(user_id, message.chat.id) else {
+        auth::reject(user_id, chat, text, true);
+        return;
+    };
+    let ctx = CommandContext { stack, chat, user_id, permission };
+
+    let mut reply = String::new();
+    match find_command(name) {
+        Some(command) if command.permission() > permission => {
+            auth::reject(user_id, chat, text, true);
+            return;
+        }
+        Some(command) => {
+            let _ = command.run(&ctx, args, &mut reply);  // String never fails
+        }
+        None => {
+            let _ = write!(reply, "Unknown command: /{}. See /help", name);
+        }
+    }
+
+    if !reply.is_empty() {
+        crate::notifier::send_reply(ctx.chat, reply);
+    }
+}
+
+// Handle a button press: acknowledge the message
+async fn handle_callback(stack: embassy_net::Stack<'_>, callback: &CallbackQuery<'_>) {
+    let Some(message) = &callback.message else { return };  // too old: nothing to edit
+    let chat = Recipient { chat_id: ChatId::Id(message.chat.id), message_thread_id: None };
+    let data = callback.data.unwrap_or("");
+    defmt::info!("Bot: button {} from {} in {}", data, callback.from.id, message.chat.id);
+
+    // Who's pressing?
+    if auth::authorize(Some(callback.from.id), message.chat.id).is_none() {
+        auth::reject(Some(callback.from.id), chat, data, false);
+        answer_callback(stack, callback.id, "Not allowed").await;
+        return;
+    }
+
+    // Which button?
+    let Some(kind) = AckKind::from_callback_data(data) else {
+        answer_callback(stack, callback.id, "Unknown button").await;
+        return;
+    };
+
+    // Answer first: the user's waiting
+    answer_callback(stack, callback.id, "Thanks!").await;
+    crate::led::set_led_state(crate::led::LedState::Acknowledged);
+
+    // Show who and when
+    let Some(edited) = ack::acknowledge(message.chat.id, message.message_id, kind, &callback.from.first_name, message.text.as_deref()) else { return };
+    let result = bot_api::edit_message_text(&TelegramApi::new(stack), chat.chat_id, message.message_id, &edited.text, edited.parse_mode, edited.keyboard).await;
+    if let Err(e) = result {
+        defmt::warn!("Bot: can't edit the message: {:?}", e);
+    }
+}
+
+async fn answer_callback(stack: embassy_net::Stack<'_>, id: &str, text: &str) {
+    if let Err(e) = bot_api::answer_callback_query(&TelegramApi::new(stack), id, Some(text)).await {
+        defmt::warn!("Bot: can't answer the button press: {:?}", e);
+    }
+}
+
+/// Parse a command: "/name@bot args" → ("name", "args").
+/// In groups, Telegram appends the bot's username. Another bot's? Not ours: `None`.
+pub fn parse_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('/')?;
+    let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let (name, bot) = command.split_once('@').map_or((command, None), |(name, bot)| (name, Some(bot)));
+    if name.is_empty() {
+        return None;
+    }
+    if let (Some(bot), Some(ours)) = (bot, crate::bot_identity::username())
+        && !bot.eq_ignore_ascii_case(&ours)
+    {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+
+
+// /status: the device status
+struct StatusCommand;
+
+impl Command for StatusCommand {
+    fn name(&self) -> &'static str { "status" }
+    fn description(&self) -> &'static str { "device status" }
+    fn run(&self, ctx: &CommandContext, _args: &str, reply: &mut String) -> fmt::Result {
+        write!(reply, "{}", crate::status::status_report(ctx.stack))
+    }
+}
+
+// /ping: are you there?
+struct PingCommand;
+
+impl Command for PingCommand {
+    fn name(&self) -> &'static str { "ping" }
+    fn description(&self) -> &'static str { "check that the device is online" }
+    fn run(&self, _ctx: &CommandContext, _args: &str, reply: &mut String) -> fmt::Result {
+        reply.write_str("Pong! Uptime: ")?;
+        crate::template::write_duration(reply, Duration::from_ticks(Instant::now().as_ticks()))
+    }
+}
+
+// /count: events today
+struct CountCommand;
+
+impl Command for CountCommand {
+    fn name(&self) -> &'static str { "count" }
+    fn description(&self) -> &'static str { "how many times today" }
+    fn run(&self, _ctx: &CommandContext, _args: &str, reply: &mut String) -> fmt::Result {
+        write!(reply, "Today: {}", crate::event::count_today())
+    }
+}
+
+// /help: list commands
+struct HelpCommand;
+
+impl Command for HelpCommand {
+    fn name(&self) -> &'static str { "help" }
+    fn description(&self) -> &'static str { "this list" }
+    fn run(&self, ctx: &CommandContext, _args: &str, reply: &mut String) -> fmt::Result {
+        // Only the commands they may run
+        reply.write_str("Commands:")?;
+        for command in BUILTIN_COMMANDS.iter().filter(|c| c.permission() <= ctx.permission) {
+            write!(reply, "\n/{} — {}", command.name(), command.description())?;
+        }
+        COMMANDS.lock(|c| {
+            c.borrow().iter()
+                .filter(|c| c.permission() <= ctx.permission)
+                .try_for_each(|command| write!(reply, "\n/{} — {}", command.name(), command.description()))
+        })
+    }
+}
+
+
+
+#[derive(Serialize, defmt::Format)]
+struct GetUpdatesInput<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<i64>,  // confirms all updates before it
+    limit: u8,
+    timeout: u32,  // long polling, seconds
+    allowed_updates: &'a [&'a str],
+}
+
+// An update. We only care about messages.
+#[derive(Deserialize)]
+struct Update<'a> {
+    update_id: i64,
+    #[serde(borrow)]
+    message: Option<Message<'a>>,
+    #[serde(borrow)]
+    callback_query: Option<CallbackQuery<'a>>,
+}
+
+// Just the update's id: when the rest can't be parsed
+#[derive(Deserialize)]
+struct UpdateId {
+    update_id: i64,
+}
+
+#[derive(Deserialize)]
+struct Message<'a> {
+    #[serde(default)]
+    message_thread_id: Option<i64>,
+    #[serde(default)]
+    is_topic_message: bool,
+    #[serde(borrow)]
+    from: Option<User<'a>>,
+    chat: Chat,
+    #[serde(borrow)]
+    text: Option<Cow<'a, str>>,  // owned if it has escapes: quotes, non-ASCII
+}
+
+#[derive(Deserialize)]
+struct User<'a> {
+    id: i64,
+    #[serde(borrow)]
+    first_name: Cow<'a, str>,
+}
+
+// A button's been pressed
+#[derive(Deserialize)]
+struct CallbackQuery<'a> {
+    #[serde(borrow)]
+    id: &'a str,
+    #[serde(borrow)]
+    from: User<'a>,
+    #[serde(borrow)]
+    message: Option<CallbackMessage<'a>>,  // the message with the button
+    #[serde(borrow)]
+    data: Option<&'a str>,  // the button's callback_data
+}
+
+#[derive(Deserialize)]
+struct CallbackMessage<'a> {
+    message_id: i64,
+    chat: Chat,
+    #[serde(borrow)]
+    text: Option<Cow<'a, str>>,  // plain: without markup
+}
+
+
+/* Telegram API:
+ * $ http POST 'https://api.telegram.org/bot${TELEGRAM_BOT_TOKEN}/getUpdates' timeout:=50 limit:=8
+ * { "ok":true,
+ *   "result":[
+ *     { "update_id":150437852,
+ *       "message":{
+ *         "message_id":40,
+ *         "from":{"id":691814383,"is_bot":false,"first_name":"...","username":"...","language_code":"en"},
+ *         "chat":{"id":691814383,"first_name":"...","username":"...","type":"private"},
+ *         "date":1767364700,
+ *         "text":"/status",
+ *         "entities":[{"offset":0,"length":7,"type":"bot_command"}]
+ *       }
+ *     }
+ *   ]
+ * }
+ *
+ * A button press:
+ *     { "update_id":150437853,
+ *       "callback_query":{
+ *         "id":"2971273985722419383",
+ *         "from":{"id":691814383,"is_bot":false,"first_name":"...","username":"..."},
+ *         "message":{"message_id":39,"chat":{"id":691814383,"type":"private"},"date":1767364636,"text":"...","reply_markup":{...}},
+ *         "chat_instance":"-8791612367328312212",
+ *         "data":"ack:on_it"
+ *       }
+ *     }
+ */
@@ -0,0 +1,241 @@
+// Message templates: text with placeholders.
+//
+//   "Pokakus! #{count_today} at {time}, {since_last} since the last one"
+//
+// Placeholders are expanded right before sending.
+// Use `{{` and `}}` for literal braces.
+// Templates are checked at compile time: see `check()`.
+// Rendering doesn't allocate: it writes into any `fmt::Write`.
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+
+use crate::event::Event;
+use crate::text::{Escape, JsonEscape, ParseMode};
+
+
+/// Known placeholders
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    CountToday,  // {count_today}: events today, this one included
+    Time,        // {time}: when the event happened, local time, HH:MM
+    SinceLast,   // {since_last}: time since the previous event
+    Uptime,      // {uptime}: time since boot
+    Rssi,        // {rssi}: WiFi signal, dBm
+    Hostname,    // {hostname}: DHCP hostname
+    Gesture,     // {gesture}: click, long press, ...
+    DeviceId,    // {device_id}: MAC address
+    Message,     // {message}: the message as a whole. For webhook bodies.
+    EventId,     // {event_id}: "1a2b3c", tells duplicates apart. For webhook bodies.
+}
+
+impl Placeholder {
+    const ALL: [(&'static str, Placeholder); 10] = [
+        ("count_today", Placeholder::CountToday),
+        ("time", Placeholder::Time),
+        ("since_last", Placeholder::SinceLast),
+        ("uptime", Placeholder::Uptime),
+        ("rssi", Placeholder::Rssi),
+        ("hostname", Placeholder::Hostname),
+        ("gesture", Placeholder::Gesture),
+        ("device_id", Placeholder::DeviceId),
+        ("message", Placeholder::Message),
+        ("event_id", Placeholder::EventId),
+    ];
+
+    /// Parse by name: without the braces
+    pub const fn from_name(name: &[u8]) -> Option<Placeholder> {
+        let mut i = 0;
+        while i < Self::ALL.len() {
+            if crate::config::eq(Self::ALL[i].0.as_bytes(), name) {
+                return Some(Self::ALL[i].1);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+
+/// Template error
+#[derive(defmt::Format, Clone, Copy, PartialEq, Debug)]
+pub enum TemplateError {
+    UnknownPlaceholder(usize),  // at byte offset
+    Unclosed(usize),            // `{` without `}`
+    UnmatchedClose(usize),      // `}` without `{`: use `}}`
+    BodyOnly(usize),            // {message}, {event_id} in a message: see `validate_message()`
+}
+
+/// Validate a template. `const`: use at compile time.
+pub const fn validate(template: &str) -> Result<(), TemplateError> {
+    parse(template, false)
+}
+
+/// Validate a message template: e.g. `TELEGRAM_MESSAGE`.
+/// It is the message: no {message} in it. And no {event_id}: the event id is appended, see `notifier::event_tag()`.
+pub const fn validate_message(template: &str) -> Result<(), TemplateError> {
+    parse(template, true)
+}
+
+const fn parse(template: &str, message: bool) -> Result<(), TemplateError> {
+    let t = template.as_bytes();
+    let mut i = 0;
+    while i < t.len() {
+        match t[i] {
+            b'{' if i + 1 < t.len() && t[i + 1] == b'{' => i += 2,
+            b'}' if i + 1 < t.len() && t[i + 1] == b'}' => i += 2,
+            b'}' => return Err(TemplateError::UnmatchedClose(i)),
+            b'{' => {
+                // Find the closing brace
+                let start = i + 1;
+                let mut end = start;
+                while end < t.len() && t[end] != b'}' {
+                    end += 1;
+                }
+                if end == t.len() {
+                    return Err(TemplateError::Unclosed(i));
+                }
+                let (_, rest) = t.split_at(start);
+                let (name, _) = rest.split_at(end - start);
+                match Placeholder::from_name(name) {
+                    None => return Err(TemplateError::UnknownPlaceholder(i)),
+                    Some(Placeholder::Message | Placeholder::EventId) if message => return Err(TemplateError::BodyOnly(i)),
+                    Some(_) => (),
+                }
+                i = end + 1;
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(())
+}
+
+/// Validate a template at compile time: a bad template fails the build.
+///
+///     const _: () = pokakus::template::check(BODY);
+pub const fn check(template: &str) {
+    fail(validate(template))
+}
+
+/// Validate a message template at compile time: see `validate_message()`
+///
+///     const _: () = pokakus::template::check_message(MESSAGE);
+pub const fn check_message(template: &str) {
+    fail(validate_message(template))
+}
+
+const fn fail(result: Result<(), TemplateError>) {
+    match result {
+        Ok(()) => (),
+        Err(TemplateError::UnknownPlaceholder(_)) => panic!("Template: unknown placeholder"),
+        Err(TemplateError::Unclosed(_)) => panic!("Template: unclosed brace. For a literal brace, double it"),
+        Err(TemplateError::UnmatchedClose(_)) => panic!("Template: unmatched closing brace. For a literal brace, double it"),
+        Err(TemplateError::BodyOnly(_)) => panic!("Template: {{message}} and {{event_id}} are for webhook bodies, not messages"),
+    }
+}
+
+
+/// Values for placeholders
+pub struct Context<'a> {
+    pub parse_mode: Option<ParseMode>,  // formatted template: escape the values
+    pub json: bool,                     // a JSON template: values go into strings, escape them
+    pub event: Option<&'a Event>,
+    pub hostname: &'a str,
+    pub device_id: [u8; 6],
+    pub rssi: Option<i32>,
+    pub message: Option<&'a str>,
+    pub event_id: Option<&'a str>,
+}
+
+/// Render a template: write it into `out` with placeholders expanded.
+/// The template is markup, written as is; values are escaped according to `ctx.parse_mode`, or as JSON.
+/// Invalid templates are written as is: they've been validated at compile time anyway.
+pub fn render<W: Write>(out: &mut W, template: &str, ctx: &Context) -> fmt::Result {
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.write_str(&rest[..pos])?;
+        let tail = &rest[pos..];
+
+        // Escaped brace
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.write_str(&tail[..1])?;
+            rest = &tail[2..];
+            continue;
+        }
+
+        // Placeholder
+        let placeholder = tail.find('}')
+            .filter(|_| tail.starts_with('{'))
+            .and_then(|end| Placeholder::from_name(&tail.as_bytes()[1..end]).map(|p| (p, end)));
+        match placeholder {
+            Some((p, end)) => {
+                match ctx.json {
+                    true => write_value(&mut JsonEscape { inner: out }, p, ctx)?,
+                    false => write_value(&mut Escape { inner: out, mode: ctx.parse_mode }, p, ctx)?,
+                }
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.write_str(&tail[..1])?;
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.write_str(rest)
+}
+
+// Write a placeholder's value
+fn write_value<W: Write>(out: &mut W, p: Placeholder, ctx: &Context) -> fmt::Result {
+    match p {
+        Placeholder::CountToday => match ctx.event {
+            Some(e) => write!(out, "{}", e.count_today),
+            None => out.write_str("?"),
+        },
+        Placeholder::Time => match ctx.event.and_then(|e| e.unix_time) {
+            Some(unix) => write_time(out, unix),
+            None => out.write_str("--:--"),
+        },
+        Placeholder::SinceLast => match ctx.event.and_then(|e| e.since_last) {
+            Some(d) => write_duration(out, d),
+            None => out.write_str("—"),
+        },
+        Placeholder::Uptime => write_duration(out, Duration::from_ticks(Instant::now().as_ticks())),
+        Placeholder::Rssi => match ctx.rssi {
+            Some(rssi) => write!(out, "{}", rssi),
+            None => out.write_str("?"),
+        },
+        Placeholder::Hostname => out.write_str(ctx.hostname),
+        Placeholder::Gesture => match ctx.event {
+            Some(e) => out.write_str(e.gesture.name()),
+            None => out.write_str("?"),
+        },
+        Placeholder::DeviceId => {
+            for b in ctx.device_id {
+                write!(out, "{:02x}", b)?;
+            }
+            Ok(())
+        }
+        Placeholder::Message => out.write_str(ctx.message.unwrap_or("")),
+        Placeholder::EventId => out.write_str(ctx.event_id.unwrap_or("")),
+    }
+}
+
+/// Local time: HH:MM
+pub fn write_time<W: Write>(out: &mut W, unix: u64) -> fmt::Result {
+    let local = unix as i64 + crate::clock::TIMEZONE_OFFSET_MINUTES as i64 * 60;
+    let minutes = local.rem_euclid(86400) / 60;
+    write!(out, "{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Human-readable duration: "45 s", "12 min", "3 h 5 min", "2 d 4 h"
+pub fn write_duration<W: Write>(out: &mut W, d: Duration) -> fmt::Result {
+    let secs = d.as_secs();
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match secs {
+        0..60 => write!(out, "{} s", secs),
+        60..3600 => write!(out, "{} min", minutes),
+        3600..86400 => write!(out, "{} h {} min", hours, minutes),
+        _ => write!(out, "{} d {} h", days, hours),
+    }
+}
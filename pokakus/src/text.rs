@@ -0,0 +1,177 @@
+// Text utilities: message length limits, JSON sizes, markup escaping.
+//
+// Telegram counts message length in UTF-16 code units, not bytes or chars:
+// "Привет" is 12 bytes but 6 units; an emoji is 4 bytes but 2 units.
+
+use core::fmt;
+use alloc::string::String;
+
+use serde::{Serialize, Serializer};
+
+use crate::config;
+
+
+/// Append this when a message is cut short
+pub const TRUNCATED_MARKER: &str = "… [truncated]";
+
+
+/// Message length, the way Telegram counts it: UTF-16 code units
+pub fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Truncate text to `max_len` UTF-16 code units, with a marker.
+/// Always cuts on a char boundary: never produces broken UTF-8.
+pub fn truncate_with_marker(text: &str, max_len: usize) -> String {
+    if utf16_len(text) <= max_len {
+        return String::from(text);
+    }
+
+    // Leave room for the marker
+    let budget = max_len.saturating_sub(utf16_len(TRUNCATED_MARKER));
+    let end = prefix_len_utf16(text, budget);
+    let mut out = String::with_capacity(end + TRUNCATED_MARKER.len());
+    out.push_str(&text[..end]);
+    out.push_str(TRUNCATED_MARKER);
+    out
+}
+
+// The longest prefix that fits into `max_len` UTF-16 code units: its length in bytes
+fn prefix_len_utf16(text: &str, max_len: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        units += c.len_utf16();
+        if units > max_len {
+            return i;
+        }
+    }
+    text.len()
+}
+
+
+/// How many bytes would this string take as a JSON string, quotes included.
+/// Use it to size buffers for serialization.
+pub fn json_escaped_len(text: &str) -> usize {
+    2 + text.bytes().map(|b| match b {
+        b'"' | b'\\' | b'\x08' | b'\x0C' | b'\n' | b'\r' | b'\t' => 2,  // \"
+        0x00..=0x1F => 6,  // \u00XX
+        _ => 1,  // incl. UTF-8: passed as is
+    }).sum::<usize>()
+}
+
+
+
+/// Message formatting: how Telegram should parse the text
+#[derive(defmt::Format, Clone, Copy, PartialEq, Debug)]
+pub enum ParseMode {
+    MarkdownV2,
+    Html,
+}
+
+impl ParseMode {
+    /// Parse config: "MarkdownV2", "HTML", or empty for plain text
+    pub const fn parse(value: Option<&str>) -> Option<ParseMode> {
+        match value {
+            None => None,
+            Some(v) if v.is_empty() => None,
+            Some(v) if config::eq(v.as_bytes(), b"MarkdownV2") => Some(ParseMode::MarkdownV2),
+            Some(v) if config::eq(v.as_bytes(), b"HTML") => Some(ParseMode::Html),
+            Some(_) => panic!("Parse mode: expected 'MarkdownV2', 'HTML', or empty"),
+        }
+    }
+}
+
+impl Serialize for ParseMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            ParseMode::MarkdownV2 => "MarkdownV2",
+            ParseMode::Html => "HTML",
+        })
+    }
+}
+
+
+/// Escaping writer: whatever is written through it can't break the markup.
+/// Use it for values substituted into formatted text.
+/// With `mode=None` (plain text), passes everything as is.
+pub struct Escape<'a, W: fmt::Write> {
+    pub inner: &'a mut W,
+    pub mode: Option<ParseMode>,
+}
+
+impl<W: fmt::Write> fmt::Write for Escape<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.mode {
+            None => self.inner.write_str(s),
+            Some(ParseMode::MarkdownV2) => escape_markdown_v2(self.inner, s),
+            Some(ParseMode::Html) => escape_html(self.inner, s),
+        }
+    }
+}
+
+/// Escape text for MarkdownV2.
+/// Telegram rejects a message with any of these unescaped: _*[]()~`>#+-=|{}.!\
+pub fn escape_markdown_v2<W: fmt::Write + ?Sized>(out: &mut W, s: &str) -> fmt::Result {
+    for part in s.split_inclusive(is_markdown_v2_reserved) {
+        match part.char_indices().last() {
+            Some((i, c)) if is_markdown_v2_reserved(c) => {
+                out.write_str(&part[..i])?;
+                out.write_char('\\')?;
+                out.write_char(c)?;
+            }
+            _ => out.write_str(part)?,
+        }
+    }
+    Ok(())
+}
+
+fn is_markdown_v2_reserved(c: char) -> bool {
+    matches!(c, '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' | '!' | '\\')
+}
+
+/// Escape text for HTML: &, <, >, "
+pub fn escape_html<W: fmt::Write + ?Sized>(out: &mut W, s: &str) -> fmt::Result {
+    let mut rest = s;
+    while let Some(pos) = rest.find(['&', '<', '>', '"']) {
+        out.write_str(&rest[..pos])?;
+        out.write_str(match &rest[pos..pos + 1] {
+            "&" => "&amp;",
+            "<" => "&lt;",
+            ">" => "&gt;",
+            _ => "&quot;",
+        })?;
+        rest = &rest[pos + 1..];
+    }
+    out.write_str(rest)
+}
+
+/// Escaping writer for JSON: whatever is written through it stays inside a string.
+/// Use it for values substituted into a JSON template: see `webhook`.
+pub struct JsonEscape<'a, W: fmt::Write> {
+    pub inner: &'a mut W,
+}
+
+impl<W: fmt::Write> fmt::Write for JsonEscape<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        escape_json(self.inner, s)
+    }
+}
+
+/// Escape text for a JSON string: quotes, backslashes, control characters
+pub fn escape_json<W: fmt::Write + ?Sized>(out: &mut W, s: &str) -> fmt::Result {
+    let mut rest = s;
+    while let Some(pos) = rest.find(|c: char| c == '"' || c == '\\' || c.is_control()) {
+        out.write_str(&rest[..pos])?;
+        let c = rest[pos..].chars().next().unwrap_or(' ');
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c => write!(out, "\\u{:04x}", c as u32)?,
+        }
+        rest = &rest[pos + c.len_utf8()..];
+    }
+    out.write_str(rest)
+}
@@ -0,0 +1,145 @@
+use defmt;
+use core::cell::Cell;
+use core::net::SocketAddr;
+
+use embedded_io::Error as _;
+use embedded_nal_async::TcpConnect;
+use embedded_tls::{
+    Aes128GcmSha256, Certificate, CryptoProvider, MaxFragmentLength, TlsClock, TlsConfig, TlsConnection, TlsContext, TlsError, TlsVerifier,
+    pki::CertVerifier,
+};
+use rand_chacha::ChaCha8Rng;
+use rand_core::{CryptoRngCore, SeedableRng};
+
+
+/// Largest certificate chain we can verify, bytes: every certificate the server sends, together
+const MAX_CERT_SIZE: usize = 6144;
+
+
+/// TLS connector with server authentication.
+///
+/// Wraps a TCP connector: every connection is a TLS connection
+/// that has verified the server's certificate:
+/// - the chain must lead to the pinned CA
+/// - the certificate must be for `server_name`
+/// - the certificate must be valid: checked once we know the time (see `clock`)
+///
+/// Give it to an HTTP client instead of the TCP connector.
+/// Single-use: the buffers are handed over to the first connection.
+pub struct TlsConnector<'a, T: TcpConnect> {
+    tcp: &'a T,
+    server_name: &'a str,
+    ca: &'a [u8],
+    seed: u64,
+    max_fragment_length: Option<MaxFragmentLength>,
+    buffers: Cell<Option<(&'a mut [u8], &'a mut [u8])>>,
+    rejected: Cell<bool>,
+}
+
+impl<'a, T: TcpConnect> TlsConnector<'a, T> {
+    /// New connector.
+    /// `ca`: DER certificate of the CA to trust.
+    /// `seed`: random seed for the TLS handshake: see `wifi::random_seed()`.
+    /// The rx buffer should be 16640 bytes: the biggest TLS record. Smaller: see `with_max_fragment_length()`.
+    pub fn new(
+        tcp: &'a T,
+        server_name: &'a str,
+        ca: &'a [u8],
+        seed: u64,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        Self {
+            tcp,
+            server_name,
+            ca,
+            seed,
+            max_fragment_length: None,
+            buffers: Cell::new(Some((rx_buffer, tx_buffer))),
+            rejected: Cell::new(false),
+        }
+    }
+
+    /// Ask the server for smaller records: `Bits12` is 4K, and the buffers can be 4096+256 bytes.
+    /// The server must support it (RFC 6066). If it doesn't, its first big record breaks the connection.
+    pub fn with_max_fragment_length(mut self, len: MaxFragmentLength) -> Self {
+        self.max_fragment_length = Some(len);
+        self
+    }
+
+    /// Did the server fail authentication?
+    /// i.e. the certificate doesn't match the pin, the host, or has expired.
+    pub fn certificate_rejected(&self) -> bool {
+        self.rejected.get()
+    }
+}
+
+impl<'a, T: TcpConnect> TcpConnect for TlsConnector<'a, T> {
+    type Error = TlsError;
+    type Connection<'m> = TlsConnection<'m, T::Connection<'m>, Aes128GcmSha256> where Self: 'm;
+
+    async fn connect<'m>(&'m self, remote: SocketAddr) -> Result<Self::Connection<'m>, Self::Error> {
+        let (rx_buffer, tx_buffer) = self.buffers.take().ok_or(TlsError::InternalError)?;
+
+        // TCP
+        let socket = self.tcp.connect(remote)
+            .await
+            .map_err(|e| TlsError::Io(e.kind()))?;
+
+        // TLS
+        let mut config = TlsConfig::new()
+            .with_server_name(self.server_name)
+            .with_ca(Certificate::X509(self.ca));
+        if let Some(len) = self.max_fragment_length {
+            config = config.with_max_fragment_length(len);
+        }
+        let provider = VerifyingProvider {
+            rng: ChaCha8Rng::seed_from_u64(self.seed),
+            verifier: CertVerifier::new(),
+        };
+        let mut conn = TlsConnection::new(socket, rx_buffer, tx_buffer);
+        match conn.open(TlsContext::new(&config, provider)).await {
+            Ok(()) => Ok(conn),
+            Err(e) => {
+                if matches!(e, TlsError::InvalidCertificate | TlsError::InvalidSignature) {
+                    defmt::error!("TLS: {} failed authentication: {:?}", self.server_name, defmt::Debug2Format(&e));
+                    self.rejected.set(true);
+                } else {
+                    defmt::warn!("TLS: handshake with {} failed: {:?}", self.server_name, defmt::Debug2Format(&e));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+
+// Crypto provider that verifies the server.
+// (reqwless' default provider does not verify anything)
+struct VerifyingProvider {
+    rng: ChaCha8Rng,
+    verifier: CertVerifier<Aes128GcmSha256, WallClock, MAX_CERT_SIZE>,
+}
+
+impl CryptoProvider for VerifyingProvider {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<Self::CipherSuite>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+
+// Certificate validity dates are only checked once we know the time
+struct WallClock;
+
+impl TlsClock for WallClock {
+    fn now() -> Option<u64> {
+        crate::clock::now_unix()
+    }
+}
@@ -0,0 +1,89 @@
+// Undo: retract the last message after a mistaken press.
+//
+// Double click: the most recent event message is either
+// - edited: struck through, "cancelled" appended; or
+// - deleted: gone. Telegram only lets bots delete messages up to 48 hours old.
+// Works after delivery too: we remember the `message_id` of every copy we've sent.
+
+use defmt;
+use core::cell::RefCell;
+use alloc::string::String;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
+
+use crate::config;
+use crate::recipient::{ChatId, MAX_RECIPIENTS};
+use crate::text::{self, ParseMode};
+
+
+/// What undo does
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum UndoAction {
+    Edit,    // strike through, append "cancelled"
+    Delete,  // delete the message
+}
+
+impl UndoAction {
+    pub const fn parse(value: Option<&str>) -> UndoAction {
+        match value {
+            None => UndoAction::Edit,
+            Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"edit") => UndoAction::Edit,
+            Some(v) if config::eq(v.as_bytes(), b"delete") => UndoAction::Delete,
+            Some(_) => panic!("Undo action: expected 'edit' or 'delete'"),
+        }
+    }
+}
+
+/// Config: what undo does
+pub const UNDO_ACTION: UndoAction = UndoAction::parse(option_env!("TELEGRAM_UNDO_ACTION"));
+
+
+/// The last message: every copy of it
+pub struct LastSent {
+    pub text: String,
+    pub parse_mode: Option<ParseMode>,
+    pub messages: heapless::Vec<(ChatId, i64), MAX_RECIPIENTS>,  // chat, message_id
+}
+
+/// Remember the last message: it's the one to undo
+pub fn record_last(last: LastSent) {
+    LAST_SENT.lock(|l| *l.borrow_mut() = Some(last));
+}
+
+/// Take the last message: to undo it. Once.
+pub fn take_last() -> Option<LastSent> {
+    LAST_SENT.lock(|l| l.borrow_mut().take())
+}
+
+static LAST_SENT: Mutex<CriticalSectionRawMutex, RefCell<Option<LastSent>>> = Mutex::new(RefCell::new(None));
+
+
+/// The message, cancelled: struck through, with a note.
+/// Plain text becomes HTML: there's no other way to strike it through.
+pub fn cancelled_text(text: &str, parse_mode: Option<ParseMode>) -> (String, ParseMode) {
+    let mut out = String::new();
+    let mode = match parse_mode {
+        Some(ParseMode::MarkdownV2) => {
+            out.push('~');
+            out.push_str(text);
+            out.push('~');
+            ParseMode::MarkdownV2
+        }
+        Some(ParseMode::Html) => {
+            out.push_str("<s>");
+            out.push_str(text);
+            out.push_str("</s>");
+            ParseMode::Html
+        }
+        None => {
+            out.push_str("<s>");
+            let _ = text::escape_html(&mut out, text);  // String never fails
+            out.push_str("</s>");
+            ParseMode::Html
+        }
+    };
+    out.push_str("\n\n❌ cancelled");
+    (out, mode)
+}
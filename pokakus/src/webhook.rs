@@ -0,0 +1,185 @@
+// Webhook: a notifier that sends an HTTP request to any URL.
+//
+// Home Assistant, n8n, Slack or Discord incoming webhooks, your own backend.
+// The body is a template: the usual placeholders, plus `{message}` and `{event_id}`.
+// A JSON content type: values are escaped to fit into strings.
+//
+//   WEBHOOK_BODY='{{"text":"{message}","id":"{event_id}"}}'
+//
+// Signed, if there's a secret: HMAC-SHA256 of the body, hex, in a header.
+//
+//   X-Signature-256: sha256=5d1f...
+//
+// The receiver computes the same with its copy of the secret, and compares.
+
+use defmt;
+use alloc::string::String;
+use core::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use reqwless::request::Method;
+use sha2::Sha256;
+
+use crate::config;
+use crate::http::{self, DeliveryError, MAX_HEADERS};
+use crate::notifier::{self, Capabilities, Notifier};
+use crate::outbox::Entry;
+use crate::retry::RetryPolicy;
+use crate::template;
+
+
+// Where to
+const URL: &str = http::check_url(env!("WEBHOOK_URL"));
+
+// How: POST, PUT, GET
+const METHOD: Method = match option_env!("WEBHOOK_METHOD") {
+    None => Method::POST,
+    Some(v) if v.is_empty() || config::eq(v.as_bytes(), b"POST") => Method::POST,
+    Some(v) if config::eq(v.as_bytes(), b"PUT") => Method::PUT,
+    Some(v) if config::eq(v.as_bytes(), b"GET") => Method::GET,
+    Some(_) => panic!("Webhook method: expected POST, PUT or GET"),
+};
+
+// Content type. JSON: values in the body are escaped.
+const CONTENT_TYPE: &str = match option_env!("WEBHOOK_CONTENT_TYPE") {
+    Some(v) if !v.is_empty() => v,
+    _ => "application/json",
+};
+
+// Extra headers: "Authorization: Bearer abc; X-Source: pokakus"
+const HEADERS: &str = check_headers(match option_env!("WEBHOOK_HEADERS") {
+    Some(v) => v,
+    None => "",
+});
+
+// The body: a template, see `template`
+const BODY: &str = match option_env!("WEBHOOK_BODY") {
+    Some(v) if !v.is_empty() => v,
+    _ => r#"{{"text":"{message}","gesture":"{gesture}","time":"{time}","device":"{hostname}","event_id":"{event_id}"}}"#,
+};
+const _: () = template::check(BODY);
+
+// Signing: HMAC-SHA256 of the body with this secret. Empty: not signed.
+const SECRET: &str = match option_env!("WEBHOOK_SECRET") {
+    Some(v) => v,
+    None => "",
+};
+const SIGNATURE_HEADER: &str = match option_env!("WEBHOOK_SIGNATURE_HEADER") {
+    Some(v) if !v.is_empty() => v,
+    _ => "X-Signature-256",
+};
+
+// The CA we expect the server's certificate to be issued by: DER file
+const CA_CERT: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", env!("WEBHOOK_CA_CERT")));
+
+// Retries: see `http::retry_policy()`
+const RETRY_POLICY: RetryPolicy = http::retry_policy(config::parse_u32(option_env!("WEBHOOK_RETRY_MAX_ATTEMPTS"), 5));
+
+// The response: only the status matters, the body is only logged
+const RX_BUF_SIZE: usize = 2 * 1024;
+
+// Content-Type and the signature, and the extras: they must fit, see `MAX_HEADERS`
+const fn check_headers(headers: &str) -> &str {
+    let h = headers.as_bytes();
+    let (mut start, mut i, mut count) = (0, 0, 0);
+    while i <= h.len() {
+        if i == h.len() || h[i] == b';' {
+            // A header: "Name: value", or nothing between two semicolons
+            let mut colon = start;
+            while colon < i && h[colon] != b':' {
+                colon += 1;
+            }
+            let mut blank = true;
+            let mut j = start;
+            while j < i {
+                blank &= h[j] == b' ';
+                j += 1;
+            }
+            if !blank && (colon == i || colon == start) {
+                panic!("Webhook headers: expected Name: value, separated by semicolons");
+            }
+            if colon < i {
+                count += 1;
+            }
+            start = i + 1;
+        }
+        i += 1;
+    }
+    if count > MAX_HEADERS - 2 {
+        panic!("Webhook headers: too many");
+    }
+    headers
+}
+
+// Extra headers: parsed
+fn extra_headers() -> impl Iterator<Item = (&'static str, &'static str)> {
+    HEADERS.split(';')
+        .filter_map(|h| h.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+
+/// Webhook: a notifier, see `notifier`
+#[derive(Default)]
+pub struct WebhookNotifier;
+
+impl Notifier for WebhookNotifier {
+    type Error = DeliveryError;
+
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { replies: false, undo: false }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RETRY_POLICY
+    }
+
+    async fn send(&mut self, stack: embassy_net::Stack<'_>, entry: &Entry) -> Result<(), DeliveryError> {
+        // Body: the template, with the message in it
+        let message = notifier::plain_text(entry);
+        let event_id = notifier::event_id(entry);
+        let ctx = template::Context {
+            json: CONTENT_TYPE.contains("json"),
+            message: Some(&message),
+            event_id: Some(&event_id),
+            ..notifier::context(entry, None)
+        };
+        let mut body = String::new();
+        let _ = template::render(&mut body, BODY, &ctx);  // String never fails
+
+        // Headers: content type, signature, extras. They fit: see `check_headers()`.
+        let signature = (!SECRET.is_empty()).then(|| sign(body.as_bytes()));
+        let mut headers = heapless::Vec::<(&str, &str), MAX_HEADERS>::new();
+        let _ = headers.push(("Content-Type", CONTENT_TYPE));
+        if let Some(signature) = &signature {
+            let _ = headers.push((SIGNATURE_HEADER, signature.as_str()));
+        }
+        for header in extra_headers() {
+            let _ = headers.push(header);
+        }
+
+        // Request
+        defmt::debug!("Webhook: {}...", http::server(URL).1);  // not the URL: it may have a secret in it
+        let req = http::Request { method: METHOD, url: URL, headers: &headers, body: body.as_bytes() };
+        let mut rx_buf = alloc::vec![0u8; RX_BUF_SIZE];
+        let response = http::request(stack, &req, CA_CERT, &mut rx_buf).await?.success()?;
+        defmt::info!("Webhook: sent, HTTP {}", response.status);
+        Ok(())
+    }
+}
+
+// Signature: "sha256=" and the HMAC, hex
+fn sign(body: &[u8]) -> heapless::String<71> {
+    let mut signature = heapless::String::new();
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()) else { return signature };  // HMAC takes any key
+    mac.update(body);
+    let _ = signature.push_str("sha256=");
+    for b in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{:02x}", b);  // fits: 7 + 64
+    }
+    signature
+}
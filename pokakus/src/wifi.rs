@@ -1,5 +1,6 @@
 use defmt;
 
+use core::cell::Cell;
 use core::str::FromStr;
 
 use esp_hal::{
@@ -10,6 +11,10 @@ use esp_radio::wifi;
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 use embassy_net::{DhcpConfig};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+};
 
 use crate::mk_static;
 
@@ -26,8 +31,50 @@ const PASSWORD: &str = env!("WIFI_PASS");
 // Name yourself
 const DHCP_HOSTNAME: Option<&str> = option_env!("DHCP_HOSTNAME");
 
-// The number of sockets to allocate enough space for.
-const N_SOCKETS: usize = 7;
+// IPv6: a static configuration. embassy-net has no SLAAC.
+// No address: a link-local one, from the MAC.
+#[cfg(feature = "ipv6")]
+const IPV6_ADDRESS: Option<&str> = option_env!("IPV6_ADDRESS");
+#[cfg(feature = "ipv6")]
+const IPV6_GATEWAY: Option<&str> = option_env!("IPV6_GATEWAY");
+#[cfg(feature = "ipv6")]
+const IPV6_DNS: Option<&str> = option_env!("IPV6_DNS");
+
+/// Our hostname
+pub fn hostname() -> &'static str {
+    DHCP_HOSTNAME.unwrap_or("pokakus")
+}
+
+/// A random seed: e.g. for a TLS handshake.
+/// From the hardware RNG: truly random while the radio's on.
+pub fn random_seed() -> u64 {
+    let rng = Rng::new();  // it's ok: nothing's really initialized
+    let mut bytes = [0; 8];
+    rng.read(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// WiFi signal strength, dBm. `None` when not connected.
+pub fn rssi() -> Option<i32> {
+    RSSI.lock(|r| r.get())
+}
+
+// Last known RSSI: updated periodically while connected
+static RSSI: Mutex<CriticalSectionRawMutex, Cell<Option<i32>>> = Mutex::new(Cell::new(None));
+const RSSI_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+// The number of sockets to allocate enough space for: the most open at once.
+// Every task has one open at a time: a DNS lookup's, then the connection.
+//   DHCP                                    1
+//   embassy-net's DNS                       1
+//   the sender: Telegram, webhook, ntfy     1
+//   getUpdates: the bot                     1
+//   getMe: the bot's identity               1
+//   MQTT                                    1
+//   SNTP                                    1
+//   diagnostics: ping, DNS, TCP             1
+// Cheap: a socket's buffers are its owner's.
+const N_SOCKETS: usize = 8;
 
 
 // Start WiFi, spawn net tasks, return net stack
@@ -38,7 +85,7 @@ pub async fn start_wifi(
     // Init controller
     let radio: &esp_radio::Controller<'static> = mk_static!(esp_radio::Controller, esp_radio::init().context("Init radio")?);
     let (mut wifi_controller, interfaces) =
-        wifi::new(&radio, wifi_peripheral, Default::default())
+        wifi::new(radio, wifi_peripheral, Default::default())
             .context("Failed to initialize Wi-Fi controller")?;
     let wifi_interface = interfaces.sta;
 
@@ -47,7 +94,8 @@ pub async fn start_wifi(
     // Otherwise the chip gets really hot.
     wifi_controller.set_power_saving(wifi::PowerSaveMode::Maximum)?;
 
-    // Network config: DHCP
+    // Network config: DHCP.
+    // IPv6 comes with the link: see `task_report_network_state()`.
     let net_config = embassy_net::Config::dhcpv4({
         let mut c = DhcpConfig::default();
         c.hostname = match DHCP_HOSTNAME {  // feature="dhcpv4-hostname"
@@ -58,7 +106,6 @@ pub async fn start_wifi(
         c
     });
 
-
     // Network stack.
     // It also needs a random number: for TLS and networking.
     // The net stack wants a u64, so we join two u32-s.
@@ -103,15 +150,23 @@ async fn task_keep_wifi_client_up(mut controller: wifi::WifiController<'static>)
         crate::led::set_led_state({
             match wifi::sta_state() {
                 wifi::WifiStaState::Connected => crate::led::LedState::PresenceBlink,
-                _ => crate::led::LedState::PatientBlink,
+                _ => crate::outbox::offline_led_state(),  // shows how many messages are waiting
             }
         });
 
         // 1. Check WiFi state
         // If it is in StaConnected, we wait until it gets disconnected.
         if wifi::sta_state() == wifi::WifiStaState::Connected {
-            // wait until we're no longer connected, then a bit more -- and reconnect
-            controller.wait_for_event(wifi::WifiEvent::StaDisconnected).await;
+            // wait until we're no longer connected, then a bit more -- and reconnect.
+            // Meanwhile, keep an eye on the signal.
+            loop {
+                let disconnected = controller.wait_for_event(wifi::WifiEvent::StaDisconnected);
+                match select(disconnected, Timer::after(RSSI_UPDATE_INTERVAL)).await {
+                    Either::First(_) => break,
+                    Either::Second(_) => RSSI.lock(|r| r.set(controller.rssi().ok())),
+                }
+            }
+            RSSI.lock(|r| r.set(None));
             Timer::after(Duration::from_secs(5)).await;
         }
 
@@ -140,6 +195,7 @@ async fn task_keep_wifi_client_up(mut controller: wifi::WifiController<'static>)
             Ok(_) => {
                 let rssi = controller.rssi().unwrap_or(-999);
                 defmt::info!("WiFi: connected! rssi={}", rssi);
+                RSSI.lock(|r| r.set(controller.rssi().ok()));
             }
             Err(e) => {
                 defmt::warn!("WiFi: failed to connect: {:?}", e);
@@ -154,21 +210,95 @@ async fn task_keep_wifi_client_up(mut controller: wifi::WifiController<'static>)
 // Task: wait for the Wi-Fi link to be up, then obtain the IP address.
 #[embassy_executor::task]
 async fn task_report_network_state(stack: embassy_net::Stack<'static>) {
+    #[cfg(feature = "ipv6")]
+    let ipv6 = ipv6_config(stack.hardware_address());
+
     loop {
-        // Wait up
+        // Wait up.
+        // A static IPv6 config is up as soon as it's set: it's set once the link is, and unset when it's gone.
+        // A link-local address alone doesn't make us online: it waits for IPv4.
         defmt::info!("Network: connecting...");
-        crate::led::set_led_state(crate::led::LedState::PatientBlink);
+        crate::led::set_led_state(crate::outbox::offline_led_state());
+        #[cfg(feature = "ipv6")]
+        {
+            stack.wait_link_up().await;
+            if !ipv6.address.address().is_unicast_link_local() {
+                stack.set_config_v6(embassy_net::ConfigV6::Static(ipv6.clone()));
+            }
+        }
         stack.wait_config_up().await;
+        #[cfg(feature = "ipv6")]
+        if stack.config_v6().is_none() {
+            stack.set_config_v6(embassy_net::ConfigV6::Static(ipv6.clone()));
+        }
+
+        // Get config.
+        // Either address family is enough: an IPv6-only network is still a network.
+        let config_v4 = stack.config_v4();
+        if let Some(config) = &config_v4 {
+            defmt::info!("Network: UP! IPv4: {} MAC: {}", config.address, stack.hardware_address());
+        }
+        #[cfg(feature = "ipv6")]
+        let config_v6 = stack.config_v6();
+        #[cfg(feature = "ipv6")]
+        if let Some(config) = &config_v6 {
+            defmt::info!("Network: UP! IPv6: {} MAC: {}", config.address, stack.hardware_address());
+        }
+        #[cfg(not(feature = "ipv6"))]
+        let config_v6: Option<()> = None;
 
-        // Get config
-        if let Some(config) = stack.config_v4() {
-            defmt::info!("Network: UP! IP: {} MAC: {}", config.address, stack.hardware_address());
-            crate::led::set_led_state(crate::led::LedState::PresenceBlink);
+        if config_v4.is_some() || config_v6.is_some() {
+            // Online. But with a bad token, that's no use: keep showing it.
+            crate::led::set_led_state(match crate::notifier::is_misconfigured() {
+                true => crate::led::LedState::ConfigError,
+                false => crate::led::LedState::PresenceBlink,
+            });
+            crate::diagnostics::request_diagnostics(crate::diagnostics::ReportTo::Log);
         } else {
             continue;
         }
 
         // Wait down
+        #[cfg(feature = "ipv6")]
+        {
+            stack.wait_link_down().await;
+            stack.set_config_v6(embassy_net::ConfigV6::None);
+        }
         stack.wait_config_down().await;
     }
 }
+
+
+// IPv6 config: from IPV6_ADDRESS and co; a link-local address if there's none
+#[cfg(feature = "ipv6")]
+fn ipv6_config(hardware_address: embassy_net::HardwareAddress) -> embassy_net::StaticConfigV6 {
+    let address = match IPV6_ADDRESS {
+        Some(a) if !a.is_empty() => a.parse().expect("IPV6_ADDRESS: expected an address and a prefix length, e.g. 2001:db8::5/64"),
+        _ => {
+            let embassy_net::HardwareAddress::Ethernet(mac) = hardware_address;
+            link_local_address(mac.0)
+        }
+    };
+    let gateway = match IPV6_GATEWAY {
+        Some(g) if !g.is_empty() => Some(g.parse().expect("IPV6_GATEWAY: expected an address")),
+        _ => None,
+    };
+    let mut dns_servers = heapless_0_8::Vec::new();
+    for s in IPV6_DNS.unwrap_or("").split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let _ = dns_servers.push(s.parse().expect("IPV6_DNS: expected addresses, comma-separated"));  // 3 at most
+    }
+    embassy_net::StaticConfigV6 { address, gateway, dns_servers }
+}
+
+// fe80::/64, the interface id from the MAC: modified EUI-64 (RFC 4291)
+#[cfg(feature = "ipv6")]
+fn link_local_address(mac: [u8; 6]) -> embassy_net::Ipv6Cidr {
+    let address = embassy_net::Ipv6Address::new(
+        0xfe80, 0, 0, 0,
+        u16::from_be_bytes([mac[0] ^ 0x02, mac[1]]),
+        u16::from_be_bytes([mac[2], 0xff]),
+        u16::from_be_bytes([0xfe, mac[3]]),
+        u16::from_be_bytes([mac[4], mac[5]]),
+    );
+    embassy_net::Ipv6Cidr::new(address, 64)
+}
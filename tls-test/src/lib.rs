@@ -0,0 +1,59 @@
+// The firmware's TLS connector and HTTP client, built for the host: see `tests/tls.rs`.
+// The modules are the firmware's own; what they need from the hardware is stood in for below.
+
+extern crate alloc;
+
+#[path = "../../pokakus/src/config.rs"]
+pub mod config;
+#[path = "../../pokakus/src/http.rs"]
+pub mod http;
+#[path = "../../pokakus/src/retry.rs"]
+pub mod retry;
+#[path = "../../pokakus/src/tls.rs"]
+pub mod tls;
+
+
+/// Wall-clock time: the host's. Certificate dates are checked against it.
+pub mod clock {
+    pub fn now_unix() -> Option<u64> {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+    }
+}
+
+/// DNS: embassy-net's. The tests bring their own: see `http::request_with()`.
+pub mod dns {
+    pub use embassy_net::dns::DnsSocket as Resolver;
+}
+
+/// Delivery semantics: the firmware's default. See `http::retry_policy()`.
+pub mod notifier {
+    use crate::retry::DeliveryMode;
+
+    pub const DELIVERY_MODE: DeliveryMode = DeliveryMode::AtLeastOnce;
+}
+
+/// Randomness: the host's
+pub mod wifi {
+    use std::hash::{BuildHasher, RandomState};
+
+    pub fn random_seed() -> u64 {
+        RandomState::new().hash_one(0)
+    }
+}
+
+
+// Logs: nowhere. The tests check results, not logs.
+#[defmt::global_logger]
+struct NoLogger;
+
+unsafe impl defmt::Logger for NoLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("");
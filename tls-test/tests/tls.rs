@@ -0,0 +1,247 @@
+// The TLS connector against a local server with a chain of our own: a CA, maybe an intermediate, and a server certificate.
+// What the device does with api.telegram.org, on the host:
+//   $ cargo test
+//
+// A request goes the firmware's way: `http::request_with()`, reqwless, our connector, embedded-tls.
+// Only TCP and DNS are the host's: every name resolves to the local server.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+use rsa::pkcs8::EncodePrivateKey;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use reqwless::request::Method;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+use tls_test::http::{self, HttpError, Request};
+
+
+// The name the device asks for
+const HOST: &str = "api.pokakus.test";
+
+
+#[test]
+fn pinned_ca() {
+    let ca = Ca::new("Pokakus Test CA");
+    let server = serve(ca.issue(HOST, Validity::Current));
+    assert_eq!(get(HOST, server, &ca).map_err(|e| format!("{:?}", e)), Ok(200));
+}
+
+#[test]
+fn other_ca() {
+    // Someone in the middle, with a certificate for the right name from a CA we don't pin
+    let ca = Ca::new("Pokakus Test CA");
+    let other = Ca::new("Someone Else's CA");
+    let server = serve(other.issue(HOST, Validity::Current));
+    assert!(matches!(get(HOST, server, &ca), Err(HttpError::CertificateRejected)));
+}
+
+#[test]
+fn intermediate() {
+    // The server's certificate is from an intermediate CA, which the pinned CA has issued
+    let ca = Ca::new("Pokakus Test CA");
+    let intermediate = ca.intermediate("Pokakus Test Intermediate");
+    let server = serve(intermediate.issue(HOST, Validity::Current));
+    assert_eq!(get(HOST, server, &ca).map_err(|e| format!("{:?}", e)), Ok(200));
+}
+
+#[test]
+fn other_intermediate() {
+    let ca = Ca::new("Pokakus Test CA");
+    let intermediate = Ca::new("Someone Else's CA").intermediate("Someone Else's Intermediate");
+    let server = serve(intermediate.issue(HOST, Validity::Current));
+    assert!(matches!(get(HOST, server, &ca), Err(HttpError::CertificateRejected)));
+}
+
+#[test]
+fn rsa() {
+    // Telegram's chain: RSA all the way
+    let ca = Ca::new_rsa("Pokakus Test RSA CA");
+    let intermediate = ca.intermediate("Pokakus Test RSA Intermediate");
+    let server = serve(intermediate.issue(HOST, Validity::Current));
+    assert_eq!(get(HOST, server, &ca).map_err(|e| format!("{:?}", e)), Ok(200));
+}
+
+#[test]
+fn other_rsa() {
+    let ca = Ca::new_rsa("Pokakus Test RSA CA");
+    let other = Ca::new_rsa("Someone Else's RSA CA");
+    let server = serve(other.issue(HOST, Validity::Current));
+    assert!(matches!(get(HOST, server, &ca), Err(HttpError::CertificateRejected)));
+}
+
+#[test]
+fn wrong_host() {
+    // The right CA, but the certificate is for another name
+    let ca = Ca::new("Pokakus Test CA");
+    let server = serve(ca.issue("elsewhere.pokakus.test", Validity::Current));
+    assert!(matches!(get(HOST, server, &ca), Err(HttpError::CertificateRejected)));
+}
+
+#[test]
+fn expired() {
+    let ca = Ca::new("Pokakus Test CA");
+    let server = serve(ca.issue(HOST, Validity::Expired));
+    assert!(matches!(get(HOST, server, &ca), Err(HttpError::CertificateRejected)));
+}
+
+
+// GET https://{host}/ from the server, trusting `ca`. Returns the HTTP status.
+fn get(host: &str, server: SocketAddr, ca: &Ca) -> Result<u16, HttpError> {
+    let url = format!("https://{}:{}/", host, server.port());
+    let req = Request { method: Method::GET, url: &url, headers: &[], body: &[] };
+    let mut rx_buf = [0u8; 4096];
+    embassy_futures::block_on(http::request_with(&StdTcp, &Localhost, &req, ca.cert.der(), 1, &mut rx_buf))
+        .map(|response| response.status)
+}
+
+
+// A CA: self-signed, or issued by another
+struct Ca {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+    chain: Vec<CertificateDer<'static>>,  // this CA's certificate, then the ones above it
+}
+
+enum Validity {
+    Current,  // 2024 to 2049
+    Expired,  // 2020 to 2021
+}
+
+impl Ca {
+    fn new(name: &str) -> Ca {
+        let key = KeyPair::generate().unwrap();  // ECDSA P-256
+        let cert = Ca::params(name).self_signed(&key).unwrap();
+        Ca { chain: vec![cert.der().clone()], cert, key }
+    }
+
+    // RSA 2048, SHA-256: ring can sign with it, not generate it
+    fn new_rsa(name: &str) -> Ca {
+        let key = rsa_key();
+        let cert = Ca::params(name).self_signed(&key).unwrap();
+        Ca { chain: vec![cert.der().clone()], cert, key }
+    }
+
+    // A CA this one has issued: with the same kind of key
+    fn intermediate(&self, name: &str) -> Ca {
+        let key = if self.key.is_compatible(&rcgen::PKCS_RSA_SHA256) { rsa_key() } else { KeyPair::generate().unwrap() };
+        let cert = Ca::params(name).signed_by(&key, &self.cert, &self.key).unwrap();
+        let chain = [vec![cert.der().clone()], self.chain.clone()].concat();
+        Ca { cert, key, chain }
+    }
+
+    fn params(name: &str) -> CertificateParams {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params
+    }
+
+    // A server certificate for `host`. Returns the chain, and the server's key.
+    fn issue(&self, host: &str, validity: Validity) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let key = if self.key.is_compatible(&rcgen::PKCS_RSA_SHA256) { rsa_key() } else { KeyPair::generate().unwrap() };
+        let mut params = CertificateParams::new(vec![host.to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, host);
+        (params.not_before, params.not_after) = match validity {
+            Validity::Current => (rcgen::date_time_ymd(2024, 1, 1), rcgen::date_time_ymd(2049, 12, 31)),
+            Validity::Expired => (rcgen::date_time_ymd(2020, 1, 1), rcgen::date_time_ymd(2021, 1, 1)),
+        };
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        let chain = [vec![cert.der().clone()], self.chain.clone()].concat();
+        (chain, PrivatePkcs8KeyDer::from(key.serialize_der()).into())
+    }
+}
+
+fn rsa_key() -> KeyPair {
+    let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+    let der = key.to_pkcs8_der().unwrap();
+    KeyPair::from_pkcs8_der_and_sign_algo(&PrivatePkcs8KeyDer::from(der.as_bytes()), &rcgen::PKCS_RSA_SHA256).unwrap()
+}
+
+
+// A TLS 1.3 server: one connection. Any request gets 200 "ok".
+// Returns its address.
+fn serve((chain, key): (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)) -> SocketAddr {
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let Ok((tcp, _)) = listener.accept() else { return };
+        let mut tls = rustls::StreamOwned::new(rustls::ServerConnection::new(Arc::new(config)).unwrap(), tcp);
+
+        // The request: up to the end of the headers. A client that's rejected us: an error.
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            match tls.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let _ = tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+        tls.conn.send_close_notify();
+        let _ = tls.flush();
+    });
+    addr
+}
+
+
+// TCP: the host's, blocking. The server's on another thread: nothing else to wait for.
+struct StdTcp;
+
+struct StdConnection(TcpStream);
+
+impl TcpConnect for StdTcp {
+    type Error = std::io::Error;
+    type Connection<'a> = StdConnection where Self: 'a;
+
+    async fn connect(&self, remote: SocketAddr) -> Result<StdConnection, std::io::Error> {
+        TcpStream::connect(remote).map(StdConnection)
+    }
+}
+
+impl embedded_io_async::ErrorType for StdConnection {
+    type Error = std::io::Error;
+}
+
+impl embedded_io_async::Read for StdConnection {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        self.0.read(buf)
+    }
+}
+
+impl embedded_io_async::Write for StdConnection {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.0.write(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.0.flush()
+    }
+}
+
+
+// DNS: every name is the local server
+struct Localhost;
+
+impl Dns for Localhost {
+    type Error = std::io::Error;
+
+    async fn get_host_by_name(&self, _host: &str, _addr_type: AddrType) -> Result<IpAddr, std::io::Error> {
+        Ok(Ipv4Addr::LOCALHOST.into())
+    }
+
+    async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, std::io::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}